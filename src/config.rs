@@ -0,0 +1,400 @@
+use std::{collections::HashMap, fs::{self, DirEntry, Metadata}, io::ErrorKind, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::RwLock;
+use tower_lsp::lsp_types::{ConfigurationItem, Registration, Url};
+
+use crate::{error::BhcError, glob::{Gitignore, Glob}, Backend, BHC_PATH, CONFIG_PATH};
+
+/// The section of the client's settings the configuration is read from, e.g. `bhc.exclude`
+const CONFIG_SECTION: &str = "bhc";
+
+const CONFIG_CHANGE_ID: &str = "bhc-config-change";
+
+/// Folders that are never worth searching, so they are left out on top of whatever the configuration excludes
+const DEFAULT_EXCLUDE: [&str; 3] = [".git", "node_modules", "dist"];
+
+/// Files bigger than this are left out by default, as they are most likely generated or bundled
+const DEFAULT_MAX_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Which files in a workspace are searched for HTML and CSS, read from `.bhc/config.json` and the client's settings
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorkspaceConfig {
+    pub include: Vec<String>, // globs of the files to search, every file is searched if there are none
+    pub exclude: Vec<String>, // globs of the files and folders to leave out, even if they are included. `DEFAULT_EXCLUDE` is always left out as well
+    pub use_gitignore: bool, // leave out whatever the `.gitignore` files in the workspace ignore
+    pub max_file_size: u64, // in bytes, files bigger than this are left out. 0 is no limit
+    pub follow_symlinks: bool, // search the files and folders that symbolic links point to
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        WorkspaceConfig {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            use_gitignore: true,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            follow_symlinks: false,
+        }
+    }
+}
+
+/// The parts of a `WorkspaceConfig` set by one source, anything it doesn't set comes from the next source or the default
+#[derive(Deserialize, Debug, Default, PartialEq, Clone)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+struct ConfigOverrides {
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    use_gitignore: Option<bool>,
+    max_file_size: Option<u64>,
+    follow_symlinks: Option<bool>,
+}
+
+impl WorkspaceConfig {
+    /// Read the configuration of the workspace at `workspace_path`. The `.bhc/config.json` takes priority over the `settings` from the client, and whatever neither of them set is the default.
+    /// Returns `Err(BhcError)` if the config file could not be read, or either of them isn't a valid configuration.
+    pub fn load(workspace_path: &Path, settings: Option<Value>) -> Result<WorkspaceConfig, BhcError> {
+        let config_path = workspace_path.join(CONFIG_PATH);
+
+        let file_overrides = match fs::read_to_string(&config_path) {
+            Ok(value) => match serde_json::from_str::<ConfigOverrides>(&value) {
+                Ok(value) => value,
+                Err(error) => return Err(BhcError::json(&config_path, error))
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => ConfigOverrides::default(),
+            Err(error) => return Err(BhcError::io(&config_path, error))
+        };
+
+        let settings_overrides = match settings {
+            Some(Value::Null) | None => ConfigOverrides::default(),
+            Some(value) => match serde_json::from_value::<ConfigOverrides>(value) {
+                Ok(value) => value,
+                Err(error) => return Err(BhcError::Settings(error))
+            }
+        };
+
+        let mut config = WorkspaceConfig::default();
+        config.apply(settings_overrides);
+        config.apply(file_overrides);
+
+        Ok(config)
+    }
+
+    fn apply(&mut self, overrides: ConfigOverrides) {
+        self.include = overrides.include.unwrap_or(std::mem::take(&mut self.include));
+        self.exclude = overrides.exclude.unwrap_or(std::mem::take(&mut self.exclude));
+        self.use_gitignore = overrides.use_gitignore.unwrap_or(self.use_gitignore);
+        self.max_file_size = overrides.max_file_size.unwrap_or(self.max_file_size);
+        self.follow_symlinks = overrides.follow_symlinks.unwrap_or(self.follow_symlinks);
+    }
+}
+
+/// Decides which files in a workspace are searched, from its `WorkspaceConfig`.
+/// Everything inside of the `.bhc` folder belongs to the server, so it is always included whatever the configuration says.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    workspace_path: PathBuf,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    use_gitignore: bool,
+    max_file_size: u64,
+    pub follow_symlinks: bool,
+}
+
+impl FileFilter {
+    pub fn new(workspace_path: &Path, config: &WorkspaceConfig) -> FileFilter {
+        FileFilter {
+            workspace_path: workspace_path.to_path_buf(),
+            include: config.include.iter().map(|pattern| Glob::new(pattern)).collect(),
+            exclude: DEFAULT_EXCLUDE.iter().copied().chain(config.exclude.iter().map(String::as_str)).map(Glob::new).collect(),
+            use_gitignore: config.use_gitignore,
+            max_file_size: config.max_file_size,
+            follow_symlinks: config.follow_symlinks,
+        }
+    }
+
+    /// Read the `.gitignore` directly inside of `directory`, if the filter uses them.
+    pub fn read_gitignore(&self, directory: &Path) -> Option<Gitignore> {
+        if self.use_gitignore {
+            Gitignore::read(directory)
+        } else {
+            None
+        }
+    }
+
+    /// Get the metadata of the file or folder of the `entry`, which is where it points to if it is a symbolic link.
+    /// Returns `None` if it is a symbolic link that isn't followed, or it could not be read.
+    pub fn read_entry(&self, entry: &DirEntry) -> Option<Metadata> {
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() && self.follow_symlinks => fs::metadata(entry.path()).ok(),
+            Ok(file_type) if file_type.is_symlink() => None,
+            Ok(_) => entry.metadata().ok(),
+            Err(_) => None
+        }
+    }
+
+    /// Check whether the folder at `directory` is searched, given the `gitignores` of every folder above it, with the deepest last.
+    pub fn is_directory_included(&self, directory: &Path, gitignores: &[Gitignore]) -> bool {
+        let relative_path = match directory.strip_prefix(&self.workspace_path) {
+            Ok(value) => value,
+            Err(_) => return false
+        };
+
+        if relative_path.starts_with(BHC_PATH) {
+            return true
+        }
+
+        !self.exclude.iter().any(|glob| glob.is_match(relative_path)) && !is_ignored(gitignores, directory, true)
+    }
+
+    /// Check whether the file at `file_path`, which is `size` bytes, is searched, given the `gitignores` of every folder above it, with the deepest last.
+    /// The folders it is inside of are expected to have been checked already.
+    pub fn is_file_included(&self, file_path: &Path, size: u64, gitignores: &[Gitignore]) -> bool {
+        let relative_path = match file_path.strip_prefix(&self.workspace_path) {
+            Ok(value) => value,
+            Err(_) => return false
+        };
+
+        if relative_path.starts_with(BHC_PATH) {
+            return true
+        }
+
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(relative_path)))
+            && !self.exclude.iter().any(|glob| glob.is_match(relative_path))
+            && (self.max_file_size == 0 || size <= self.max_file_size)
+            && !is_ignored(gitignores, file_path, false)
+    }
+
+    /// Check whether the file at `file_path` would be found by searching the workspace, e.g. for a file the client says was created.
+    /// Every folder between the workspace and the file is checked too, along with their `.gitignore` files.
+    /// Returns `false` if the file doesn't exist.
+    pub fn is_included(&self, file_path: &Path) -> bool {
+        let relative_path = match file_path.strip_prefix(&self.workspace_path) {
+            Ok(value) => value,
+            Err(_) => return false
+        };
+
+        let mut gitignores: Vec<Gitignore> = Vec::new();
+        let mut directory = self.workspace_path.clone();
+
+        if let Some(parent) = relative_path.parent() {
+            for component in parent.components() {
+                gitignores.extend(self.read_gitignore(&directory));
+                directory.push(component);
+
+                let is_symlink = fs::symlink_metadata(&directory).is_ok_and(|metadata| metadata.file_type().is_symlink());
+
+                if (is_symlink && !self.follow_symlinks) || !self.is_directory_included(&directory, &gitignores) {
+                    return false
+                }
+            }
+        }
+
+        gitignores.extend(self.read_gitignore(&directory));
+
+        let metadata = match fs::symlink_metadata(file_path) {
+            Ok(value) if value.file_type().is_symlink() && self.follow_symlinks => fs::metadata(file_path),
+            Ok(value) if value.file_type().is_symlink() => return false,
+            result => result
+        };
+
+        match metadata {
+            Ok(value) => value.is_file() && self.is_file_included(file_path, value.len(), &gitignores),
+            Err(_) => false
+        }
+    }
+}
+
+/// The deepest `.gitignore` with a rule that matches decides, so a folder can bring back what the one above it ignored
+fn is_ignored(gitignores: &[Gitignore], path: &Path, is_directory: bool) -> bool {
+    gitignores
+        .iter()
+        .rev()
+        .find_map(|gitignore| gitignore.is_ignored(path, is_directory))
+        .unwrap_or(false)
+}
+
+/// The `WorkspaceConfig` of every workspace, so the client is only asked for its settings when something changes
+#[derive(Debug, Clone, Default)]
+pub struct ConfigCache {
+    workspaces: Arc<RwLock<HashMap<PathBuf, WorkspaceConfig>>>,
+    supports_settings: Arc<AtomicBool>, // whether the client can be asked for its settings with `workspace/configuration`
+}
+
+impl ConfigCache {
+    pub fn set_supports_settings(&self, supports_settings: bool) {
+        self.supports_settings.store(supports_settings, Ordering::Relaxed);
+    }
+
+    pub fn supports_settings(&self) -> bool {
+        self.supports_settings.load(Ordering::Relaxed)
+    }
+
+    pub async fn get(&self, workspace_path: &Path) -> Option<WorkspaceConfig> {
+        self.workspaces.read().await.get(workspace_path).cloned()
+    }
+
+    pub async fn insert(&self, workspace_path: &Path, config: WorkspaceConfig) {
+        self.workspaces.write().await.insert(workspace_path.to_path_buf(), config);
+    }
+
+    /// Forget the configuration of the workspace at `workspace_path`, e.g. once it has been closed.
+    pub async fn remove(&self, workspace_path: &Path) {
+        self.workspaces.write().await.remove(workspace_path);
+    }
+}
+
+impl Backend {
+    /// Get the configuration of the workspace at `workspace_path`, which is only read if it hasn't been already. See [`Backend::reload_workspace_config`].
+    pub async fn get_workspace_config(&self, workspace_path: &Path) -> WorkspaceConfig {
+        match self.configs.get(workspace_path).await {
+            Some(value) => value,
+            None => self.reload_workspace_config(workspace_path).await
+        }
+    }
+
+    /// Read the configuration of the workspace at `workspace_path` again, from `.bhc/config.json` and the client's settings. See [`WorkspaceConfig::load`].
+    /// Errors are reported, and the default configuration is used instead.
+    pub async fn reload_workspace_config(&self, workspace_path: &Path) -> WorkspaceConfig {
+        let settings = match self.get_client_settings(workspace_path).await {
+            Ok(value) => value,
+            Err(error) => {
+                self.report_error(error).await;
+                None
+            }
+        };
+
+        let config = match WorkspaceConfig::load(workspace_path, settings) {
+            Ok(value) => value,
+            Err(error) => {
+                self.report_error(error).await;
+                WorkspaceConfig::default()
+            }
+        };
+
+        self.configs.insert(workspace_path, config.clone()).await;
+
+        config
+    }
+
+    /// Ask the client for its settings for the workspace at `workspace_path`. Calls into the LSP [`workspace/configuration`](https://microsoft.github.io/language-server-protocol/specification#workspace_configuration)
+    /// Returns `Ok(None)` if the client can't be asked, or has no settings for the server.
+    /// Returns `Err(BhcError)` if the request failed.
+    async fn get_client_settings(&self, workspace_path: &Path) -> Result<Option<Value>, BhcError> {
+        if !self.configs.supports_settings() {
+            return Ok(None)
+        }
+
+        let item = ConfigurationItem {
+            scope_uri: Url::from_file_path(workspace_path).ok(),
+            section: Some(String::from(CONFIG_SECTION)),
+        };
+
+        match self.client.configuration(vec![item]).await {
+            Ok(value) => Ok(value.into_iter().next()),
+            Err(error) => Err(BhcError::Client(format!("Could not get the settings: {}", error)))
+        }
+    }
+
+    /// Ask the client to send `workspace/didChangeConfiguration` when its settings change, so the workspaces can be searched again.
+    /// Errors are logged, e.g. when the client doesn't support registering it dynamically.
+    pub async fn register_config_changes(&self) {
+        if !self.configs.supports_settings() {
+            return
+        }
+
+        let registration = Registration {
+            id: String::from(CONFIG_CHANGE_ID),
+            method: String::from("workspace/didChangeConfiguration"),
+            register_options: None,
+        };
+
+        if let Err(error) = self.client.register_capability(vec![registration]).await {
+            self.report_error(BhcError::Client(format!("Could not register for configuration changes: {}", error))).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use crate::{file::recursive_file_search, CONFIG_PATH, METADATA_PATH};
+
+    use super::{FileFilter, WorkspaceConfig};
+
+    #[test]
+    fn load_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_config_load_test");
+        let _ = fs::remove_dir_all(&workspace_path);
+
+        assert_eq!(WorkspaceConfig::load(&workspace_path, None).unwrap(), WorkspaceConfig::default());
+
+        fs::create_dir_all(workspace_path.join(".bhc")).unwrap();
+        fs::write(workspace_path.join(CONFIG_PATH), json!({ "exclude": ["vendor"], "maxFileSize": 0 }).to_string()).unwrap();
+
+        // the config file takes priority over the client's settings
+        let config = WorkspaceConfig::load(&workspace_path, Some(json!({ "exclude": ["build"], "followSymlinks": true }))).unwrap();
+
+        assert_eq!(config.exclude, vec![String::from("vendor")]);
+        assert_eq!(config.max_file_size, 0);
+        assert!(config.follow_symlinks);
+        assert!(config.use_gitignore);
+
+        assert!(WorkspaceConfig::load(&workspace_path, Some(json!({ "exclude": "vendor" }))).is_err());
+        assert!(WorkspaceConfig::load(&workspace_path, Some(json!({ "excludes": ["vendor"] }))).is_err());
+    }
+
+    #[test]
+    fn file_filter_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_file_filter_test");
+        let _ = fs::remove_dir_all(&workspace_path);
+
+        for folder in ["node_modules/package", "site/generated", "site/logs", ".bhc/.meta"] {
+            fs::create_dir_all(workspace_path.join(folder)).unwrap();
+        }
+
+        for file in ["index.html", "node_modules/package/index.html", "site/generated/page.html", "site/generated/keep.html", "site/logs/error.css", METADATA_PATH] {
+            fs::write(workspace_path.join(file), "").unwrap();
+        }
+
+        fs::write(workspace_path.join("large.html"), "<p></p>".repeat(10)).unwrap();
+        fs::write(workspace_path.join(".gitignore"), "generated/\n*.css\n/.bhc\n").unwrap();
+        fs::write(workspace_path.join("site/.gitignore"), "!logs/*.css\n").unwrap();
+
+        let config = WorkspaceConfig { max_file_size: 64, ..WorkspaceConfig::default() };
+        let filter = FileFilter::new(&workspace_path, &config);
+
+        let mut files = recursive_file_search(&workspace_path, &filter);
+        files.sort();
+
+        // the server's own folder is searched even though it is ignored, and a deeper `.gitignore` can bring back what the one above it ignored
+        assert_eq!(files, vec![
+            workspace_path.join(METADATA_PATH),
+            workspace_path.join(".gitignore"),
+            workspace_path.join("index.html"),
+            workspace_path.join("site/.gitignore"),
+            workspace_path.join("site/logs/error.css"),
+        ]);
+
+        assert!(filter.is_included(&workspace_path.join("site/logs/error.css")));
+        assert!(!filter.is_included(&workspace_path.join("site/generated/keep.html")));
+        assert!(!filter.is_included(&workspace_path.join("node_modules/package/index.html")));
+        assert!(!filter.is_included(&workspace_path.join("large.html")));
+
+        // excluding something else doesn't bring back the folders that are always left out
+        let config = WorkspaceConfig { exclude: vec![String::from("site")], ..WorkspaceConfig::default() };
+
+        assert!(!FileFilter::new(&workspace_path, &config).is_included(&workspace_path.join("site/logs/error.css")));
+        assert!(!FileFilter::new(&workspace_path, &config).is_included(&workspace_path.join("node_modules/package/index.html")));
+
+        let config = WorkspaceConfig { include: vec![String::from("site/**/*.css")], use_gitignore: false, max_file_size: 0, ..WorkspaceConfig::default() };
+
+        assert!(!FileFilter::new(&workspace_path, &config).is_included(&workspace_path.join("index.html")));
+        assert!(!FileFilter::new(&workspace_path, &config).is_included(&workspace_path.join("site/generated/page.html")));
+        assert!(FileFilter::new(&workspace_path, &config).is_included(&workspace_path.join("site/logs/error.css")));
+    }
+}
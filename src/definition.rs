@@ -0,0 +1,142 @@
+use std::{collections::HashMap, ffi::OsStr, path::PathBuf};
+
+use tower_lsp::lsp_types::{GotoDefinitionParams, GotoDefinitionResponse, Location, Url};
+
+use crate::{
+    error::{uri_to_path, BhcError},
+    document_store::read_file,
+    file::get_css_file_paths,
+    metadata::{
+        css_metadata::find_symbol_ranges,
+        html_metadata::html_element::{parse_elements, symbol_at_position, HtmlSymbol},
+    },
+    workspace::FileScope,
+    Backend, EXT_HTML,
+};
+
+impl Backend {
+    /// Get every rule in the stylesheets linked from the HTML document in `params` that targets the class or id under the cursor.
+    /// Returns `Ok(None)` if the cursor isn't on a class or id, or no linked stylesheet targets it.
+    /// Returns `Err(BhcError)` if the document could not be read.
+    pub async fn get_definition(&self, params: &GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>, BhcError> {
+        let position = params.text_document_position_params.position;
+        let uri = &params.text_document_position_params.text_document.uri;
+
+        let file_path = match uri_to_path(uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        if file_path.extension().and_then(OsStr::to_str) != Some(EXT_HTML) {
+            return Ok(None)
+        }
+
+        let scope = self.get_file_scope(&file_path).await;
+
+        let html_string = match self.read_document(&file_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let elements = parse_elements(&html_string);
+
+        let symbol = match symbol_at_position(&elements, position) {
+            Some((_, symbol @ (HtmlSymbol::Class(_) | HtmlSymbol::Id(_)), _)) => symbol,
+            _ => return Ok(None)
+        };
+
+        let css_paths = self.get_linked_stylesheet_paths(&scope, &file_path, &html_string).await;
+        let locations = get_symbol_locations(&css_paths, &symbol, &self.documents.get_texts().await);
+
+        if locations.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(GotoDefinitionResponse::Array(locations)))
+        }
+    }
+
+    /// Get the stylesheets linked from the HTML document at `html_path`, in the order they are linked.
+    /// The links are read from `html_string` so unsaved links are included, if it has none and is in a workspace the `css_sheets` recorded in its `HtmlMetaData` are used instead.
+    pub async fn get_linked_stylesheet_paths(&self, scope: &FileScope, html_path: &PathBuf, html_string: &str) -> Vec<PathBuf> {
+        let css_paths = get_css_file_paths(html_path, html_string).unwrap_or_default();
+
+        let workspace_path = match scope {
+            FileScope::Workspace(value) if css_paths.is_empty() => value,
+            _ => return css_paths
+        };
+
+        match self.get_html_metadata(workspace_path, html_path).await {
+            Ok(html_metadata) => html_metadata
+            .css_sheets
+            .unwrap_or_default()
+            .iter()
+            .map(|sheet| PathBuf::from(&sheet.absolute_path))
+            .collect(),
+            Err(_) => Vec::new()
+        }
+    }
+}
+
+/// Get the location of every selector in the stylesheets at `css_paths` that uses the `symbol`. Stylesheets that can't be read are skipped.
+/// Stylesheets in `open_documents` are read from there, so the locations match what is in the editor.
+pub fn get_symbol_locations(css_paths: &[PathBuf], symbol: &HtmlSymbol, open_documents: &HashMap<PathBuf, String>) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+
+    for css_path in css_paths {
+        let css_string = match read_file(open_documents, css_path) {
+            Some(value) => value,
+            None => continue
+        };
+
+        let uri = match Url::from_file_path(css_path) {
+            Ok(value) => value,
+            Err(_) => continue
+        };
+
+        for range in find_symbol_ranges(&css_string, symbol) {
+            locations.push(Location::new(uri.clone(), range));
+        }
+    }
+
+    locations
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use tower_lsp::lsp_types::{Position, Range, Url};
+
+    use crate::{file::get_css_file_paths, metadata::html_metadata::html_element::HtmlSymbol};
+
+    use super::get_symbol_locations;
+
+    #[test]
+    fn get_symbol_locations_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_definition_test");
+        let html_path = workspace_path.join("index.html");
+        let css_path = workspace_path.join("css").join("base.css");
+
+        fs::create_dir_all(css_path.parent().unwrap()).unwrap();
+
+        let html_string = "<link rel=\"stylesheet\" href=\"css/base.css\">\n<div class=\"card\"></div>";
+        fs::write(&html_path, html_string).unwrap();
+        fs::write(&css_path, "p { color: red; }\n.card, .card-title {\n\tmargin: 0;\n}\n").unwrap();
+
+        let css_paths = get_css_file_paths(&html_path, html_string).unwrap();
+
+        assert_eq!(css_paths, vec![css_path.clone()]);
+
+        let locations = get_symbol_locations(&css_paths, &HtmlSymbol::Class(String::from("card")), &HashMap::new());
+
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].uri, Url::from_file_path(&css_path).unwrap());
+        assert_eq!(locations[0].range, Range::new(Position::new(1, 1), Position::new(1, 5)));
+
+        // a stylesheet that is open is read from the editor instead of from disk
+        let open_documents = HashMap::from([(css_path.clone(), String::from(".card { margin: 0; }"))]);
+        let locations = get_symbol_locations(&css_paths, &HtmlSymbol::Class(String::from("card")), &open_documents);
+
+        assert_eq!(locations[0].range, Range::new(Position::new(0, 1), Position::new(0, 5)));
+    }
+}
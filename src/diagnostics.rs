@@ -0,0 +1,177 @@
+use std::{ffi::OsStr, path::{Path, PathBuf}};
+
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Range, Url};
+
+use crate::{
+    error::BhcError,
+    metadata::{css_metadata::find_parse_errors, html_metadata::html_link::{parse_stylesheet_links, LinkTarget}, workspace_metadata::{id_to_json_file_name, WorkspaceMetaData}},
+    workspace::FileScope,
+    Backend, CSS_METADATA_PATH, EXT_CSS, EXT_HTML,
+};
+
+pub const DIAGNOSTIC_SOURCE: &str = "bhc";
+
+impl Backend {
+    /// Check the HTML or CSS document at `uri`, and publish a diagnostic for each broken stylesheet link or parse error in it.
+    /// `text` is the current text of the document, so changes that haven't been saved yet are checked too.
+    /// Errors are reported, and documents that aren't HTML or CSS are ignored.
+    pub async fn publish_diagnostics(&self, uri: &Url, text: &str) {
+        let file_path = match uri.to_file_path() {
+            Ok(value) => value,
+            Err(_) => return
+        };
+
+        let diagnostics = match file_path.extension().and_then(OsStr::to_str) {
+            Some(EXT_HTML) => match self.get_html_diagnostics(&file_path, text).await {
+                Ok(value) => value,
+                Err(error) => {
+                    self.report_error(error).await;
+                    return
+                }
+            },
+            Some(EXT_CSS) => get_parse_diagnostics(&file_path, text),
+            _ => return
+        };
+
+        self.client.publish_diagnostics(uri.clone(), diagnostics, None).await;
+    }
+
+    async fn get_html_diagnostics(&self, html_path: &PathBuf, html_string: &str) -> Result<Vec<Diagnostic>, BhcError> {
+        // loose files have no metadata, so only their links are checked
+        match self.get_file_scope(html_path).await {
+            FileScope::Workspace(workspace_path) => {
+                let workspace_metadata = match self.get_workspace_metadata(&workspace_path).await {
+                    Ok(value) => value,
+                    Err(error) => return Err(error)
+                };
+
+                Ok(get_link_diagnostics(Some((&workspace_path, &workspace_metadata)), html_path, html_string))
+            },
+            FileScope::Loose => Ok(get_link_diagnostics(None, html_path, html_string))
+        }
+    }
+}
+
+/// Get a diagnostic for everywhere the stylesheet at `css_path` could not be parsed, so the user sees why some of its styles aren't being tracked.
+pub fn get_parse_diagnostics(css_path: &Path, css_string: &str) -> Vec<Diagnostic> {
+    find_parse_errors(css_path, css_string)
+        .iter()
+        .filter_map(|error| error.to_diagnostic())
+        .collect()
+}
+
+/// Get a diagnostic for every `<link rel="stylesheet">` in `html_string` whose `href` doesn't exist, is outside of the `workspace`, or has no `CssMetaData`.
+/// Loose files have no `workspace`, so only stylesheets that don't exist are reported. Each diagnostic covers the value of the `href`.
+pub fn get_link_diagnostics(workspace: Option<(&Path, &WorkspaceMetaData)>, html_path: &PathBuf, html_string: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for link in parse_stylesheet_links(html_path, html_string) {
+        // stylesheets on other servers, or inside of the link itself, can't be checked
+        let css_path = match link.target {
+            LinkTarget::Local(value) => value,
+            _ => continue
+        };
+
+        let (workspace_path, workspace_metadata) = match (css_path.is_file(), workspace) {
+            (false, _) => {
+                diagnostics.push(link_diagnostic(link.href_range, DiagnosticSeverity::ERROR, format!("Stylesheet \"{}\" does not exist", link.href)));
+                continue
+            },
+            (true, Some(value)) => value,
+            (true, None) => continue
+        };
+
+        let (severity, message) = if !css_path.starts_with(workspace_path) {
+            (DiagnosticSeverity::WARNING, format!("Stylesheet \"{}\" is outside of the workspace, so its styles are not tracked", link.href))
+        } else {
+            match workspace_metadata.get_css_file_id(&css_path) {
+                Some(id) if workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&id)).is_file() => continue,
+                _ => (DiagnosticSeverity::WARNING, format!("Stylesheet \"{}\" has no metadata, reopen the workspace to create it", link.href)),
+            }
+        };
+
+        diagnostics.push(link_diagnostic(link.href_range, severity, message));
+    }
+
+    diagnostics
+}
+
+fn link_diagnostic(range: Range, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some(String::from(DIAGNOSTIC_SOURCE)),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tower_lsp::lsp_types::{DiagnosticSeverity, Position, Range};
+
+    use crate::{metadata::workspace_metadata::{id_to_json_file_name, workspace_css_file::WorkspaceCssFile, WorkspaceMetaData}, CSS_METADATA_PATH};
+
+    use super::{get_link_diagnostics, get_parse_diagnostics};
+
+    #[test]
+    fn get_link_diagnostics_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_diagnostics_test");
+        let html_path = workspace_path.join("index.html");
+        let css_path = workspace_path.join("base.css");
+        let untracked_path = workspace_path.join("untracked.css");
+
+        fs::create_dir_all(workspace_path.join(CSS_METADATA_PATH)).unwrap();
+        fs::write(&css_path, "h1 { color: red; }").unwrap();
+        fs::write(&untracked_path, "h1 { color: blue; }").unwrap();
+        fs::write(workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&1)), "{}").unwrap();
+
+        let mut workspace_metadata = WorkspaceMetaData::new();
+        let mut css_file = WorkspaceCssFile::new();
+        css_file.id = 1;
+        css_file.absolute_path = css_path.to_str().unwrap().to_string();
+        workspace_metadata.css_files.push(css_file);
+
+        let html_string = "<link rel=\"stylesheet\" href=\"base.css\">\n<link rel=\"stylesheet\" href=\"missing.css\">\n<link rel=\"stylesheet\" href=\"untracked.css\">\n<link rel=\"icon\" href=\"favicon.ico\">\n<link rel=\"stylesheet\" href=\"https://example.com/style.css\">";
+
+        fs::write(&html_path, html_string).unwrap();
+
+        let diagnostics = get_link_diagnostics(Some((&workspace_path, &workspace_metadata)), &html_path, html_string);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(1, 29), Position::new(1, 40)));
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[1].range, Range::new(Position::new(2, 29), Position::new(2, 42)));
+        assert_eq!(diagnostics[1].severity, Some(DiagnosticSeverity::WARNING));
+
+        // without a workspace only the missing stylesheet is reported
+        let loose_diagnostics = get_link_diagnostics(None, &html_path, html_string);
+
+        assert_eq!(loose_diagnostics, vec![diagnostics[0].clone()]);
+    }
+
+    #[test]
+    fn get_parse_diagnostics_test() {
+        let css_path = std::env::temp_dir().join("base.css");
+
+        let valid_string = ".card { --gap: ; background: url(bg.png); &:hover { color: red; } }\n@media print { a:visited { color: blue; } }";
+
+        assert_eq!(get_parse_diagnostics(&css_path, valid_string), Vec::new());
+
+        let css_string = "h1 { color: ; }\n}\n.note { content: \"open\n}\n@media print {\n  a { color: red; }";
+
+        let diagnostics: Vec<(Range, String)> = get_parse_diagnostics(&css_path, css_string)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.range, diagnostic.message))
+            .collect();
+
+        assert_eq!(diagnostics, vec![
+            (Range::new(Position::new(0, 5), Position::new(0, 10)), String::from("Expected a value for `color`")),
+            (Range::new(Position::new(1, 0), Position::new(1, 1)), String::from("Unexpected `}`")),
+            (Range::new(Position::new(2, 17), Position::new(2, 22)), String::from("Expected a closing quote before the end of the line")),
+            (Range::new(Position::new(4, 0), Position::new(4, 6)), String::from("Expected a `}` to close the block")),
+        ]);
+    }
+}
@@ -0,0 +1,289 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use tokio::sync::RwLock;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent, Url};
+
+use crate::{error::BhcError, file::is_virtual_file, workspace::FileScope, Backend};
+
+/// How long a document has to go without changing before its metadata is derived from it again, so it isn't re-parsed on every keystroke
+const METADATA_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// The text of a document that is open in the editor, which can be ahead of what is saved on disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextDocument {
+    pub text: String,
+    pub version: i32,
+    line_offsets: Vec<usize>, // the byte offset that each line starts at, so an edit only has to look at the line it starts on
+}
+
+impl TextDocument {
+    pub fn new(text: String, version: i32) -> TextDocument {
+        let mut document = TextDocument { text, version, line_offsets: vec![0] };
+        document.update_line_offsets(0);
+
+        document
+    }
+
+    /// Apply a change sent by the client. A change with a range replaces that range, and one without replaces the whole text.
+    pub fn apply_change(&mut self, change: &TextDocumentContentChangeEvent) {
+        match change.range {
+            Some(range) => {
+                let start = self.offset_at(range.start);
+                let end = self.offset_at(range.end).max(start);
+
+                self.text.replace_range(start..end, &change.text);
+
+                // the lines before the edit haven't moved
+                let line = (range.start.line as usize).min(self.line_offsets.len() - 1);
+                self.update_line_offsets(line);
+            },
+            None => {
+                self.text = change.text.clone();
+                self.update_line_offsets(0);
+            }
+        }
+    }
+
+    /// Get the byte offset of `position`, with characters counted in UTF-16 code units.
+    /// A character past the end of its line is clamped to the end of the line, and a line past the end of the document is clamped to the end of the document.
+    pub fn offset_at(&self, position: Position) -> usize {
+        let line_start = match self.line_offsets.get(position.line as usize) {
+            Some(value) => *value,
+            None => return self.text.len()
+        };
+
+        let line_end = self.line_offsets.get(position.line as usize + 1).copied().unwrap_or(self.text.len());
+        let line = self.text[line_start..line_end].trim_end_matches(['\n', '\r']);
+
+        let mut utf16_count: u32 = 0;
+
+        for (index, character) in line.char_indices() {
+            if utf16_count >= position.character {
+                return line_start + index
+            }
+
+            utf16_count += character.len_utf16() as u32;
+        }
+
+        line_start + line.len()
+    }
+
+    /// Find where every line from `line` onwards starts again, after the text has changed.
+    fn update_line_offsets(&mut self, line: usize) {
+        self.line_offsets.truncate(line + 1);
+
+        let line_start = self.line_offsets[line];
+
+        self.line_offsets.extend(self.text[line_start..].match_indices('\n').map(|(index, _)| line_start + index + 1));
+    }
+}
+
+/// The documents that are open in the editor, shared between the requests of the server.
+/// Only documents sent with `textDocument/didOpen` are in the store, everything else is read from disk.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentStore {
+    documents: Arc<RwLock<HashMap<PathBuf, TextDocument>>>,
+}
+
+impl DocumentStore {
+    /// Start keeping track of the document at `file_path`, replacing it if it was already open.
+    pub async fn open(&self, file_path: &Path, text: String, version: i32) {
+        self.documents.write().await.insert(file_path.to_path_buf(), TextDocument::new(text, version));
+    }
+
+    /// Apply the `changes` to the document at `file_path`, in the order they are given.
+    /// Returns `None` if the document isn't open.
+    pub async fn change(&self, file_path: &Path, version: i32, changes: &[TextDocumentContentChangeEvent]) -> Option<i32> {
+        let mut documents = self.documents.write().await;
+        let document = documents.get_mut(file_path)?;
+
+        for change in changes {
+            document.apply_change(change);
+        }
+
+        document.version = version;
+
+        Some(version)
+    }
+
+    /// Stop keeping track of the document at `file_path`, after this it is read from disk again.
+    pub async fn close(&self, file_path: &Path) {
+        self.documents.write().await.remove(file_path);
+    }
+
+    /// Get the text of the document at `file_path`, if it is open.
+    pub async fn get_text(&self, file_path: &Path) -> Option<String> {
+        self.documents.read().await.get(file_path).map(|document| document.text.clone())
+    }
+
+    /// Get the text of every open document, e.g. for a request that reads a lot of files.
+    pub async fn get_texts(&self) -> HashMap<PathBuf, String> {
+        self.documents.read().await.iter().map(|(file_path, document)| (file_path.clone(), document.text.clone())).collect()
+    }
+
+    /// Check whether the document at `file_path` is open with text that is different to what is saved on disk.
+    pub async fn has_unsaved_changes(&self, file_path: &Path) -> bool {
+        match self.get_text(file_path).await {
+            Some(text) => fs::read_to_string(file_path).map_or(true, |saved_text| saved_text != text),
+            None => false
+        }
+    }
+
+    /// Get the version of the document at `file_path`, if it is open.
+    pub async fn get_version(&self, file_path: &Path) -> Option<i32> {
+        self.documents.read().await.get(file_path).map(|document| document.version)
+    }
+}
+
+/// Get the text of the file at `file_path` from `open_documents` if it is open, otherwise from disk. See [`DocumentStore::get_texts`].
+/// Returns `None` if it isn't open and could not be read.
+pub fn read_file(open_documents: &HashMap<PathBuf, String>, file_path: &Path) -> Option<String> {
+    match open_documents.get(file_path) {
+        Some(value) => Some(value.clone()),
+        None => fs::read_to_string(file_path).ok()
+    }
+}
+
+impl Backend {
+    /// Get the text of the file at `file_path`, from the editor if it is open so unsaved changes are included, otherwise from disk.
+    /// Returns `Err(BhcError)` if it isn't open and could not be read.
+    pub async fn read_document(&self, file_path: &Path) -> Result<String, BhcError> {
+        if let Some(text) = self.documents.get_text(file_path).await {
+            return Ok(text)
+        }
+
+        match fs::read_to_string(file_path) {
+            Ok(value) => Ok(value),
+            Err(error) => Err(BhcError::io(file_path, error))
+        }
+    }
+
+    /// Once the document at `uri` has gone `METADATA_DEBOUNCE` without another change after `version`, derive its metadata from what is in the editor and publish its diagnostics again.
+    /// Nothing happens if it has changed again or been closed by then, as the later change schedules its own update.
+    pub fn schedule_document_update(&self, uri: Url, file_path: PathBuf, version: i32) {
+        let backend = self.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(METADATA_DEBOUNCE).await;
+
+            if backend.documents.get_version(&file_path).await != Some(version) {
+                return
+            }
+
+            let text = match backend.documents.get_text(&file_path).await {
+                Some(value) => value,
+                None => return
+            };
+
+            if let Err(error) = backend.apply_document_contents(&file_path, &text).await {
+                backend.report_error(error).await;
+            }
+
+            backend.publish_diagnostics(&uri, &text).await;
+        });
+    }
+
+    /// Derive the metadata of the CSS or HTML file at `file_path` from `text` in the `MetadataCache`, without writing it to disk.
+    /// Loose files, virtual files and files without metadata are left alone.
+    /// Returns `Err(BhcError)` if the workspace metadata could not be read.
+    pub async fn apply_document_contents(&self, file_path: &Path, text: &str) -> Result<(), BhcError> {
+        if is_virtual_file(&file_path.to_path_buf()) {
+            return Ok(())
+        }
+
+        let workspace_path = match self.get_file_scope(file_path).await {
+            FileScope::Workspace(value) => value,
+            FileScope::Loose => return Ok(())
+        };
+
+        self.metadata_cache.write(&workspace_path, |index| index.apply_document_contents(file_path, text)).await.map(|_| ())
+    }
+
+    /// Throw away the unsaved changes to the CSS or HTML file at `file_path` in the `MetadataCache`, so what is saved on disk is used again.
+    /// Loose files and files without metadata are left alone.
+    /// Returns `Err(BhcError)` if the workspace metadata could not be read.
+    pub async fn discard_document_contents(&self, file_path: &Path) -> Result<(), BhcError> {
+        let workspace_path = match self.get_file_scope(file_path).await {
+            FileScope::Workspace(value) => value,
+            FileScope::Loose => return Ok(())
+        };
+
+        self.metadata_cache.write(&workspace_path, |index| index.discard_document_contents(file_path)).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tower_lsp::lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+    use super::{DocumentStore, TextDocument};
+
+    fn change(range: Option<Range>, text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent { range, range_length: None, text: text.to_string() }
+    }
+
+    #[test]
+    fn apply_change_test() {
+        let mut document = TextDocument::new(String::from("h1 {\r\n\tcolor: red;\r\n}\r\n"), 1);
+
+        // replacing a value on one line
+        document.apply_change(&change(Some(Range::new(Position::new(1, 8), Position::new(1, 11))), "blue"));
+        assert_eq!(document.text, "h1 {\r\n\tcolor: blue;\r\n}\r\n");
+
+        // inserting lines, then editing one of the lines that moved
+        document.apply_change(&change(Some(Range::new(Position::new(1, 13), Position::new(1, 13))), "\r\n\tmargin: 0;"));
+        document.apply_change(&change(Some(Range::new(Position::new(3, 0), Position::new(3, 1))), "} /* end */"));
+        assert_eq!(document.text, "h1 {\r\n\tcolor: blue;\r\n\tmargin: 0;\r\n} /* end */\r\n");
+
+        // deleting across lines, characters past the end of a line are clamped to the end of it
+        document.apply_change(&change(Some(Range::new(Position::new(1, 100), Position::new(2, 12))), ""));
+        assert_eq!(document.text, "h1 {\r\n\tcolor: blue;\r\n} /* end */\r\n");
+
+        // characters are counted in UTF-16, so the emoji is two
+        document.apply_change(&change(None, "a::after { content: \"😀\"; }"));
+        document.apply_change(&change(Some(Range::new(Position::new(0, 23), Position::new(0, 24))), "!"));
+        assert_eq!(document.text, "a::after { content: \"😀!; }");
+
+        // a position past the end of the document is the end of it
+        document.apply_change(&change(Some(Range::new(Position::new(5, 0), Position::new(5, 0))), "\n"));
+        assert_eq!(document.offset_at(Position::new(1, 0)), document.text.len());
+    }
+
+    #[tokio::test]
+    async fn document_store_test() {
+        let store = DocumentStore::default();
+        let file_path = PathBuf::from("/workspace/index.html");
+
+        assert_eq!(store.change(&file_path, 2, &[change(None, "<p>")]).await, None);
+
+        store.open(&file_path, String::from("<div>"), 1).await;
+
+        assert_eq!(store.change(&file_path, 2, &[change(Some(Range::new(Position::new(0, 1), Position::new(0, 4))), "p"), change(None, "<span>")]).await, Some(2));
+        assert_eq!(store.get_text(&file_path).await, Some(String::from("<span>")));
+        assert_eq!(store.get_version(&file_path).await, Some(2));
+
+        store.close(&file_path).await;
+
+        assert_eq!(store.get_text(&file_path).await, None);
+    }
+
+    #[tokio::test]
+    async fn has_unsaved_changes_test() {
+        let store = DocumentStore::default();
+        let file_path = std::env::temp_dir().join("bhc_has_unsaved_changes_test.css");
+
+        std::fs::write(&file_path, "h1 { color: red; }").unwrap();
+
+        assert!(!store.has_unsaved_changes(&file_path).await);
+
+        store.open(&file_path, String::from("h1 { color: red; }"), 1).await;
+
+        assert!(!store.has_unsaved_changes(&file_path).await);
+
+        store.change(&file_path, 2, &[change(None, "h1 { color: blue; }")]).await;
+
+        assert!(store.has_unsaved_changes(&file_path).await);
+    }
+}
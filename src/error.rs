@@ -0,0 +1,145 @@
+use std::{fmt::Display, io, path::{Path, PathBuf}};
+
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, MessageType, Range, Url};
+
+use crate::{diagnostics::DIAGNOSTIC_SOURCE, logging::Logging, Backend};
+
+/// Something that went wrong while handling a request or notification, along with the file or URI it went wrong for
+#[derive(Debug)]
+pub enum BhcError {
+    Io(PathBuf, io::Error), // a file or folder could not be read, written, created or deleted
+    Json(PathBuf, serde_json::Error), // metadata could not be serialized, or the JSON saved at the path could not be deserialized
+    Path(PathBuf), // a path that isn't valid UTF-8, or has no file name or parent folder
+    Uri(Url), // a URI that isn't a file on disk
+    Parse(PathBuf, Range, String), // the contents of a file could not be parsed, and where it went wrong
+    NoWorkspaces, // the client has no workspace folders open
+    Workspace(PathBuf), // a file that isn't inside of any open workspace
+    Metadata(PathBuf), // a file that has no metadata in its workspace
+    DuplicateId(PathBuf, u32), // workspace metadata that gives the same id to more than one file
+    Migration(PathBuf, String), // metadata saved by another version of the server that can't be upgraded, and why
+    Locked(PathBuf), // another editor held the lock on the metadata of a workspace for too long
+    UnsavedChanges(PathBuf), // a file that would be overwritten is open with changes that haven't been saved
+    InvalidName(String), // a class or id name that can't be used without escaping it
+    Client(String), // a request sent to the client failed
+    Settings(serde_json::Error), // the settings sent by the client aren't a valid configuration
+}
+
+impl BhcError {
+    pub fn io(path: &Path, error: io::Error) -> BhcError {
+        BhcError::Io(path.to_path_buf(), error)
+    }
+
+    pub fn json(path: &Path, error: serde_json::Error) -> BhcError {
+        BhcError::Json(path.to_path_buf(), error)
+    }
+
+    pub fn path(path: &Path) -> BhcError {
+        BhcError::Path(path.to_path_buf())
+    }
+
+    pub fn metadata(path: &Path) -> BhcError {
+        BhcError::Metadata(path.to_path_buf())
+    }
+
+    /// Whether the user should be shown the error with `window/showMessage`, because they can do something about it, e.g. a file that can't be read or metadata that is corrupted.
+    /// Everything else happens in the normal running of the server, e.g. hovering over a file outside of a workspace, so it is only logged.
+    pub fn is_shown(&self) -> bool {
+        matches!(self, BhcError::Io(..) | BhcError::Json(..) | BhcError::Locked(..) | BhcError::UnsavedChanges(..) | BhcError::Settings(..))
+    }
+
+    /// Get the diagnostic for an error in the contents of a document, which is shown in the document instead of as a message.
+    /// Returns `None` if the error isn't about the contents of a document.
+    pub fn to_diagnostic(&self) -> Option<Diagnostic> {
+        match self {
+            BhcError::Parse(_, range, message) => Some(Diagnostic {
+                range: *range,
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some(String::from(DIAGNOSTIC_SOURCE)),
+                message: message.clone(),
+                ..Diagnostic::default()
+            }),
+            _ => None
+        }
+    }
+}
+
+impl Display for BhcError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BhcError::Io(path, error) => write!(formatter, "Error occurred trying to access {:?}: {}", path, error),
+            BhcError::Json(path, error) => write!(formatter, "Error occurred trying to serialize or deserialize metadata ({:?}): {}", path, error),
+            BhcError::Path(path) => write!(formatter, "Path is not valid UTF-8, or has no file name: {:?}", path),
+            BhcError::Uri(uri) => write!(formatter, "Could not convert URI to a file path: {}", uri),
+            BhcError::Parse(path, range, message) => write!(formatter, "Error parsing {:?} at {}:{}: {}", path, range.start.line + 1, range.start.character + 1, message),
+            BhcError::NoWorkspaces => write!(formatter, "There are no workspaces open"),
+            BhcError::Workspace(path) => write!(formatter, "Could not find workspace for file: {:?}", path),
+            BhcError::Metadata(path) => write!(formatter, "Could not find metadata for file: {:?}", path),
+            BhcError::DuplicateId(path, id) => write!(formatter, "More than one file has the id {} in the metadata at {:?}", id, path),
+            BhcError::Migration(path, reason) => write!(formatter, "Could not upgrade the metadata at {:?}, as {}", path, reason),
+            BhcError::Locked(path) => write!(formatter, "Timed out waiting for another editor to finish updating the metadata ({:?})", path),
+            BhcError::UnsavedChanges(path) => write!(formatter, "{:?} has unsaved changes, save or revert it before saving the virtual file", path),
+            BhcError::InvalidName(name) => write!(formatter, "\"{}\" is not a valid class or id name", name),
+            BhcError::Client(message) => write!(formatter, "Error occurred in a request to the client: {}", message),
+            BhcError::Settings(error) => write!(formatter, "The \"bhc\" settings aren't a valid configuration: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BhcError {}
+
+impl Backend {
+    /// Log the `error`, and show it to the user if they can do something about it. See [`BhcError::is_shown`].
+    pub async fn report_error(&self, error: BhcError) {
+        self.log_error(&error).await;
+
+        if error.is_shown() {
+            self.client.show_message(MessageType::ERROR, error).await;
+        }
+    }
+}
+
+/// Get the path of the file at `uri`.
+/// Returns `Err(BhcError::Uri)` if it isn't a file on disk.
+pub fn uri_to_path(uri: &Url) -> Result<PathBuf, BhcError> {
+    match uri.to_file_path() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(BhcError::Uri(uri.clone()))
+    }
+}
+
+/// Get `path` as a string, and its file name.
+/// Returns `Err(BhcError::Path)` if it isn't valid UTF-8 or has no file name.
+pub fn path_to_strings(path: &Path) -> Result<(String, String), BhcError> {
+    match (path.to_str(), path.file_name().and_then(|name| name.to_str())) {
+        (Some(absolute_path), Some(file_name)) => Ok((absolute_path.to_string(), file_name.to_string())),
+        _ => Err(BhcError::path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use tower_lsp::lsp_types::{Position, Range, Url};
+
+    use super::{path_to_strings, uri_to_path, BhcError};
+
+    #[test]
+    fn bhc_error_test() {
+        let path = PathBuf::from("/workspace/base.css");
+
+        assert!(BhcError::io(&path, io::Error::from(io::ErrorKind::NotFound)).is_shown());
+        assert!(!BhcError::Workspace(path.clone()).is_shown());
+
+        let parse_error = BhcError::Parse(path.clone(), Range::new(Position::new(2, 4), Position::new(2, 5)), String::from("Unexpected token"));
+
+        assert_eq!(parse_error.to_string(), "Error parsing \"/workspace/base.css\" at 3:5: Unexpected token");
+        assert_eq!(parse_error.to_diagnostic().map(|diagnostic| diagnostic.range.start), Some(Position::new(2, 4)));
+        assert_eq!(BhcError::NoWorkspaces.to_diagnostic(), None);
+
+        assert_eq!(path_to_strings(&path).unwrap(), (String::from("/workspace/base.css"), String::from("base.css")));
+        assert!(path_to_strings(&PathBuf::from("/")).is_err());
+
+        assert!(matches!(uri_to_path(&Url::parse("https://example.com/base.css").unwrap()), Err(BhcError::Uri(_))));
+    }
+}
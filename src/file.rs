@@ -1,38 +1,40 @@
-use html5gum::{HtmlString, Token, Tokenizer};
 use std::{
-    ffi::OsStr, fs::{self, File}, ops::Deref, path::{Component, PathBuf}};
-use tower_lsp::lsp_types::{DidOpenTextDocumentParams, TextDocumentItem};
+    collections::HashSet, ffi::OsStr, fs::{self, File}, io::Write, path::{Component, Path, PathBuf}, process, sync::atomic::{AtomicU64, Ordering}};
+use tower_lsp::lsp_types::DidOpenTextDocumentParams;
 
-use crate::{metadata::{css_metadata::get_metadata_files, file_metadata::FormattedCssFile}, Backend, VIRTUAL_PATH};
+use crate::{config::FileFilter, error::{path_to_strings, uri_to_path, BhcError}, glob::Gitignore, metadata::{css_metadata::{resolve_imports, write_styles, CssMetaData}, file_metadata::FormattedCssFile, html_metadata::{get_document_stylesheets, parse_inline_styles, INLINE_STYLE_ID, html_link::{parse_stylesheet_links, LinkTarget}}}, workspace::FileScope, Backend, EXT_HTML, VIRTUAL_PATH};
 
 
+/// Counts up for every temporary file made by [`write_file_atomic`], so no two writes share one
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 //TODO: I need to make metadata for the virtual file to act as a "staging" area for changes that are made but save has not been pressed
 //TODO: Think about what is needed to send back to the client to get the colouring of lines correct
 // for each line we need to know its owner, 
 
 impl Backend {
-    pub async fn get_css_file(&self, params: DidOpenTextDocumentParams) -> Result<Option<FormattedCssFile>, String> {
-        let file_path = file_to_pathbuf(&params.text_document);
+    pub async fn get_css_file(&self, params: DidOpenTextDocumentParams) -> Result<Option<FormattedCssFile>, BhcError> {
+        let file_path = match uri_to_path(&params.text_document.uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
 
         if let Some(file_path_root) = file_path.parent(){
             let file_pathbuf = file_path_root.to_path_buf();
 
-            let workspace_path = match self.get_workspace_folder(&file_path).await {
-                Ok(value) => value,
-                Err(error) => return Err(error),
-            };
-
-            let file_destination = get_full_path(&file_path, &workspace_path);
+            let scope = self.get_file_scope(&file_path).await;
     
             let css_files = match get_css_file_paths(&file_pathbuf, &params.text_document.text) {
                 Ok(value) => value,
                 Err(error) => return Err(error),
             };
 
-            match css_files.len() {
-                0 => return Ok(None),
-                1 => {
-                    let css_metadata_files = match get_metadata_files(&css_files, &workspace_path) {
+            let has_inline_styles = parse_inline_styles(&params.text_document.text).is_some();
+
+            match (css_files.len(), has_inline_styles) {
+                (0, false) => return Ok(None),
+                (1, false) => {
+                    let css_metadata_files = match self.get_stylesheets(&scope, &css_files).await {
                         Ok(value) => value,
                         Err(error) => return Err(error)
                     };
@@ -40,89 +42,191 @@ impl Backend {
                     if let Some(metadata) = css_metadata_files {
                         let mut formatted_file = FormattedCssFile::generate_formatted_file(&metadata);
 
-                        formatted_file.absolute_path = css_files.first().unwrap().to_str().unwrap().to_string();
+                        formatted_file.absolute_path = match path_to_strings(&css_files[0]) {
+                            Ok((absolute_path, _)) => absolute_path,
+                            Err(error) => return Err(error)
+                        };
                     
                         return Ok(Some(formatted_file))
                     }
                 },
                 _ => {
-                    let css_metadata_files = match get_metadata_files(&css_files, &workspace_path) {
-                        Ok(value) => value,
+                    // virtual files are kept inside of the workspace, so there is nowhere to put one for a loose file
+                    let workspace_path = match scope {
+                        FileScope::Workspace(value) => value,
+                        FileScope::Loose => return Ok(None)
+                    };
+
+                    let file_destination = get_full_path(&file_path, &workspace_path);
+
+                    let linked_metadata_files = match self.get_metadata_files(&css_files, &workspace_path).await {
+                        Ok(value) => value.unwrap_or_default(),
                         Err(error) => return Err(error)
                     };
 
-                    if let Some(metadata) = css_metadata_files {
-                        let mut formatted_file = FormattedCssFile::generate_formatted_file(&metadata);
+                    // styles inside of the document are its own owner, and go where they are written among the linked stylesheets
+                    let css_metadata_files = get_document_stylesheets(&file_path, &params.text_document.text, linked_metadata_files);
 
-                        let css_string = formatted_file.to_css_string();
-                        
-                        match save_css_file(&css_string, &file_destination) {
-                            Ok(value) => {
-                                formatted_file.absolute_path = value.to_str().unwrap().to_string();
+                    let mut formatted_file = FormattedCssFile::generate_formatted_file(&css_metadata_files);
 
-                                return Ok(Some(formatted_file))
-                            },
-                            Err(error) => return Err(error)
-                        };
-                    } 
+                    let css_string = formatted_file.to_css_string();
+                    
+                    match save_css_file(&css_string, &file_destination) {
+                        Ok(value) => {
+                            formatted_file.absolute_path = match path_to_strings(&value) {
+                                Ok((absolute_path, _)) => absolute_path,
+                                Err(error) => return Err(error)
+                            };
+
+                            return Ok(Some(formatted_file))
+                        },
+                        Err(error) => return Err(error)
+                    };
                 }
             }
         }; 
 
-        Err(format!("Could not get css file for file: {:?}", file_path))
+        Err(BhcError::path(&file_path))
     }
-}
 
-pub fn get_css_file_paths(absolute_path_of_html: &PathBuf, file_contents: &str) -> Result<Vec<PathBuf>, String> {
-    let tag_name: HtmlString = HtmlString(b"link".to_vec());
-    let href: HtmlString = HtmlString(b"href".to_vec());
+    /// Write the contents of the virtual file at `virtual_path` back to the stylesheets it was generated from.
+    /// The formatted file is regenerated from the HTML document the virtual file belongs to, so the owner of each line is known,
+    /// then every stylesheet that has changed is rewritten and its metadata updated.
+    /// Returns `Ok(Vec<PathBuf>)` of the stylesheets that were changed.
+    /// Returns `Err(BhcError::UnsavedChanges)` if a stylesheet that would change is open with unsaved changes, in which case nothing is written.
+    /// Returns `Err(BhcError)` if the HTML document or any of its stylesheets could not be read or written.
+    pub async fn save_virtual_file(&self, virtual_path: &PathBuf) -> Result<Vec<PathBuf>, BhcError> {
+        let workspace_path = match self.get_workspace_folder(virtual_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error),
+        };
 
-    let mut css_vec: Vec<PathBuf> = Vec::new();
+        let html_path = match get_original_path(virtual_path, &workspace_path) {
+            Some(value) => value,
+            None => return Err(BhcError::metadata(virtual_path))
+        };
 
-    let mut error_occurred = false;
-    let mut error_string: String = String::new();
+        // the virtual file is generated from what is in the editor, so it is split the same way
+        let html_string = match self.read_document(&html_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
 
-    Tokenizer::new(file_contents)
-        .infallible()
-        .filter_map(|token| match token {
-            Token::StartTag(tag) => {
-                if tag.name == tag_name {
-                    Some(tag)
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        })
-        .for_each(|link| match link.attributes.get_key_value(&href) {
-            Some((_, value)) => {
-                let s = value.deref().to_vec();
-                let string_result = String::from_utf8_lossy(&s);
-                let pathbuf = PathBuf::from(&string_result.to_string());
-
-                match find_absolute_path(&absolute_path_of_html, &pathbuf) {
-                    Ok(value) => css_vec.push(value),
-                    Err(error) => {
-                        error_occurred = true;
-                        error_string = error;
-                        return;
-                    }
-                }
+        let virtual_string = match fs::read_to_string(virtual_path) {
+            Ok(value) => value,
+            Err(error) => return Err(BhcError::io(virtual_path, error))
+        };
+
+        let css_files = match get_css_file_paths(&html_path, &html_string) {
+            Ok(value) => value,
+            Err(error) => return Err(error),
+        };
+
+        let linked_metadata_files = match self.get_metadata_files(&css_files, &workspace_path).await {
+            Ok(value) => value.unwrap_or_default(),
+            Err(error) => return Err(error)
+        };
+
+        // the file has to be generated the same way it was opened, so the owner of each line matches. Changes to inline styles aren't written back
+        let css_metadata_files = get_document_stylesheets(&html_path, &html_string, linked_metadata_files);
+
+        if css_metadata_files.is_empty() {
+            return Ok(Vec::new())
+        }
+
+        // every stylesheet is checked before any are written, so the changes are never only partly saved
+        let new_files = match get_stylesheet_changes(&css_metadata_files, &virtual_string) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        for (css_path, _) in &new_files {
+            // writing to disk would throw away what has been typed into the stylesheet
+            if self.documents.has_unsaved_changes(css_path).await {
+                return Err(BhcError::UnsavedChanges(css_path.clone()))
             }
-            None => (),
-        });
+        }
+
+        let mut changed_files: Vec<PathBuf> = Vec::new();
+
+        for (css_path, new_css_string) in new_files {
+            match write_file_atomic(&css_path, &new_css_string) {
+                Ok(_) => (),
+                Err(error) => return Err(error),
+            };
+
+            self.update_file_metadata(&css_path).await?;
+
+            changed_files.push(css_path);
+        }
 
-    if error_occurred {
-        return Err(error_string);
+        Ok(changed_files)
     }
+}
+
+/// Get the new contents of every stylesheet in `css_metadata_files` that is changed by `virtual_string`, the contents of the virtual file generated from them.
+/// Styles inside of the HTML document are owned by [`INLINE_STYLE_ID`] and aren't written back, as the document isn't a stylesheet.
+/// Returns `Err(BhcError)` if a stylesheet could not be read.
+pub fn get_stylesheet_changes(css_metadata_files: &Vec<CssMetaData>, virtual_string: &str) -> Result<Vec<(PathBuf, String)>, BhcError> {
+    let formatted_file = FormattedCssFile::generate_formatted_file(css_metadata_files);
+
+    let mut new_files: Vec<(PathBuf, String)> = Vec::new();
+
+    for (owner, styles) in formatted_file.split_by_owner(virtual_string) {
+        if owner == INLINE_STYLE_ID {
+            continue
+        }
+
+        let css_metadata = match css_metadata_files.iter().find(|metadata| metadata.id == owner) {
+            Some(value) => value,
+            None => continue
+        };
+
+        let css_path = PathBuf::from(&css_metadata.absolute_path);
 
-    Ok(css_vec)
+        let css_string = match fs::read_to_string(&css_path) {
+            Ok(value) => value,
+            Err(error) => return Err(BhcError::io(&css_path, error))
+        };
+
+        let new_css_string = write_styles(&css_string, &styles);
+
+        if new_css_string != css_string {
+            new_files.push((css_path, new_css_string));
+        }
+    }
+
+    Ok(new_files)
+}
+
+/// Check if the `file_path` is a virtual file inside of `.bhc/.virtual`
+pub fn is_virtual_file(file_path: &PathBuf) -> bool {
+    match file_path.to_str() {
+        Some(path) => path.contains(VIRTUAL_PATH),
+        None => false
+    }
+}
+
+/// Get the paths of the local stylesheets applied to the HTML document at `absolute_path_of_html`, in the order they cascade in.
+/// Links that aren't stylesheets, and stylesheets that are remote or inside of a `data:` URL, are left out. See [`parse_stylesheet_links`].
+/// Sheets brought in with `@import` are included before the sheet that imports them. See [`resolve_imports`].
+/// Returns `Ok(Vec<PathBuf>)`, the paths may not exist.
+pub fn get_css_file_paths(absolute_path_of_html: &PathBuf, file_contents: &str) -> Result<Vec<PathBuf>, BhcError> {
+    let css_vec: Vec<PathBuf> = parse_stylesheet_links(absolute_path_of_html, file_contents)
+        .into_iter()
+        .filter_map(|link| match link.target {
+            LinkTarget::Local(path) => Some(path),
+            _ => None,
+        })
+        .collect();
+
+    Ok(resolve_imports(&css_vec))
 }
 
 /// For a given `document_path`, which will be an absolute path of a HTML document, and a `css_path` which may or may not be an absolute path, 
 /// Returns `Ok(PathBuf)` if it was able to find the path. This will be the absolute path of the `css_path`
-/// Returns `Err(String)` if it was unable to find the absolute path for the provided `css_path`
-fn find_absolute_path(document_path: &PathBuf, css_path: &PathBuf) -> Result<PathBuf, String> {
+/// Returns `Err(BhcError)` if it was unable to find the absolute path for the provided `css_path`
+pub fn find_absolute_path(document_path: &PathBuf, css_path: &PathBuf) -> Result<PathBuf, BhcError> {
     if css_path.exists() && css_path.is_absolute() {
         return Ok(css_path.clone());
     }
@@ -132,14 +236,18 @@ fn find_absolute_path(document_path: &PathBuf, css_path: &PathBuf) -> Result<Pat
         let mut actual_path = PathBuf::new();
         
         let mut document_dir: PathBuf = if document_path.is_file() {
-            document_path.parent().unwrap().to_path_buf()
+            match document_path.parent() {
+                Some(value) => value.to_path_buf(),
+                None => return Err(BhcError::path(document_path))
+            }
         } else {
             document_path.clone()
         };
 
         for component in css_components {
             if component == Component::ParentDir {
-                document_dir = document_dir.parent().unwrap().to_path_buf()
+                // going above the root stays at the root, the same as a browser
+                document_dir.pop();
             } else {
                 actual_path.push(component);
             }
@@ -150,47 +258,50 @@ fn find_absolute_path(document_path: &PathBuf, css_path: &PathBuf) -> Result<Pat
         return Ok(final_path)
     }
 
-    Err(format!("Error trying to find CSS File: {:?}", css_path))
+    Err(BhcError::path(css_path))
 }
 
-pub fn save_css_file(css_string: &str, save_path: &PathBuf) -> Result<PathBuf, String> {
-
-    match create_dir_and_file(&save_path) {
+pub fn save_css_file(css_string: &str, save_path: &PathBuf) -> Result<PathBuf, BhcError> {
+    match write_file_atomic(save_path, css_string) {
         Ok(_) => (),
         Err(error) => return Err(error)
     };
 
-    match fs::write(save_path, css_string) {
-        Ok(_) => (),
-        Err(error) => return Err(format!("Error occurred trying to write to the file ({:?}), {:?}", save_path, error)),
-    };
-
     Ok(save_path.clone())
 }
 
-pub fn create_dir_and_file(file_path: &PathBuf) -> Result<(), String> {
-    let directory = file_path.parent().unwrap().to_path_buf();
+/// Write `contents` to `file_path` by writing them to a temporary file next to it and renaming that into place, so the file is never left empty or half written, e.g. if the server crashes part way through.
+/// The folder it is in is created if it doesn't exist yet.
+/// Returns `Err(BhcError)` if the folder or file could not be written, in which case the file is left as it was.
+pub fn write_file_atomic(file_path: &Path, contents: &str) -> Result<(), BhcError> {
+    let (directory, file_name) = match (file_path.parent(), file_path.file_name()) {
+        (Some(directory), Some(file_name)) => (directory, file_name.to_string_lossy()),
+        _ => return Err(BhcError::path(file_path))
+    };
 
     match fs::create_dir_all(directory) {
         Ok(_) => (),
-        Err(error) => return Err(format!("Error occurred trying to create the save directory: {:?}", error)),
+        Err(error) => return Err(BhcError::io(directory, error)),
     };
 
-    match File::create(file_path) {
-        Ok(_) => (),
-        Err(error) => return Err(format!("Error occurred trying to create the file: ({:?}), {:?}", file_path, error)),
+    // unique within the process too, as two requests can write the same file at once
+    let temp_id = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path = directory.join(format!(".{}.{}-{}.tmp", file_name, process::id(), temp_id));
+
+    let result = File::create(&temp_path)
+    .and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    })
+    .and_then(|_| fs::rename(&temp_path, file_path));
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(BhcError::io(file_path, error))
+        }
     }
-
-    Ok(())
-}
-
-fn file_to_pathbuf(document: &TextDocumentItem) -> PathBuf {
-    let file_path = match document.uri.to_file_path() {
-        Ok(value) => value,
-        Err(_) => PathBuf::new(), // Need a default, if file doesn't exist, this should never happen
-    };
-
-    file_path
 }
 
 /// Get the full PathBuf for a virtual file. The virtual file gets created when a HTML file contains more than one CSS link inside it so we can concatenate all of its contents.
@@ -210,42 +321,65 @@ fn get_full_path(file_pathbuf: &PathBuf, workspace_pathbuf: &PathBuf) -> PathBuf
     final_path
 }
 
-/// For a given path, return all of the files it contains as a `Vec<PathBuf>`
-pub fn recursive_file_search(path: &PathBuf) -> Vec<PathBuf> {
+/// Get the HTML document a virtual file was created for, this is the reverse of `get_full_path`.
+pub fn get_original_path(virtual_pathbuf: &PathBuf, workspace_pathbuf: &PathBuf) -> Option<PathBuf> {
+    let extra_path = virtual_pathbuf.strip_prefix(workspace_pathbuf.join(VIRTUAL_PATH)).ok()?;
+
+    let mut final_path = workspace_pathbuf.join(extra_path);
+    final_path.set_extension(EXT_HTML);
+
+    Some(final_path)
+}
+
+/// For a given path, return all of the files it contains that the `filter` includes as a `Vec<PathBuf>`.
+/// Folders the `filter` excludes aren't searched at all, and the `.gitignore` in each folder applies to everything inside of it.
+pub fn recursive_file_search(path: &Path, filter: &FileFilter) -> Vec<PathBuf> {
     let mut found_paths: Vec<PathBuf> = Vec::new();
-    
-    match fs::read_dir(path) {
-        Ok(value) => value.for_each(|res| match res {
-            Ok(value) => {
-                if value.path().is_dir() {
-                    inner_recursive_file_search(&value.path(), &mut found_paths);
-
-                } else if value.path().is_file() {
-                    found_paths.push(value.path())
-                }
-            }
-            Err(_) => (),
-        }),
-        Err(_) => (),
-    }
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+
+    inner_recursive_file_search(path, filter, &mut Vec::new(), &mut visited, &mut found_paths);
 
     found_paths
 }
 
-fn inner_recursive_file_search(path: &PathBuf, found_paths: &mut Vec<PathBuf>) {
-    match fs::read_dir(path) {
-        Ok(value) => value.for_each(|res| match res {
-            Ok(value) => {
-                if value.path().is_dir() {
-                    inner_recursive_file_search(&value.path(), found_paths);
+fn inner_recursive_file_search(path: &Path, filter: &FileFilter, gitignores: &mut Vec<Gitignore>, visited: &mut HashSet<PathBuf>, found_paths: &mut Vec<PathBuf>) {
+    // a symbolic link can point back at a folder that is already being searched
+    if filter.follow_symlinks {
+        let is_new = match fs::canonicalize(path) {
+            Ok(value) => visited.insert(value),
+            Err(_) => false,
+        };
+
+        if !is_new {
+            return
+        }
+    }
+
+    let gitignore = filter.read_gitignore(path);
+    let has_gitignore = gitignore.is_some();
+    gitignores.extend(gitignore);
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let metadata = match filter.read_entry(&entry) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let entry_path = entry.path();
 
-                } else if value.path().is_file() {
-                    found_paths.push(value.path())
+            if metadata.is_dir() {
+                if filter.is_directory_included(&entry_path, gitignores) {
+                    inner_recursive_file_search(&entry_path, filter, gitignores, visited, found_paths);
                 }
+            } else if metadata.is_file() && filter.is_file_included(&entry_path, metadata.len(), gitignores) {
+                found_paths.push(entry_path)
             }
-            Err(_) => (),
-        }),
-        Err(_) => (),
+        }
+    }
+
+    if has_gitignore {
+        gitignores.pop();
     }
 }
 
@@ -266,9 +400,9 @@ pub fn contains_web_documents(file_paths: &Vec<PathBuf>) -> bool {
 #[cfg(test)]
 mod tests {
 
-    use std::path::PathBuf;
+    use std::{fs, path::PathBuf};
 
-    use crate::file::{find_absolute_path, get_css_file_paths, get_full_path, save_css_file};
+    use crate::{file::{find_absolute_path, get_css_file_paths, get_full_path, get_original_path, get_stylesheet_changes, save_css_file, write_file_atomic}, metadata::{css_metadata::CssMetaData, file_metadata::FormattedCssFile, html_metadata::get_document_stylesheets}};
 
     #[test]
     fn test_find_absolute_path() {
@@ -330,6 +464,16 @@ mod tests {
         assert_eq!(get_full_path(&file_pathbuf, &workspace_path), a);
     }
 
+    #[test]
+    fn test_get_original_path() {
+        let workspace_path = PathBuf::from("/temp/random/path");
+
+        let virtual_path = PathBuf::from("/temp/random/path/.bhc/.virtual/html/myfile.css");
+
+        assert_eq!(get_original_path(&virtual_path, &workspace_path), Some(PathBuf::from("/temp/random/path/html/myfile.html")));
+        assert_eq!(get_original_path(&PathBuf::from("/temp/random/path/css/myfile.css"), &workspace_path), None);
+    }
+
     #[test]
     fn test_save_css_file() {
         let file_contents = r#"
@@ -344,9 +488,57 @@ p {
 }
 "#;
 
-        let save_path = PathBuf::from("C:\\Users\\Ollie\\Documents\\testing123\\new_file.css");
+        let save_path = std::env::temp_dir().join("bhc_save_css_file_test").join("new_file.css");
 
         assert_eq!(save_css_file(file_contents, &save_path).unwrap(), save_path.clone());
     }
+
+    #[test]
+    fn write_file_atomic_test() {
+        let directory = std::env::temp_dir().join("bhc_write_file_atomic_test");
+        let file_path = directory.join(".meta").join("meta.json");
+
+        let _ = fs::remove_dir_all(&directory);
+
+        write_file_atomic(&file_path, "{}").unwrap();
+        write_file_atomic(&file_path, "{ \"id\": 1 }").unwrap();
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "{ \"id\": 1 }");
+
+        // the temporary files have all been renamed into place
+        assert_eq!(fs::read_dir(file_path.parent().unwrap()).unwrap().count(), 1);
+
+        assert!(write_file_atomic(&PathBuf::from("/"), "{}").is_err());
+    }
+
+    #[test]
+    fn get_stylesheet_changes_test() {
+        let directory = std::env::temp_dir().join("bhc_get_stylesheet_changes_test");
+        let html_path = directory.join("index.html");
+        let css_path = directory.join("base.css");
+
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let html_string = "<html><head>\n<link rel=\"stylesheet\" href=\"base.css\">\n<style>p.x { color: blue; }</style>\n</head><body><h1 style=\"margin: 0\">Title</h1></body></html>\n";
+
+        fs::write(&html_path, html_string).unwrap();
+        fs::write(&css_path, "h1 { color: red; }\n").unwrap();
+
+        let css_metadata_files = get_document_stylesheets(&html_path, html_string, vec![CssMetaData::parse_file(&css_path, &1).unwrap()]);
+        let virtual_string = FormattedCssFile::generate_formatted_file(&css_metadata_files).to_css_string();
+
+        // both the linked and the inline styles are edited, but only the linked stylesheet is written back
+        let edited_string = virtual_string.replace("red", "green").replace("blue", "black").replace("margin: 0", "margin: 1px");
+        let changes = get_stylesheet_changes(&css_metadata_files, &edited_string).unwrap();
+
+        assert_eq!(changes, vec![(css_path.clone(), String::from("h1 { color: green; }\n"))]);
+
+        for (file_path, contents) in changes {
+            write_file_atomic(&file_path, &contents).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&html_path).unwrap(), html_string);
+    }
 }
 /* #endregion */
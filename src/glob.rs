@@ -0,0 +1,245 @@
+use std::{fs, path::{Component, Path, PathBuf}};
+
+const GITIGNORE: &str = ".gitignore";
+
+/// A glob pattern, matched against a path relative to the folder it belongs to.
+/// `*` matches anything within one component of the path, `?` matches one character, `[a-z]` matches one character in the set, `**` matches any number of components and `{a,b}` matches either alternative.
+/// A pattern with no `/` in it, other than at the end, matches at any depth, the same as in a `.gitignore`. Otherwise it starts at the folder it belongs to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Glob {
+    alternatives: Vec<Vec<String>>, // the components of each pattern the braces expand to
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        let pattern = pattern.trim().trim_end_matches('/');
+        let is_anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+
+        let alternatives = expand_braces(pattern)
+            .into_iter()
+            .map(|pattern| {
+                let mut components: Vec<String> = if is_anchored { Vec::new() } else { vec![String::from("**")] };
+                components.extend(pattern.split('/').filter(|component| !component.is_empty()).map(String::from));
+
+                components
+            })
+            .collect();
+
+        Glob { alternatives }
+    }
+
+    /// Check whether the glob matches `relative_path`, which is relative to the folder the glob belongs to.
+    /// A path that leaves the folder, e.g. with `..`, never matches.
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        let components: Option<Vec<&str>> = relative_path
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        match components {
+            Some(components) => self.alternatives.iter().any(|pattern| match_components(pattern, &components)),
+            None => false,
+        }
+    }
+}
+
+/// A rule read from a `.gitignore`
+#[derive(Debug, PartialEq, Clone)]
+struct GitignoreRule {
+    glob: Glob,
+    is_negated: bool, // starts with `!`, so it brings back something an earlier rule ignored
+    is_directory_only: bool, // ends with `/`, so it only matches folders
+}
+
+/// The rules of one `.gitignore`, which apply to the folder it is in and everything inside of it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Gitignore {
+    directory: PathBuf,
+    rules: Vec<GitignoreRule>,
+}
+
+impl Gitignore {
+    pub fn parse(directory: &Path, contents: &str) -> Gitignore {
+        let rules = contents
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (is_negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, line),
+                };
+
+                // `\#` and `\!` are how a pattern starts with those characters
+                let pattern = pattern.strip_prefix('\\').unwrap_or(pattern);
+
+                GitignoreRule {
+                    glob: Glob::new(pattern),
+                    is_negated,
+                    is_directory_only: pattern.ends_with('/'),
+                }
+            })
+            .collect();
+
+        Gitignore { directory: directory.to_path_buf(), rules }
+    }
+
+    /// Read the `.gitignore` directly inside of `directory`.
+    /// Returns `None` if there isn't one, or it could not be read.
+    pub fn read(directory: &Path) -> Option<Gitignore> {
+        let contents = fs::read_to_string(directory.join(GITIGNORE)).ok()?;
+
+        Some(Gitignore::parse(directory, &contents))
+    }
+
+    /// Check whether the file or folder at `path` is ignored. The last rule that matches decides.
+    /// Returns `None` if no rule matches, or `path` isn't inside of the folder the `.gitignore` is in.
+    pub fn is_ignored(&self, path: &Path, is_directory: bool) -> Option<bool> {
+        let relative_path = path.strip_prefix(&self.directory).ok()?;
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_directory || !rule.is_directory_only) && rule.glob.is_match(relative_path))
+            .map(|rule| !rule.is_negated)
+    }
+}
+
+/// Check whether `path` is a `.gitignore`
+pub fn is_gitignore(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == GITIGNORE)
+}
+
+fn match_components(pattern: &[String], components: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=components.len()).any(|skipped| match_components(rest, &components[skipped..])),
+        Some((first, rest)) => match components.split_first() {
+            Some((component, components)) => {
+                let first: Vec<char> = first.chars().collect();
+                let component: Vec<char> = component.chars().collect();
+
+                match_wildcards(&first, &component) && match_components(rest, components)
+            },
+            None => false,
+        },
+    }
+}
+
+fn match_wildcards(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skipped| match_wildcards(rest, &text[skipped..])),
+        Some(('?', rest)) => !text.is_empty() && match_wildcards(rest, &text[1..]),
+        Some(('[', rest)) if rest.contains(&']') => {
+            let end = rest.iter().skip(1).position(|character| *character == ']').map_or(0, |position| position + 1);
+
+            match text.first() {
+                Some(character) => match_class(&rest[..end], *character) && match_wildcards(&rest[end + 1..], &text[1..]),
+                None => false,
+            }
+        },
+        Some(('\\', [escaped, rest @ ..])) => text.first() == Some(escaped) && match_wildcards(rest, &text[1..]),
+        Some((character, rest)) => text.first() == Some(character) && match_wildcards(rest, &text[1..]),
+    }
+}
+
+/// Check whether `character` is in the set inside of a `[...]`, which can have ranges like `a-z` and start with `!` to match anything not in it
+fn match_class(class: &[char], character: char) -> bool {
+    let (is_negated, class) = match class.split_first() {
+        Some(('!', rest)) | Some(('^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut is_match = false;
+    let mut index = 0;
+
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            is_match |= (class[index]..=class[index + 2]).contains(&character);
+            index += 3;
+        } else {
+            is_match |= class[index] == character;
+            index += 1;
+        }
+    }
+
+    is_match != is_negated
+}
+
+/// Expand the first `{a,b}` in `pattern` into one pattern for each alternative, then any after it.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let start = match pattern.find('{') {
+        Some(value) => value,
+        None => return vec![pattern.to_string()],
+    };
+
+    let mut depth = 0;
+    let mut alternatives: Vec<&str> = Vec::new();
+    let mut alternative_start = start + 1;
+
+    for (index, character) in pattern.char_indices().skip_while(|(index, _)| *index <= start) {
+        match character {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&pattern[alternative_start..index]);
+                alternative_start = index + 1;
+            },
+            '}' => {
+                alternatives.push(&pattern[alternative_start..index]);
+
+                return alternatives
+                    .iter()
+                    .flat_map(|alternative| expand_braces(&format!("{}{}{}", &pattern[..start], alternative, &pattern[index + 1..])))
+                    .collect()
+            },
+            _ => (),
+        }
+    }
+
+    // a brace that is never closed is just a character
+    vec![pattern.to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{Gitignore, Glob};
+
+    #[test]
+    fn glob_test() {
+        assert!(Glob::new("node_modules").is_match(Path::new("packages/site/node_modules")));
+        assert!(Glob::new("*.min.css").is_match(Path::new("css/base.min.css")));
+        assert!(!Glob::new("/*.html").is_match(Path::new("pages/index.html")));
+        assert!(Glob::new("/*.html").is_match(Path::new("index.html")));
+        assert!(Glob::new("src/**/*.{html,css}").is_match(Path::new("src/base.css")));
+        assert!(Glob::new("src/**/*.{html,css}").is_match(Path::new("src/pages/about/index.html")));
+        assert!(!Glob::new("src/**/*.{html,css}").is_match(Path::new("dist/index.html")));
+        assert!(Glob::new("page-[0-9].html").is_match(Path::new("page-2.html")));
+        assert!(!Glob::new("page-[!0-9].html").is_match(Path::new("page-2.html")));
+        assert!(Glob::new("?.css").is_match(Path::new("a.css")));
+        assert!(!Glob::new("*").is_match(Path::new("../index.html")));
+    }
+
+    #[test]
+    fn gitignore_test() {
+        let workspace_path = PathBuf::from("/workspace");
+        let gitignore = Gitignore::parse(&workspace_path, "# build output\ndist/\n*.log\n!keep.log\n/vendor\n\\#notes.html\n");
+
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("site/dist"), true), Some(true));
+        // only folders are matched by a pattern ending in a `/`
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("dist"), false), None);
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("logs/error.log"), false), Some(true));
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("logs/keep.log"), false), Some(false));
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("vendor"), true), Some(true));
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("site/vendor"), true), None);
+        assert_eq!(gitignore.is_ignored(&workspace_path.join("#notes.html"), false), Some(true));
+        assert_eq!(gitignore.is_ignored(&PathBuf::from("/elsewhere/error.log"), false), None);
+    }
+}
@@ -0,0 +1,404 @@
+use std::{ffi::OsStr, path::{Path, PathBuf}};
+
+use cssparser::{Parser, ParserInput};
+use tower_lsp::lsp_types::{Hover, HoverContents, HoverParams, MarkupContent, MarkupKind, Position, Range};
+
+use crate::{
+    error::{uri_to_path, BhcError},
+    file::{get_css_file_paths, get_original_path, is_virtual_file},
+    metadata::{
+        css_metadata::{css_style::CssStyle, parse_sheet, CssMetaData},
+        html_metadata::{get_document_stylesheets, html_element::{parse_elements, symbol_at_position, HtmlSymbol}},
+    },
+    position::{range_contains, word_at_position},
+    workspace::FileScope,
+    Backend, EXT_CSS, EXT_HTML,
+};
+
+/// A single value of a property, from one of the stylesheets in the cascade
+#[derive(Debug, PartialEq, Clone)]
+struct CascadeValue {
+    file_name: String,
+    value: String,
+    important: bool,
+    range: Option<Range>,
+}
+
+impl Backend {
+    /// Get the hover for the position in `params`.
+    /// In CSS and virtual files this is the cascade of the selector or property under the cursor, in HTML documents it is every linked rule that matches the element, class or id under the cursor.
+    /// Returns `Ok(None)` if there is nothing to show at the position.
+    /// Returns `Err(BhcError)` if the document, or the metadata of its stylesheets, could not be read.
+    pub async fn get_hover(&self, params: &HoverParams) -> Result<Option<Hover>, BhcError> {
+        let position = params.text_document_position_params.position;
+        let uri = &params.text_document_position_params.text_document.uri;
+
+        let file_path = match uri_to_path(uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let scope = self.get_file_scope(&file_path).await;
+
+        let file_string = match self.read_document(&file_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        match file_path.extension().and_then(OsStr::to_str) {
+            Some(EXT_HTML) => {
+                let css_files = match get_css_file_paths(&file_path, &file_string) {
+                    Ok(value) => value,
+                    Err(error) => return Err(error),
+                };
+
+                let stylesheets = match self.get_stylesheets(&scope, &css_files).await {
+                    Ok(value) => value.unwrap_or_default(),
+                    Err(error) => return Err(error)
+                };
+
+                Ok(html_hover(&file_string, position, &stylesheets))
+            },
+            Some(EXT_CSS) => {
+                let stylesheets = match scope {
+                    FileScope::Workspace(workspace_path) if is_virtual_file(&file_path) => self.get_virtual_file_stylesheets(&file_path, &workspace_path).await,
+                    FileScope::Workspace(workspace_path) => self.get_cascade_stylesheets(&file_path, &file_string, &workspace_path).await,
+                    // a loose stylesheet isn't linked from any known document, so it only cascades with itself
+                    FileScope::Loose => Ok(vec![parse_stylesheet(&file_path, &file_string)]),
+                };
+
+                match stylesheets {
+                    Ok(value) => Ok(css_hover(&file_string, position, &value)),
+                    Err(error) => Err(error)
+                }
+            },
+            _ => Ok(None)
+        }
+    }
+
+    /// Get every stylesheet that cascades together with the stylesheet at `css_path`, in the order they are linked.
+    /// These are the stylesheets linked from the same HTML documents, the stylesheet itself is always included and is parsed from `css_string` so it matches what is being hovered.
+    async fn get_cascade_stylesheets(&self, css_path: &PathBuf, css_string: &str, workspace_path: &Path) -> Result<Vec<CssMetaData>, BhcError> {
+        let workspace_metadata = match self.get_workspace_metadata(workspace_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let mut current_stylesheet = parse_stylesheet(css_path, css_string);
+        current_stylesheet.id = workspace_metadata.get_css_file_id(css_path).unwrap_or_default();
+
+        let mut ids: Vec<u32> = Vec::new();
+
+        for html_file in workspace_metadata.get_referencing_html_files(&current_stylesheet.id) {
+            for id in html_file.css_files.unwrap_or_default().iter() {
+                if !ids.contains(id) {
+                    ids.push(*id);
+                }
+            }
+        }
+
+        if ids.is_empty() {
+            return Ok(vec![current_stylesheet])
+        }
+
+        let stylesheets: Result<Result<Vec<CssMetaData>, BhcError>, BhcError> = self.metadata_cache.read(workspace_path, |index| {
+            ids
+            .iter()
+            .map(|id| {
+                if *id == current_stylesheet.id {
+                    return Ok(current_stylesheet.clone())
+                }
+
+                match index.get_css_metadata_by_id(id) {
+                    Some(value) => Ok(value.clone()),
+                    None => {
+                        let css_path = workspace_metadata.css_files.iter().find(|css_file| css_file.id == *id).map(|css_file| PathBuf::from(&css_file.absolute_path));
+
+                        Err(BhcError::metadata(&css_path.unwrap_or(workspace_path.to_path_buf())))
+                    }
+                }
+            })
+            .collect()
+        }).await;
+
+        match stylesheets {
+            Ok(value) => value,
+            Err(error) => Err(error)
+        }
+    }
+
+    /// Get the stylesheets that were combined into the virtual file at `virtual_path`, in the order they cascade in for its HTML document.
+    async fn get_virtual_file_stylesheets(&self, virtual_path: &PathBuf, workspace_path: &PathBuf) -> Result<Vec<CssMetaData>, BhcError> {
+        let html_path = match get_original_path(virtual_path, workspace_path) {
+            Some(value) => value,
+            None => return Err(BhcError::metadata(virtual_path))
+        };
+
+        let html_string = self.read_document(&html_path).await?;
+
+        let css_files = get_css_file_paths(&html_path, &html_string)?;
+
+        let stylesheets = self.get_metadata_files(&css_files, workspace_path).await?.unwrap_or_default();
+
+        // the virtual file includes the styles inside of the document
+        Ok(get_document_stylesheets(&html_path, &html_string, stylesheets))
+    }
+}
+
+/// Build the metadata of the stylesheet at `css_path` from `css_string`, so it matches what is being hovered. It is given an id of 0.
+fn parse_stylesheet(css_path: &Path, css_string: &str) -> CssMetaData {
+    let mut stylesheet = CssMetaData::new();
+    stylesheet.file_name = css_path.file_name().and_then(OsStr::to_str).unwrap_or_default().to_string();
+    stylesheet.absolute_path = css_path.to_str().unwrap_or_default().to_string();
+    stylesheet.styles = parse_css_string(css_string);
+
+    stylesheet
+}
+
+fn parse_css_string(css_string: &str) -> Option<Vec<CssStyle>> {
+    let mut parser_input = ParserInput::new(css_string);
+    let mut parser = Parser::new(&mut parser_input);
+
+    parse_sheet(&mut parser)
+}
+
+/// Build the hover for the selector or property at `position` in the `css_string`, using the `stylesheets` for the cascade.
+fn css_hover(css_string: &str, position: Position, stylesheets: &[CssMetaData]) -> Option<Hover> {
+    let styles = parse_css_string(css_string)?;
+
+    let style = styles
+    .iter()
+    .filter(|style| style.range.is_some_and(|range| range_contains(&range, position)))
+    .max_by_key(|style| style.range.map(|range| range.start))?;
+
+    if let Some(selector_range) = style.selector_range.filter(|range| range_contains(range, position)) {
+        return Some(markdown_hover(selector_markdown(style, stylesheets), selector_range))
+    }
+
+    let (word, word_range) = word_at_position(css_string, position)?;
+
+    let attribute = style.attributes.iter().find(|attribute| {
+        attribute.name == word || attribute.ranges.iter().any(|range| range_contains(range, position))
+    })?;
+
+    Some(markdown_hover(property_markdown(style, &attribute.name, stylesheets), word_range))
+}
+
+/// Build the hover for the element, class or id at `position` in the `html_string`, listing every rule from the `stylesheets` that matches it.
+fn html_hover(html_string: &str, position: Position, stylesheets: &[CssMetaData]) -> Option<Hover> {
+    let elements = parse_elements(html_string);
+
+    let (element, symbol, symbol_range) = symbol_at_position(&elements, position)?;
+
+    let title = match &symbol {
+        HtmlSymbol::Element(name) => format!("<{}>", name),
+        HtmlSymbol::Class(class) => format!(".{}", class),
+        HtmlSymbol::Id(id) => format!("#{}", id),
+    };
+
+    let mut markdown = format!("**`{}`**\n\n", title);
+    let mut found_rule = false;
+
+    for stylesheet in stylesheets {
+        for style in stylesheet.styles.iter().flatten() {
+            let matches = style.selectors.iter().any(|selector| {
+                let subject = match selector.subject() {
+                    Some(value) => value,
+                    None => return false
+                };
+
+                subject.matches_element(element) && match &symbol {
+                    HtmlSymbol::Element(_) => true,
+                    HtmlSymbol::Class(class) => subject.classes.contains(class),
+                    HtmlSymbol::Id(id) => subject.ids.contains(id),
+                }
+            });
+
+            if matches {
+                markdown.push_str(&format!("- `{}` — {}\n", style_label(style), location_label(&stylesheet.file_name, style.range)));
+                found_rule = true;
+            }
+        }
+    }
+
+    if !found_rule {
+        markdown.push_str("No rules in the linked stylesheets match this.\n");
+    }
+
+    Some(markdown_hover(markdown, symbol_range))
+}
+
+/// List every stylesheet that declares the rule of `style`, and the value of each of its properties that wins the cascade.
+fn selector_markdown(style: &CssStyle, stylesheets: &[CssMetaData]) -> String {
+    let mut markdown = format!("**`{}`**\n\nDeclared in:\n", style_label(style));
+
+    let mut property_names: Vec<String> = Vec::new();
+
+    for stylesheet in stylesheets {
+        for matching_style in stylesheet.styles.iter().flatten().filter(|other| other.key() == style.key()) {
+            markdown.push_str(&format!("- {}\n", location_label(&stylesheet.file_name, matching_style.range)));
+
+            for attribute in &matching_style.attributes {
+                if !property_names.contains(&attribute.name) {
+                    property_names.push(attribute.name.clone());
+                }
+            }
+        }
+    }
+
+    if property_names.is_empty() {
+        return markdown
+    }
+
+    property_names.sort();
+
+    markdown.push_str("\nWinning values:\n");
+
+    for name in property_names {
+        let values = get_cascade_values(style, &name, stylesheets);
+
+        if let Some(winner) = get_winner(&values).and_then(|index| values.get(index)) {
+            markdown.push_str(&format!("- `{}: {}` — {}\n", name, value_label(winner), winner.file_name));
+        }
+    }
+
+    markdown
+}
+
+/// List every value of the property `name` inside of the rule of `style` in cascade order, marking the one that wins.
+fn property_markdown(style: &CssStyle, name: &str, stylesheets: &[CssMetaData]) -> String {
+    let values = get_cascade_values(style, name, stylesheets);
+    let winner = get_winner(&values);
+
+    let mut markdown = format!("**`{}`** in `{}`\n\n", name, style_label(style));
+
+    for (index, value) in values.iter().enumerate() {
+        let wins = if Some(index) == winner { " **(wins)**" } else { "" };
+
+        markdown.push_str(&format!("{}. `{}` — {}{}\n", index + 1, value_label(value), location_label(&value.file_name, value.range), wins));
+    }
+
+    markdown
+}
+
+/// Get every value of the property `name` declared for the same rule as `style`, stylesheets are in link order and values in the order they are written.
+fn get_cascade_values(style: &CssStyle, name: &str, stylesheets: &[CssMetaData]) -> Vec<CascadeValue> {
+    let mut values: Vec<CascadeValue> = Vec::new();
+
+    for stylesheet in stylesheets {
+        for matching_style in stylesheet.styles.iter().flatten().filter(|other| other.key() == style.key()) {
+            for attribute in matching_style.attributes.iter().filter(|attribute| attribute.name == name) {
+                for (index, value) in attribute.values.iter().enumerate() {
+                    values.push(CascadeValue {
+                        file_name: stylesheet.file_name.clone(),
+                        value: value.clone(),
+                        important: attribute.is_important(index),
+                        range: attribute.get_range(index),
+                    });
+                }
+            }
+        }
+    }
+
+    values
+}
+
+/// Every value has the same selector, so the last `!important` value wins, or the last value if none of them are important.
+fn get_winner(values: &[CascadeValue]) -> Option<usize> {
+    values.iter().rposition(|value| value.important).or(values.len().checked_sub(1))
+}
+
+fn style_label(style: &CssStyle) -> String {
+    let (conditions, tag) = style.key();
+
+    if conditions.is_empty() {
+        tag
+    } else {
+        format!("{} {}", conditions, tag)
+    }
+}
+
+fn value_label(value: &CascadeValue) -> String {
+    if value.important {
+        format!("{} !important", value.value)
+    } else {
+        value.value.clone()
+    }
+}
+
+fn location_label(file_name: &str, range: Option<Range>) -> String {
+    match range {
+        Some(value) => format!("{} (line {})", file_name, value.start.line + 1),
+        None => file_name.to_string(),
+    }
+}
+
+fn markdown_hover(markdown: String, range: Range) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: markdown,
+        }),
+        range: Some(range),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cssparser::{Parser, ParserInput};
+    use tower_lsp::lsp_types::{HoverContents, Position};
+
+    use crate::metadata::css_metadata::{parse_sheet, CssMetaData};
+
+    use super::{css_hover, html_hover};
+
+    fn stylesheet(file_name: &str, css_string: &str) -> CssMetaData {
+        let mut parser_input = ParserInput::new(css_string);
+        let mut parser = Parser::new(&mut parser_input);
+
+        let mut metadata = CssMetaData::new();
+        metadata.file_name = file_name.to_string();
+        metadata.styles = parse_sheet(&mut parser);
+
+        metadata
+    }
+
+    fn hover_text(hover: Option<tower_lsp::lsp_types::Hover>) -> String {
+        match hover.map(|value| value.contents) {
+            Some(HoverContents::Markup(markup)) => markup.value,
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn css_hover_test() {
+        let base = "h1 {\n\tcolor: red !important;\n\tmargin: 0;\n}\n";
+        let theme = "h1 {\n\tcolor: blue;\n\tmargin: 4px;\n}\n";
+
+        let stylesheets = vec![stylesheet("base.css", base), stylesheet("theme.css", theme)];
+
+        let property = hover_text(css_hover(theme, Position::new(1, 3), &stylesheets));
+
+        assert_eq!(property, "**`color`** in `h1`\n\n1. `red !important` — base.css (line 2) **(wins)**\n2. `blue` — theme.css (line 2)\n");
+
+        let selector = hover_text(css_hover(theme, Position::new(0, 1), &stylesheets));
+
+        assert_eq!(selector, "**`h1`**\n\nDeclared in:\n- base.css (line 1)\n- theme.css (line 1)\n\nWinning values:\n- `color: red !important` — base.css\n- `margin: 4px` — theme.css\n");
+
+        assert_eq!(css_hover(theme, Position::new(4, 0), &stylesheets), None);
+    }
+
+    #[test]
+    fn html_hover_test() {
+        let html = "<body>\n\t<button class=\"btn btn-primary\">Save</button>\n</body>";
+        let stylesheets = vec![stylesheet("base.css", ".btn { padding: 0; }\n@media print {\n\tbutton.btn-primary { display: none; }\n}\n.btn-primary.large { color: red; }\n")];
+
+        let class = hover_text(html_hover(html, Position::new(1, 22), &stylesheets));
+
+        assert_eq!(class, "**`.btn-primary`**\n\n- `@media print button.btn-primary` — base.css (line 3)\n");
+
+        let element = hover_text(html_hover(html, Position::new(1, 3), &stylesheets));
+
+        assert_eq!(element, "**`<button>`**\n\n- `.btn` — base.css (line 1)\n- `@media print button.btn-primary` — base.css (line 3)\n");
+    }
+}
@@ -1,18 +1,40 @@
 mod bhc_commands;
+mod config;
+mod definition;
+mod diagnostics;
+mod document_store;
+mod error;
 mod file;
+mod glob;
+mod hover;
 mod logging;
 mod metadata;
+mod position;
+mod references;
+mod rename;
+mod watched_files;
 mod workspace;
 
+use std::{path::PathBuf, sync::Arc};
+
 use bhc_commands::BhcShowDocumentParams;
+use config::ConfigCache;
+use document_store::DocumentStore;
+use error::BhcError;
+use file::is_virtual_file;
 use logging::Logging;
+use metadata::metadata_cache::MetadataCache;
 use tower_lsp::lsp_types::*;
+use tokio::sync::RwLock;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 
 
+const BHC_PATH: &'static str = ".bhc"; // everything the server keeps in a workspace is inside of this folder
+const CONFIG_PATH: &'static str = ".bhc/config.json"; // which files in the workspace are searched, see `config::WorkspaceConfig`
 const METADATA_PATH: &'static str = ".bhc/.meta/meta.json";
 const CSS_METADATA_PATH: &'static str = ".bhc/.meta/css";
 const HTML_METADATA_PATH: &'static str = ".bhc/.meta/html";
+const METADATA_LOCK_PATH: &'static str = ".bhc/.meta/.lock"; // held while the metadata is being changed, so two editors open on the same folder take turns
 const SHARED_PATH: &'static str = ".bhc/.shared"; //TODO: Make the user have an elective shared folder to fit their folder structure. Will need to make a sidebar tool, not for now
 const VIRTUAL_PATH: &'static str = ".bhc/.virtual";
 
@@ -24,11 +46,18 @@ const EXT_CSS: &'static str = "css";
 #[derive(Debug, Clone)]
 pub struct Backend {
     client: Client,
+    metadata_cache: MetadataCache, // the metadata of every workspace, so it is only read from disk once
+    documents: DocumentStore, // the text of every open document, including changes that haven't been saved
+    configs: ConfigCache, // which files are searched in each workspace
+    workspace_folders: Arc<RwLock<Vec<PathBuf>>>, // the workspaces that are open, so the client doesn't have to be asked for every request
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> tower_lsp::jsonrpc::Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> tower_lsp::jsonrpc::Result<InitializeResult> {
+        let supports_settings = params.capabilities.workspace.and_then(|workspace| workspace.configuration).unwrap_or(false);
+        self.configs.set_supports_settings(supports_settings);
+
         Ok(InitializeResult {
             server_info: None,
             offset_encoding: None,
@@ -39,7 +68,7 @@ impl LanguageServer for Backend {
                 color_provider: None,
                 completion_provider: None,
                 declaration_provider: None,
-                definition_provider: None,
+                definition_provider: Some(OneOf::Left(true)),
                 diagnostic_provider: None,
                 document_formatting_provider: None,
                 document_highlight_provider: None,
@@ -50,19 +79,27 @@ impl LanguageServer for Backend {
                 execute_command_provider: None,
                 experimental: None,
                 folding_range_provider: None,
-                hover_provider: None,
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
                 implementation_provider: None,
                 inlay_hint_provider: None,
                 inline_value_provider: None,
                 linked_editing_range_provider: None,
                 moniker_provider: None,
                 position_encoding: None,
-                references_provider: None,
-                rename_provider: None,
+                references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
                 selection_range_provider: None,
                 semantic_tokens_provider: None,
                 signature_help_provider: None,
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::INCREMENTAL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..TextDocumentSyncOptions::default()
+                })),
                 type_definition_provider: None,
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
@@ -84,10 +121,19 @@ impl LanguageServer for Backend {
 
         self.log_info("BHC language server initialized!").await;
 
+        // registered before looking for workspaces, so folders added later are watched too
+        self.register_file_watchers().await;
+        self.register_config_changes().await;
+
+        if let Err(error) = self.load_workspace_folders().await {
+            self.report_error(error).await;
+            return
+        }
+
         let workspaces = match self.get_workspaces().await {
             Ok(value) => value,
             Err(error) => {
-                self.log_error(error).await;
+                self.report_error(error).await;
                 return
             }
         };
@@ -104,6 +150,12 @@ impl LanguageServer for Backend {
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         self.log_info(format!("File Opened: {}", params.text_document.uri)).await;
 
+        if let Ok(file_path) = params.text_document.uri.to_file_path() {
+            self.documents.open(&file_path, params.text_document.text.clone(), params.text_document.version).await;
+        }
+
+        self.publish_diagnostics(&params.text_document.uri, &params.text_document.text).await;
+
         match params.text_document.language_id.as_str() {
             EXT_HTML => {
                 //TODO: If it contains multiple then we put it into the .bhc/.virtual folder.
@@ -111,7 +163,7 @@ impl LanguageServer for Backend {
                 let formatted_css_file = match self.get_css_file(params).await {
                     Ok(value) => value,
                     Err(error) => {
-                        self.log_error(error).await;
+                        self.report_error(error).await;
                         return
                     }
                 };
@@ -124,7 +176,13 @@ impl LanguageServer for Backend {
                 //TODO: This needs to pass back more information to colour the page.
 
                 if let Some(formatted_file) = formatted_css_file {
-                    let css_file_url = Url::parse(&formatted_file.absolute_path).unwrap();
+                    let css_file_url = match Url::from_file_path(&formatted_file.absolute_path) {
+                        Ok(value) => value,
+                        Err(_) => {
+                            self.report_error(BhcError::Path(PathBuf::from(&formatted_file.absolute_path))).await;
+                            return
+                        }
+                    };
 
                     let params = BhcShowDocumentParams { 
                         uri: css_file_url,
@@ -133,7 +191,7 @@ impl LanguageServer for Backend {
     
                     match self.client.send_request::<bhc_commands::BhcShowDocumentRequest>(params).await {
                         Ok(_) => (),
-                        Err(error) => self.log_error(format!("Error occurred trying to open CSS file: {}", error)).await,
+                        Err(error) => self.report_error(BhcError::Client(format!("Could not open CSS file: {}", error))).await,
                     };
                 }
             },
@@ -147,30 +205,158 @@ impl LanguageServer for Backend {
         }
     }
 
+    async fn hover(&self, params: HoverParams) -> tower_lsp::jsonrpc::Result<Option<Hover>> {
+        match self.get_hover(&params).await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                self.report_error(error).await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> tower_lsp::jsonrpc::Result<Option<GotoDefinitionResponse>> {
+        match self.get_definition(&params).await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                self.report_error(error).await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn references(&self, params: ReferenceParams) -> tower_lsp::jsonrpc::Result<Option<Vec<Location>>> {
+        match self.get_references(&params).await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                self.report_error(error).await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn prepare_rename(&self, params: TextDocumentPositionParams) -> tower_lsp::jsonrpc::Result<Option<PrepareRenameResponse>> {
+        match self.get_prepare_rename(&params).await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                self.report_error(error).await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn rename(&self, params: RenameParams) -> tower_lsp::jsonrpc::Result<Option<WorkspaceEdit>> {
+        match self.get_rename(&params).await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                let message = error.to_string();
+                self.report_error(error).await;
+                // the client shows this to the user, e.g. when the new name isn't valid
+                Err(tower_lsp::jsonrpc::Error::invalid_params(message))
+            }
+        }
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let file_path = match params.text_document.uri.to_file_path() {
+            Ok(value) => value,
+            Err(_) => return
+        };
+
+        // only the edits are sent, so the metadata and diagnostics are updated from the document store once typing pauses
+        match self.documents.change(&file_path, params.text_document.version, &params.content_changes).await {
+            Some(version) => self.schedule_document_update(params.text_document.uri, file_path, version),
+            None => self.log_error(format!("Changes were sent for a document that isn't open: {}", params.text_document.uri)).await
+        }
+    }
+
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         self.log_info(format!("File Closed: {}", params.text_document.uri)).await;
+
+        let file_path = match params.text_document.uri.to_file_path() {
+            Ok(value) => value,
+            Err(_) => return
+        };
+
+        self.documents.close(&file_path).await;
+
+        // changes that weren't saved are thrown away, so the metadata goes back to what is saved
+        if let Err(error) = self.discard_document_contents(&file_path).await {
+            self.report_error(error).await;
+        }
     }
 
     async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
         self.log_info("Workspace Folder Changed.").await;
 
+        let removed_workspaces = self.parse_workspaces(&params.event.removed).await;
         let added_workspaces = self.parse_workspaces(&params.event.added).await;
 
+        self.change_workspace_folders(&added_workspaces, &removed_workspaces).await;
+
+        // the metadata of a removed workspace stays on disk for when it is opened again
+        for workspace in removed_workspaces {
+            self.metadata_cache.remove(&workspace).await;
+            self.configs.remove(&workspace).await;
+        }
+
         for workspace in added_workspaces {
             self.initialize_workspace(&workspace).await;
         }
     }
 
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+    async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
+        self.log_info("Configuration Changed.").await;
+
+        let workspaces = match self.get_workspaces().await {
+            Ok(value) => value,
+            Err(error) => {
+                self.report_error(error).await;
+                return
+            }
+        };
+
+        // which files are searched could have changed, so every workspace is searched again, which reads the settings again too
+        for workspace in workspaces {
+            self.initialize_workspace(&workspace).await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
         self.log_info("watched files have changed!").await;
+
+        self.apply_file_events(&params.changes).await;
     }
 
 
-    async fn did_save(&self, x: DidSaveTextDocumentParams) {
-        self.log_info(format!("Saved files changed: {}", x.text_document.uri)).await;
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        self.log_info(format!("Saved files changed: {}", params.text_document.uri)).await;
+
+        let file_path = match params.text_document.uri.to_file_path() {
+            Ok(value) => value,
+            Err(_) => return
+        };
+
+        if !is_virtual_file(&file_path) {
+            if let Err(error) = self.update_file_metadata(&file_path).await {
+                self.report_error(error).await;
+            }
+        }
 
-        //TODO: Add updating the metadata files when saved
-        //TODO: Add updating the original files when virtual file is saved, we know virtual file by their paths
+        if let Ok(text) = self.read_document(&file_path).await {
+            self.publish_diagnostics(&params.text_document.uri, &text).await;
+        }
+
+        if is_virtual_file(&file_path) {
+            match self.save_virtual_file(&file_path).await {
+                Ok(changed_files) => {
+                    for changed_file in changed_files {
+                        self.log_info(format!("Virtual file changes written to: {:?}", changed_file)).await;
+                    }
+                },
+                Err(error) => self.report_error(error).await,
+            }
+        }
     }
 }
 /* #endregion */
@@ -182,7 +368,7 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::build(|client| Backend { client }).finish();
+    let (service, socket) = LspService::build(|client| Backend { client, metadata_cache: MetadataCache::default(), documents: DocumentStore::default(), configs: ConfigCache::default(), workspace_folders: Arc::default() }).finish();
 
     Server::new(stdin, stdout, socket).serve(service).await;
 }
\ No newline at end of file
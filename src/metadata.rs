@@ -1,6 +1,9 @@
-use std::{collections::HashMap, ffi::OsStr, fs, path::PathBuf};
+use std::{collections::HashMap, ffi::OsStr, fs, path::{Path, PathBuf}};
 
-use crate::{CSS_METADATA_PATH, HTML_METADATA_PATH, METADATA_PATH, VIRTUAL_PATH};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{error::BhcError, file::write_file_atomic, CSS_METADATA_PATH, HTML_METADATA_PATH, METADATA_PATH, VIRTUAL_PATH};
 
 use self::{css_metadata::CssMetaData, html_metadata::HtmlMetaData};
 
@@ -8,6 +11,11 @@ pub mod css_metadata;
 pub mod workspace_metadata;
 pub mod html_metadata;
 pub mod file_metadata;
+pub mod cascade;
+pub mod metadata_cache;
+pub mod reconcile;
+pub mod metadata_lock;
+pub mod migration;
 
 // TODO: Make the absolute_path of all the metadata the same, some seem to be using unix syntax, others using windows 
 
@@ -78,17 +86,16 @@ impl GroupedFiles {
 	pub fn map_css_files(&self) -> HashMap<PathBuf, PathBuf> {
 		let mut css_map: HashMap<PathBuf, PathBuf> = HashMap::new();
 		
+		// metadata that can't be read is left out, so the file gets new metadata
 		self.json_files
 		.css_files
 		.iter()
 		.for_each(|metadata_file_path|{
-			let json_string = fs::read_to_string(metadata_file_path).unwrap(); // File will exist (only time it might not is if the person deletes a file between the creation of the vector and the reading of the file)
-
-			let parsed_css_file: CssMetaData = serde_json::from_str(&json_string).unwrap();
+			if let Ok(parsed_css_file) = read_metadata::<CssMetaData>(metadata_file_path) {
+				let css_file_path = PathBuf::from(&parsed_css_file.absolute_path);
 
-			let css_file_path = PathBuf::from(&parsed_css_file.absolute_path);
-
-			css_map.insert(css_file_path,metadata_file_path.clone());
+				css_map.insert(css_file_path,metadata_file_path.clone());
+			}
 		});
 		
 		css_map
@@ -99,13 +106,11 @@ impl GroupedFiles {
 		let mut html_map: HashMap<PathBuf, PathBuf> = HashMap::new();
 		
 		for metadata_file_path in &self.json_files.html_files {
-			let json_string = fs::read_to_string(metadata_file_path).unwrap(); // File will exist (only time it might not is if the person deletes a file between the creation of the vector and the reading of the file)
+			if let Ok(parsed_html_file) = read_metadata::<HtmlMetaData>(metadata_file_path) {
+				let html_file_path = PathBuf::from(&parsed_html_file.absolute_path);
 	
-			let parsed_html_file: HtmlMetaData = serde_json::from_str(&json_string).unwrap();
-	
-			let html_file_path = PathBuf::from(&parsed_html_file.absolute_path);
-	
-			html_map.insert(html_file_path,metadata_file_path.clone());
+				html_map.insert(html_file_path,metadata_file_path.clone());
+			}
 		}
 		
 		html_map
@@ -116,6 +121,114 @@ impl GroupedFiles {
 	}
 }
 
+/// Read the metadata saved as JSON at `metadata_path`.
+/// Returns `Err(BhcError)` if it could not be read or deserialized.
+pub fn read_metadata<T: DeserializeOwned>(metadata_path: &Path) -> Result<T, BhcError> {
+	let contents = match fs::read_to_string(metadata_path) {
+		Ok(value) => value,
+		Err(error) => return Err(BhcError::io(metadata_path, error))
+	};
+
+	match serde_json::from_str(&contents) {
+		Ok(value) => Ok(value),
+		Err(error) => Err(BhcError::json(metadata_path, error))
+	}
+}
+
+/// Write the `metadata` to `metadata_path` as JSON, replacing whatever was there. See [`write_file_atomic`].
+/// Returns `Err(BhcError)` if it could not be serialized or written.
+pub fn write_metadata<T: Serialize>(metadata_path: &Path, metadata: &T) -> Result<(), BhcError> {
+	let contents = serialize_metadata(metadata_path, metadata)?;
+
+	write_file_atomic(metadata_path, &contents)
+}
+
+/// Get the JSON the `metadata` is saved as at `metadata_path`, without writing it.
+/// Returns `Err(BhcError)` if it could not be serialized.
+pub fn serialize_metadata<T: Serialize>(metadata_path: &Path, metadata: &T) -> Result<String, BhcError> {
+	match serde_json::to_string_pretty(metadata) {
+		Ok(value) => Ok(value),
+		Err(error) => Err(BhcError::json(metadata_path, error))
+	}
+}
+
+/// Hash the `contents` of a file, to tell whether it has changed or been moved. This uses 64-bit FNV-1a, which is stable between runs and versions of the server.
+pub fn hash_contents(contents: &str) -> String {
+	let hash = contents.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+
+	format!("{:016x}", hash)
+}
+
+/// How close to when the metadata was made a file can have been modified before its `FileStamp` isn't trusted. A filesystem with a coarse clock gives an edit made just after the file was read the same modification time.
+const RACY_STAMP_WINDOW: i64 = 2_000_000_000; // in nanoseconds
+
+/// The modification time and size of a file when its metadata was made from it, so a file that hasn't been touched since doesn't have to be read to know it is unchanged
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct FileStamp {
+	pub modified: i64, // in nanoseconds since the Unix epoch, as `last_updated` is only saved in seconds
+	pub len: u64,
+}
+
+impl FileStamp {
+	/// Get the stamp of the file at `file_path` as it is now.
+	/// Returns `Err(BhcError)` if its metadata could not be read.
+	pub fn read(file_path: &Path) -> Result<FileStamp, BhcError> {
+		let metadata = match fs::metadata(file_path) {
+			Ok(value) => value,
+			Err(error) => return Err(BhcError::io(file_path, error))
+		};
+
+		let modified = match metadata.modified() {
+			Ok(value) => DateTime::<Utc>::from(value),
+			Err(error) => return Err(BhcError::io(file_path, error))
+		};
+
+		Ok(FileStamp { modified: modified.timestamp_nanos_opt().unwrap_or_default(), len: metadata.len() })
+	}
+}
+
+/// Whether the file that some metadata was made from has changed since
+#[derive(Debug, PartialEq)]
+pub enum Staleness {
+	Fresh, // the file hasn't changed
+	Touched(FileStamp), // the contents are the same, but the stamp couldn't be trusted, e.g. after a git checkout. The new stamp should be saved with a new `last_updated` so the file isn't read again next time
+	Stale, // the contents have changed, so the metadata has to be made again
+}
+
+/// Check whether the file at `file_path` still has the contents its metadata was made from.
+/// The `content_hash` is the authority on that, as modification times can go backwards or be too coarse to tell. The `stamp` is only a pre-check that lets a file skip being read, and only when it matches exactly and wasn't modified within `RACY_STAMP_WINDOW` of `last_updated`.
+/// Returns `Err(BhcError)` if the file could not be read.
+pub fn check_staleness(file_path: &Path, content_hash: Option<&str>, stamp: Option<&FileStamp>, last_updated: DateTime<Utc>) -> Result<Staleness, BhcError> {
+	let current_stamp = FileStamp::read(file_path)?;
+
+	let trusted_before = last_updated.timestamp_nanos_opt().unwrap_or_default() - RACY_STAMP_WINDOW;
+
+	if stamp == Some(&current_stamp) && current_stamp.modified < trusted_before {
+		return Ok(Staleness::Fresh)
+	}
+
+	let contents = match fs::read_to_string(file_path) {
+		Ok(value) => value,
+		Err(error) => return Err(BhcError::io(file_path, error))
+	};
+
+	match content_hash {
+		Some(hash) if hash == hash_contents(&contents) => Ok(Staleness::Touched(current_stamp)),
+		_ => Ok(Staleness::Stale)
+	}
+}
+
+/// Read the file at `file_path` to make its metadata from, along with its stamp. The stamp is read first, so a change made while it is being read isn't missed next time.
+/// Returns `Err(BhcError)` if the file could not be read.
+pub fn read_source(file_path: &Path) -> Result<(String, Option<FileStamp>), BhcError> {
+	let stamp = FileStamp::read(file_path).ok();
+
+	match fs::read_to_string(file_path) {
+		Ok(value) => Ok((value, stamp)),
+		Err(error) => Err(BhcError::io(file_path, error))
+	}
+}
+
 impl Into<GroupedFiles> for Vec<PathBuf> {
 	fn into(self) -> GroupedFiles {
 		let mut grouped_files = GroupedFiles::new();
@@ -128,8 +241,11 @@ impl Into<GroupedFiles> for Vec<PathBuf> {
 
 #[cfg(test)]
 mod tests {
-	use std::path::PathBuf;
-	use crate::metadata::{GroupedFiles, GroupedJsonFiles};
+	use std::{fs::{self, File}, path::PathBuf, time::{Duration, SystemTime}};
+
+	use chrono::{TimeDelta, Utc};
+
+	use crate::metadata::{check_staleness, hash_contents, FileStamp, GroupedFiles, GroupedJsonFiles, Staleness};
 
 	#[test]
 	fn sort_files_test() {
@@ -156,4 +272,33 @@ mod tests {
 
 		assert_eq!(actual, expected);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn hash_contents_test() {
+		assert_eq!(hash_contents(""), "cbf29ce484222325");
+		assert_eq!(hash_contents("a"), "af63dc4c8601ec8c");
+		assert_ne!(hash_contents("h1 { color: red; }"), hash_contents("h1 { color: blue; }"));
+	}
+
+	#[test]
+	fn check_staleness_test() {
+		let file_path = std::env::temp_dir().join("bhc_check_staleness_test.css");
+
+		fs::write(&file_path, "h1 { color: red; }").unwrap();
+
+		let hash = hash_contents("h1 { color: red; }");
+		let stamp = FileStamp::read(&file_path).unwrap();
+		let later = Utc::now() + TimeDelta::try_minutes(1).unwrap();
+
+		// a stamp that matches is trusted, unless the file was modified around when the metadata was made
+		assert_eq!(check_staleness(&file_path, Some(&hash), Some(&stamp), later).unwrap(), Staleness::Fresh);
+		assert_eq!(check_staleness(&file_path, Some(&hash), Some(&stamp), Utc::now()).unwrap(), Staleness::Touched(stamp));
+
+		// restored with an older modification time, e.g. by git
+		fs::write(&file_path, "h1 { color: blue; }").unwrap();
+		File::options().write(true).open(&file_path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(3600)).unwrap();
+
+		assert_eq!(check_staleness(&file_path, Some(&hash), Some(&stamp), later).unwrap(), Staleness::Stale);
+		assert_eq!(check_staleness(&file_path, None, None, later).unwrap(), Staleness::Stale);
+	}
+}
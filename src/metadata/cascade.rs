@@ -0,0 +1,240 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use tower_lsp::lsp_types::Position;
+
+use super::{css_metadata::css_selector::{parse_selector_list, CssSelector}, file_metadata::CssStyleExtended};
+
+/// A single value of a property in the formatted file, along with what is needed to rank it against the other values of the same property.
+struct Declaration {
+    style: usize,
+    attribute: usize,
+    important: bool,
+    is_style_attribute: bool,
+    sheet: usize, // where the sheet it came from is in the order they cascade in
+    position: Option<Position>, // where it is in its owner's file
+}
+
+/// Set `is_overwritten` on every declaration in `styles`, to whether another declaration of the same property always wins the cascade against it.
+/// Declarations are ranked by `!important`, then whether they are in a `style` attribute, then the specificity of their selector, then the order of their sheets, then the order they are written in their file.
+/// A declaration only wins if it applies everywhere the other one does, so its selector has to cover the other's and its conditions have to be part of the other's.
+/// A `style` attribute only applies to its own element, so it never wins against a selector, or against the `style` attribute of another element.
+/// Declarations inside of at-rules such as `@font-face` aren't ranked and are left as they are.
+/// Neither are declarations inside of an `@layer`, as layers rank by the order they are first declared in across every sheet, and unlayered styles outrank all of them.
+pub fn mark_overwritten(styles: &mut [CssStyleExtended]) {
+    let selectors: Vec<Vec<CssSelector>> = styles.iter().map(|style| parse_selector_list(&style.tag)).collect();
+
+    let mut declarations: HashMap<String, Vec<Declaration>> = HashMap::new();
+
+    for (style_index, style) in styles.iter().enumerate() {
+        let is_layered = style.conditions.iter().any(|condition| condition.name == "layer");
+
+        if style.tag.starts_with('@') || selectors[style_index].is_empty() || is_layered {
+            continue
+        }
+
+        for (attribute_index, attribute) in style.attributes.iter().enumerate() {
+            declarations.entry(attribute.name.clone()).or_default().push(Declaration {
+                style: style_index,
+                attribute: attribute_index,
+                important: attribute.important,
+                is_style_attribute: attribute.is_style_attribute,
+                sheet: attribute.sheet,
+                position: attribute.range.map(|range| range.start),
+            });
+        }
+    }
+
+    let mut results: Vec<(usize, usize, bool)> = Vec::new();
+
+    for group in declarations.values() {
+        for (loser_index, loser) in group.iter().enumerate() {
+            let is_overwritten = group
+            .iter()
+            .enumerate()
+            .any(|(winner_index, winner)| winner_index != loser_index && beats(winner, loser, styles, &selectors));
+
+            results.push((loser.style, loser.attribute, is_overwritten));
+        }
+    }
+
+    for (style, attribute, is_overwritten) in results {
+        styles[style].attributes[attribute].is_overwritten = Some(is_overwritten);
+    }
+}
+
+/// Whether `winner` wins the cascade against `loser` for every element `loser` applies to.
+fn beats(winner: &Declaration, loser: &Declaration, styles: &[CssStyleExtended], selectors: &[Vec<CssSelector>]) -> bool {
+    let winner_conditions = &styles[winner.style].conditions;
+    let loser_conditions = &styles[loser.style].conditions;
+
+    // a declaration inside of a condition that the other isn't in doesn't always apply
+    if !winner_conditions.iter().all(|condition| loser_conditions.contains(condition)) {
+        return false
+    }
+
+    if winner.is_style_attribute && (!loser.is_style_attribute || winner.sheet != loser.sheet) {
+        return false
+    }
+
+    let order = source_order(winner, loser);
+
+    selectors[loser.style].iter().all(|loser_selector| {
+        selectors[winner.style].iter().any(|winner_selector| {
+            if !winner_selector.covers(loser_selector) {
+                return false
+            }
+
+            match (winner.important, winner.is_style_attribute).cmp(&(loser.important, loser.is_style_attribute)) {
+                Ordering::Equal => match winner_selector.specificity().cmp(&loser_selector.specificity()) {
+                    Ordering::Equal => order == Some(Ordering::Greater),
+                    specificity => specificity == Ordering::Greater,
+                },
+                level => level == Ordering::Greater,
+            }
+        })
+    })
+}
+
+/// Compare where two declarations are written, `Ordering::Greater` means `first` comes after `second`.
+/// Returns `None` if they are in the same sheet but where they are in it isn't known.
+fn source_order(first: &Declaration, second: &Declaration) -> Option<Ordering> {
+    if first.sheet != second.sheet {
+        return Some(first.sheet.cmp(&second.sheet))
+    }
+
+    match (first.position, second.position) {
+        (Some(first_position), Some(second_position)) => Some(first_position.cmp(&second_position)),
+        // values of the same property in the same style are kept in the order they are written
+        _ if first.style == second.style => Some(first.attribute.cmp(&second.attribute)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::Utc;
+
+    use crate::metadata::{
+        css_metadata::{css_at_rule::CssCondition, css_attribute::CssAttribute, css_style::CssStyle, CssMetaData},
+        file_metadata::FormattedCssFile,
+        html_metadata::get_document_stylesheets,
+        migration::SCHEMA_VERSION,
+    };
+
+    fn attribute(name: &str, value: &str, important: bool) -> CssAttribute {
+        let mut attribute = CssAttribute::new();
+        attribute.name = String::from(name);
+        attribute.push_value(String::from(value), important, None);
+        attribute
+    }
+
+    fn metadata(id: u32, styles: Vec<CssStyle>) -> CssMetaData {
+        CssMetaData {
+            schema_version: SCHEMA_VERSION,
+            id,
+            file_name: format!("{}.css", id),
+            absolute_path: format!("/workspace/{}.css", id),
+            last_updated: Utc::now(),
+            content_hash: None,
+            source_stamp: None,
+            imported_sheets: None,
+            at_rules: None,
+            styles: Some(styles),
+        }
+    }
+
+    /// Get (tag, name, value, is_overwritten) for every declaration in the formatted file
+    fn overwritten(formatted_file: &FormattedCssFile) -> Vec<(String, String, String, Option<bool>)> {
+        formatted_file
+        .styles
+        .iter()
+        .flat_map(|style| style.attributes.iter().map(|attribute| (style.tag.clone(), attribute.name.clone(), attribute.value.clone(), attribute.is_overwritten)))
+        .collect()
+    }
+
+    #[test]
+    fn mark_overwritten_test() {
+        let mut print_style = CssStyle { attributes: vec![attribute("color", "black", false)], ..CssStyle::from_selector(".card") };
+        print_style.conditions = vec![CssCondition::new("media", "print")];
+
+        let base = metadata(1, vec![
+            CssStyle { attributes: vec![attribute("color", "red", false), attribute("margin", "0", true)], ..CssStyle::from_selector(".card") },
+            CssStyle { attributes: vec![attribute("color", "green", false)], ..CssStyle::from_selector("div.card") },
+        ]);
+
+        let theme = metadata(2, vec![
+            CssStyle { attributes: vec![attribute("color", "blue", false), attribute("margin", "4px", false)], ..CssStyle::from_selector(".card") },
+            CssStyle { attributes: vec![attribute("color", "white", false)], ..CssStyle::from_selector(".card:hover") },
+            print_style,
+        ]);
+
+        let formatted_file = FormattedCssFile::generate_formatted_file(&vec![base, theme]);
+
+        let expected = vec![
+            (".card", "color", "red", true),     // a later file has the same selector
+            (".card", "color", "blue", false),   // `div.card` is more specific, but doesn't apply to every `.card`, and the print style only applies when printing
+            (".card", "margin", "0", false),     // `!important` beats the later file
+            (".card", "margin", "4px", true),
+            (".card:hover", "color", "white", false),
+            ("div.card", "color", "green", false),
+            (".card", "color", "black", false),
+        ];
+
+        let expected: Vec<(String, String, String, Option<bool>)> = expected
+        .into_iter()
+        .map(|(tag, name, value, is_overwritten)| (String::from(tag), String::from(name), String::from(value), Some(is_overwritten)))
+        .collect();
+
+        assert_eq!(overwritten(&formatted_file), expected);
+    }
+
+    #[test]
+    fn mark_overwritten_inline_test() {
+        let html_string = "<head>\n\t<style>.intro { color: red; }</style>\n\t<link rel=\"stylesheet\" href=\"1.css\">\n\t<style>p.intro { padding: 0; }</style>\n</head>\n<body>\n\t<p class=\"intro\" style=\"color: blue; padding: 4px; margin: 0\">One</p>\n\t<p class=\"intro\" style=\"color: black\">Two</p>\n</body>";
+
+        let theme = metadata(1, vec![
+            CssStyle { attributes: vec![attribute("color", "green", false)], ..CssStyle::from_selector(".intro") },
+            CssStyle { attributes: vec![attribute("margin", "4px", true)], ..CssStyle::from_selector("p") },
+        ]);
+
+        let stylesheets = get_document_stylesheets(Path::new("/workspace/index.html"), html_string, vec![theme]);
+        let formatted_file = FormattedCssFile::generate_formatted_file(&stylesheets);
+        let declarations = overwritten(&formatted_file);
+
+        let is_overwritten = |tag: &str, name: &str, value: &str| declarations
+            .iter()
+            .find(|declaration| declaration.0 == tag && declaration.1 == name && declaration.2 == value)
+            .and_then(|declaration| declaration.3)
+            .unwrap();
+
+        // the first `<style>` is written before the link, so the linked stylesheet wins
+        assert!(is_overwritten(".intro", "color", "red"));
+        // a `style` attribute beats any selector, but only applies to its own element
+        assert!(!is_overwritten(".intro", "color", "green"));
+        assert!(!is_overwritten("p.intro", "padding", "0"));
+        assert!(!is_overwritten("p.intro", "padding", "4px"));
+        assert!(!is_overwritten("p.intro", "color", "blue"));
+        assert!(!is_overwritten("p.intro", "color", "black"));
+        // except against `!important`
+        assert!(is_overwritten("p.intro", "margin", "0"));
+    }
+
+    #[test]
+    fn mark_overwritten_layer_test() {
+        let mut layered_style = CssStyle { attributes: vec![attribute("color", "blue", false)], ..CssStyle::from_selector(".card") };
+        layered_style.conditions = vec![CssCondition::new("layer", "theme")];
+
+        let base = metadata(1, vec![CssStyle { attributes: vec![attribute("color", "red", false)], ..CssStyle::from_selector(".card") }]);
+        let theme = metadata(2, vec![layered_style]);
+
+        let formatted_file = FormattedCssFile::generate_formatted_file(&vec![base, theme]);
+
+        // the layered style comes later, but unlayered styles outrank every layer
+        assert_eq!(overwritten(&formatted_file), vec![
+            (String::from(".card"), String::from("color"), String::from("red"), Some(false)),
+            (String::from(".card"), String::from("color"), String::from("blue"), None),
+        ]);
+    }
+}
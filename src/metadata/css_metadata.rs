@@ -1,326 +1,1298 @@
+pub mod css_at_rule;
 pub mod css_attribute;
 pub mod css_file;
+pub mod css_selector;
 pub mod css_style;
 
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
 use chrono::{DateTime, serde::ts_seconds, Utc};
-use cssparser::{ParseError, Parser, ParserInput, Token};
+use cssparser::{Delimiter, ParseError, Parser, ParserInput, SourceLocation, SourcePosition, ToCss, Token};
 use serde::{Deserialize, Serialize};
-use crate::{file::{create_dir_and_file, recursive_file_search}, CSS_METADATA_PATH};
-use self::{css_attribute::CssAttribute, css_file::CssFile, css_style::CssStyle};
-use super::workspace_metadata::workspace_css_file::WorkspaceCssFile;
+use tower_lsp::lsp_types::{Position, Range};
+use crate::{error::{path_to_strings, BhcError}, position::{advance_position, position_to_offset, to_position, word_at_position}};
+use self::{css_at_rule::{build_at_rules, conditions_to_string, CssAtRule, CssCondition, CONDITIONAL_AT_RULES, DECLARATION_AT_RULES}, css_attribute::CssAttribute, css_file::CssFile, css_selector::{find_symbol_offsets, normalize_selector_text}, css_style::CssStyle};
+use super::{check_staleness, hash_contents, migration::SCHEMA_VERSION, read_metadata, read_source, write_metadata, html_metadata::{html_element::HtmlSymbol, html_link::{classify_href, LinkTarget}}, workspace_metadata::{workspace_css_file::WorkspaceCssFile, WorkspaceMetaData}, FileStamp, Staleness};
 
-//TODO: Consider using lazy_static crate in the future, to cache the metadata, so searching through it doesn't require iteratively looking through many files 
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct CssMetaData {
+	#[serde(default)]
+	pub schema_version: u32, // the layout the metadata was saved in, see `SCHEMA_VERSION`
+
     pub id: u32,
 	pub file_name: String,
 	pub absolute_path: String,
 
 	#[serde(with = "ts_seconds")]
 	pub last_updated: DateTime<Utc>,
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub content_hash: Option<String>, // the hash of the stylesheet when it was last parsed, see `hash_contents`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub source_stamp: Option<FileStamp>, // the stamp of the stylesheet when it was last parsed from disk, None if it was parsed from an open document
 	
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub imported_sheets: Option<Vec<CssFile>>, // imported files from .bhc/.shared/
 	
 	#[serde(skip_serializing_if = "Option::is_none")]
-	pub styles: Option<Vec<CssStyle>>,
+	pub styles: Option<Vec<CssStyle>>, // every style in the sheet, including the ones nested inside of conditional group rules
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub at_rules: Option<Vec<CssAtRule>>, // the tree of conditional group rules (@media, @supports, ...) and the styles inside of them
 }
 
 impl CssMetaData {
 	pub fn new() -> CssMetaData {
 		CssMetaData {
+			schema_version: SCHEMA_VERSION,
             id: 0,
 			file_name: String::new(),
 			absolute_path: String::new(),
 			last_updated: Utc::now(),
+			content_hash: None,
+			source_stamp: None,
 			imported_sheets: None,
 			styles: None,
+			at_rules: None,
 		}
 	}
 
-    pub fn from_json(file_path: &PathBuf) -> Result<CssMetaData, String> {
-        let contents = match fs::read_to_string(file_path) {
-            Ok(value) => value,
-            Err(error) => return Err(format!("Error occurred trying to read metadata file ({:?}): {:?}", file_path, error))
-        };
-        let metadata: CssMetaData = serde_json::from_str(&contents).unwrap();
+    /// Read the metadata saved at `file_path`.
+    /// Returns `Err(BhcError)` if the file could not be read or deserialized.
+    pub fn from_json(file_path: &Path) -> Result<CssMetaData, BhcError> {
+        read_metadata(file_path)
+    }
+
+
+    /// For the provided mutable `self`, modify all the `CssStyle`'s. The styles will be updated based on the contents of `new_styles`, if a style is not present in `new_styles`, that is indicative that is has been deleted and will be removed. 
+    pub fn update_styles(&mut self, new_styles: Vec<CssStyle>) {
+        let mut new_styles_map: HashMap<(String, String), CssStyle> = HashMap::new();
+
+        for new_style in new_styles {
+            let existing_style = new_styles_map.entry(new_style.key()).or_insert(new_style.clone());
+
+            existing_style.update_attributes(new_style.attributes.clone())
+        }
+
+        if let Some(styles) = &mut self.styles{
+            for original_style in styles {
+                if let Some(new_style) = new_styles_map.remove(&original_style.key()) {
+                    // Replace the original attribute with the new one
+                    original_style.update_or_insert(&new_style);
+                }
+            }
+        }
+
+        for (_, style) in new_styles_map.into_iter() {
+            if let Some(styles) = &mut self.styles {
+                styles.push(style);
+            } else {
+                self.styles = Some(vec![style]);
+            }
+        }
+
+        self.at_rules = match &self.styles {
+            Some(styles) => build_at_rules(styles),
+            None => None
+        };
+    }
+
+	pub fn create_metadata(metadata_path: &Path, file_path: &Path, id: &u32) -> Result<CssMetaData, BhcError> {
+		let metadata = CssMetaData::parse_file(file_path, id)?;
+
+		write_metadata(metadata_path, &metadata)?;
+
+		Ok(metadata)
+	}
+
+	/// Build the metadata of the stylesheet at `file_path` without saving it, e.g. for a stylesheet that isn't in a workspace.
+	/// Returns `Err(BhcError)` if the stylesheet could not be read.
+	pub fn parse_file(file_path: &Path, id: &u32) -> Result<CssMetaData, BhcError> {
+		let (css_string, stamp) = read_source(file_path)?;
+
+		CssMetaData::from_contents(file_path, id, &css_string, stamp)
+	}
+
+	/// The same as [`CssMetaData::parse_file`], but from `css_string` that has already been read from the stylesheet with the `stamp`.
+	/// Returns `Err(BhcError)` if `file_path` could not be turned into a string.
+	pub fn from_contents(file_path: &Path, id: &u32, css_string: &str, stamp: Option<FileStamp>) -> Result<CssMetaData, BhcError> {
+		let mut metadata = CssMetaData::new();
+
+        metadata.id = *id;
+		(metadata.absolute_path, metadata.file_name) = path_to_strings(file_path)?;
+		metadata.parse_contents(css_string);
+		metadata.source_stamp = stamp;
+
+		Ok(metadata)
+	}
+
+	/// Parse `css_string`, the contents of the stylesheet, into the styles, conditional group rules and imports of the metadata, e.g. from a document that is open but not saved.
+	/// Imported sheets keep the ids they already had, see [`get_imported_sheets`].
+	/// Anything that can't be parsed is left out, the same as a browser would, see [`find_parse_errors`] for where that happened.
+	pub fn parse_contents(&mut self, css_string: &str) {
+		let file_path = PathBuf::from(&self.absolute_path);
+
+		let mut parser_input = ParserInput::new(css_string);
+		let mut parser = Parser::new(&mut parser_input);
+
+		self.last_updated = Utc::now();
+		self.styles = parse_sheet(&mut parser);
+		self.at_rules = self.styles.as_ref().and_then(|styles| build_at_rules(styles));
+		self.imported_sheets = get_imported_sheets(css_string, &file_path, self.imported_sheets.as_deref());
+		self.content_hash = Some(hash_contents(css_string));
+		self.source_stamp = None;
+	}
+	
+	/// Give every sheet in `imported_sheets` the id it has in the `workspace_metadata`, sheets outside of the workspace are given 0.
+	/// Ids can only be known once every CSS file in the workspace has been given one, so this is done after creating the metadata.
+	/// Returns `true` if any of the ids changed.
+	pub fn update_import_ids(&mut self, workspace_metadata: &WorkspaceMetaData) -> bool {
+		let mut changed = false;
+
+		for sheet in self.imported_sheets.iter_mut().flatten() {
+			let id = workspace_metadata.get_css_file_id(&PathBuf::from(&sheet.absolute_path)).unwrap_or(0);
+
+			if sheet.id != id {
+				sheet.id = id;
+				changed = true;
+			}
+		}
+
+		changed
+	}
+
+	/// Check whether the stylesheet has changed since the metadata was made from it, see [`check_staleness`].
+	/// Returns `Err(BhcError)` if the stylesheet could not be read.
+	pub fn check_staleness(&self) -> Result<Staleness, BhcError> {
+		check_staleness(Path::new(&self.absolute_path), self.content_hash.as_deref(), self.source_stamp.as_ref(), self.last_updated)
+	}
+
+	/// Write the metadata to `metadata_path` as it is
+	pub fn save_metadata(&self, metadata_path: &Path) -> Result<(), BhcError> {
+		write_metadata(metadata_path, self)
+	}
+
+	pub fn update_metadata(&mut self, metadata_path: &Path) -> Result<WorkspaceCssFile, BhcError> {
+        let file_path = PathBuf::from(&self.absolute_path);
+        
+        let mut new_metadata = self.clone();
+
+        let (css_string, stamp) = read_source(&file_path)?;
+
+        new_metadata.parse_contents(&css_string);
+        new_metadata.source_stamp = stamp;
+
+        if let Some(styles) = new_metadata.styles.clone() {
+            self.update_styles(styles.clone());
+        } else {
+            self.styles = None
+        }
+
+        write_metadata(metadata_path, &new_metadata)?;
+
+        Ok(WorkspaceCssFile::parse(&new_metadata))
+	}
+}
+
+/// Get every local stylesheet that `css_string` imports with `@import`, resolved against `css_path`. Remote stylesheets can't be indexed, so they are left out.
+/// Each sheet keeps its id from `previous_sheets` if it was already imported, otherwise it is 0 until [`CssMetaData::update_import_ids`] is called.
+/// Returns `None` if nothing is imported.
+pub fn get_imported_sheets(css_string: &str, css_path: &PathBuf, previous_sheets: Option<&[CssFile]>) -> Option<Vec<CssFile>> {
+    let mut imported_sheets: Vec<CssFile> = Vec::new();
+
+    for (url, conditions) in parse_imports(css_string) {
+        let import_path = match classify_href(css_path, &url) {
+            LinkTarget::Local(value) => value,
+            _ => continue
+        };
+
+        let absolute_path = import_path.to_str().unwrap_or_default().to_string();
+
+        let mut css_file = CssFile::new();
+        css_file.id = previous_sheets.into_iter().flatten().find(|sheet| sheet.absolute_path == absolute_path).map(|sheet| sheet.id).unwrap_or(0);
+        css_file.file_name = import_path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+        css_file.absolute_path = absolute_path;
+        css_file.conditions = conditions;
+
+        imported_sheets.push(css_file);
+    }
+
+    if imported_sheets.is_empty() {
+        None
+    } else {
+        Some(imported_sheets)
+    }
+}
+
+/// Get the URL and conditions of every `@import` in `css_string`, such as `@import url("theme.css") layer(theme) supports(display: grid) screen;`.
+/// The conditions are given outermost first, as layer, supports and then media. Imports are only allowed before any other rules, so anything after the first rule is ignored.
+pub fn parse_imports(css_string: &str) -> Vec<(String, Vec<CssCondition>)> {
+    let mut parser_input = ParserInput::new(css_string);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let mut imports: Vec<(String, Vec<CssCondition>)> = Vec::new();
+
+    loop {
+        let name = match parser.next() {
+            Ok(Token::AtKeyword(name)) => name.to_ascii_lowercase(),
+            _ => break
+        };
+
+        // @charset and @layer statements can come before imports, anything else ends them
+        if !["import", "charset", "layer"].contains(&name.as_str()) {
+            break
+        }
+
+        let start = parser.position();
+
+        let import: Result<Option<(String, Vec<CssCondition>)>, ParseError<()>> = parser.parse_until_after(Delimiter::Semicolon | Delimiter::CurlyBracketBlock, |inner_parser| {
+            if name != "import" {
+                while inner_parser.next().is_ok() {}
+
+                return Ok(None)
+            }
+
+            Ok(parse_import_prelude(inner_parser))
+        });
+
+        // `@layer name { ... }` is a rule with a block, so imports can't come after it
+        if parser.slice_from(start).trim_end().ends_with('}') {
+            break
+        }
+
+        if let Ok(Some(value)) = import {
+            imports.push(value);
+        }
+    }
+
+    imports
+}
+
+fn parse_import_prelude(parser: &mut Parser) -> Option<(String, Vec<CssCondition>)> {
+    let url = match parser.next() {
+        Ok(Token::UnquotedUrl(value)) | Ok(Token::QuotedString(value)) => value.to_string(),
+        Ok(Token::Function(name)) if name.eq_ignore_ascii_case("url") => {
+            let url: Result<String, ParseError<()>> = parser.parse_nested_block(|inner_parser| match inner_parser.next() {
+                Ok(Token::QuotedString(value)) => Ok(value.to_string()),
+                _ => Err(inner_parser.new_custom_error(()))
+            });
+
+            url.ok()?
+        },
+        _ => return None
+    };
+
+    let mut conditions: Vec<CssCondition> = Vec::new();
+
+    if parser.try_parse(|inner_parser| inner_parser.expect_ident_matching("layer")).is_ok() {
+        conditions.push(CssCondition::new("layer", ""));
+    } else if parser.try_parse(|inner_parser| inner_parser.expect_function_matching("layer")).is_ok() {
+        let layer: Result<String, ParseError<()>> = parser.parse_nested_block(|inner_parser| Ok(nested_block_text(inner_parser)));
+        conditions.push(CssCondition::new("layer", &layer.unwrap_or_default()));
+    }
+
+    if parser.try_parse(|inner_parser| inner_parser.expect_function_matching("supports")).is_ok() {
+        let supports: Result<String, ParseError<()>> = parser.parse_nested_block(|inner_parser| Ok(nested_block_text(inner_parser)));
+        let supports = supports.unwrap_or_default();
+
+        // `supports(display: grid)` is a declaration, which has to be wrapped in brackets to be a condition
+        let supports = if supports.starts_with('(') || supports.to_ascii_lowercase().starts_with("not ") { supports } else { format!("({})", supports) };
+
+        conditions.push(CssCondition::new("supports", &supports));
+    }
+
+    let media = nested_block_text(parser);
+
+    if !media.is_empty() && !media.eq_ignore_ascii_case("all") {
+        conditions.push(CssCondition::new("media", &media));
+    }
+
+    Some((url, conditions))
+}
+
+/// Consume the rest of the `parser` and get its text with the whitespace normalized
+fn nested_block_text(parser: &mut Parser) -> String {
+    let start = parser.position();
+
+    while parser.next().is_ok() {}
+
+    normalize_selector_text(parser.slice_from(start))
+}
+
+/// Follow the `@import` rules of every stylesheet in `css_paths`, and get every stylesheet that ends up applied, in the order they cascade in.
+/// Imported sheets come before the sheet that imports them, a sheet is only included the first time it appears, and import cycles are stopped where they loop back.
+pub fn resolve_imports(css_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut resolved: Vec<PathBuf> = Vec::new();
+
+    for css_path in css_paths {
+        visit_imports(css_path, &mut Vec::new(), &mut resolved);
+    }
+
+    resolved
+}
+
+fn visit_imports(css_path: &Path, importing: &mut Vec<PathBuf>, resolved: &mut Vec<PathBuf>) {
+    if importing.iter().any(|path| path == css_path) || resolved.iter().any(|path| path == css_path) {
+        return
+    }
+
+    importing.push(css_path.to_path_buf());
+
+    // stylesheets that don't exist are still included, so they can be reported where they are used
+    if let Ok(css_string) = fs::read_to_string(css_path) {
+        for import in get_imported_sheets(&css_string, &css_path.to_path_buf(), None).into_iter().flatten() {
+            visit_imports(Path::new(&import.absolute_path), importing, resolved);
+        }
+    }
+
+    importing.pop();
+    resolved.push(css_path.to_path_buf());
+}
+
+/// Find the range of every selector in `css_string` that uses the `symbol`, each range only covers the name of the class, id or element.
+/// Returns the ranges in the order they are written.
+pub fn find_symbol_ranges(css_string: &str, symbol: &HtmlSymbol) -> Vec<Range> {
+    let mut parser_input = ParserInput::new(css_string);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let styles = match parse_sheet(&mut parser) {
+        Some(value) => value,
+        None => return Vec::new()
+    };
+
+    let mut ranges: Vec<Range> = Vec::new();
+
+    for style in styles {
+        // at-rules such as @font-face don't have a selector
+        if style.selectors.is_empty() {
+            continue
+        }
+
+        let selector_range = match style.selector_range {
+            Some(value) => value,
+            None => continue
+        };
+
+        let (start, end) = match (position_to_offset(css_string, selector_range.start), position_to_offset(css_string, selector_range.end)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => continue
+        };
+
+        let selector_text = &css_string[start..end];
+
+        for (name_start, name_end) in find_symbol_offsets(selector_text, symbol) {
+            ranges.push(Range::new(
+                advance_position(selector_range.start, &selector_text[..name_start]),
+                advance_position(selector_range.start, &selector_text[..name_end]),
+            ));
+        }
+    }
+
+    ranges.sort_by_key(|range| range.start);
+
+    ranges
+}
+
+/// Find the class, id or element name in a selector of `css_string` that is under `position`, and its range.
+/// Returns `None` if the position isn't on a selector, e.g. it is on a property or a value.
+pub fn symbol_at_css_position(css_string: &str, position: Position) -> Option<(HtmlSymbol, Range)> {
+    let (word, range) = word_at_position(css_string, position)?;
+
+    let start = position_to_offset(css_string, range.start)?;
+
+    let symbol = match css_string[..start].chars().last() {
+        Some('.') => HtmlSymbol::Class(word),
+        Some('#') => HtmlSymbol::Id(word),
+        _ => HtmlSymbol::Element(word),
+    };
+
+    // the word has to be one of the names that the selectors actually use, otherwise it could be a property, value or part of a pseudo-class
+    if find_symbol_ranges(css_string, &symbol).contains(&range) {
+        Some((symbol, range))
+    } else {
+        None
+    }
+}
+
+// TODO: Add more branching for more of the potential tokens as it currently only works with very basic css
+// Also sorts the styles in alphabetical order, the attributes of each style keep the order they are written in
+pub fn parse_sheet(parser: &mut Parser) -> Option<Vec<CssStyle>> {
+    let mut styles: Vec<CssStyle> = Vec::new();
+
+    parse_rules(parser, &Vec::new(), &mut styles, &mut Vec::new());
+
+    if styles.len() > 0 {
+        styles.sort_by_key(|style| style.key());
+
+        return Some(styles)
+    }
+
+    None
+
+}
+
+/// Find everything in `css_string`, the contents of the stylesheet at `css_path`, that a browser would skip because it isn't valid CSS, e.g. a stray `}` or a block that is never closed.
+/// Returns a `BhcError::Parse` for each of them, in the order they are written.
+pub fn find_parse_errors(css_path: &Path, css_string: &str) -> Vec<BhcError> {
+    let mut parser_input = ParserInput::new(css_string);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let mut errors: Vec<(Range, String)> = Vec::new();
+
+    parse_rules(&mut parser, &Vec::new(), &mut Vec::new(), &mut errors);
+
+    errors
+        .into_iter()
+        .map(|(range, message)| BhcError::Parse(css_path.to_path_buf(), range, message))
+        .collect()
+}
+
+/// Parse every rule in the `parser` into `styles`, each style is given the provided `conditions`. Conditional group rules are parsed recursively.
+/// Anything that isn't valid CSS is skipped, and its range is added to `errors` with why it was skipped.
+fn parse_rules(parser: &mut Parser, conditions: &[CssCondition], styles: &mut Vec<CssStyle>, errors: &mut Vec<(Range, String)>) {
+    let mut prelude_start: Option<(SourcePosition, SourceLocation)> = None;
+
+    while !parser.is_exhausted() {
+        parser.skip_whitespace();
+
+        let token_start = parser.position();
+        let token_location = parser.current_source_location();
+
+        match parser.next() {
+            Ok(token) => match token {
+                Token::AtKeyword(name) => {
+                    let name = name.to_ascii_lowercase();
+
+                    prelude_start = None;
+
+                    let (prelude, has_block) = parse_at_rule_prelude(parser);
+
+                    if !has_block {
+                        continue
+                    }
+
+                    if CONDITIONAL_AT_RULES.contains(&name.as_str()) {
+                        let mut nested_conditions = conditions.to_vec();
+                        nested_conditions.push(CssCondition::new(&name, &prelude));
+
+                        let (_, is_closed) = parse_block(parser, |inner_parser| parse_rules(inner_parser, &nested_conditions, styles, errors));
+
+                        if !is_closed {
+                            errors.push(unclosed_block_error(parser.slice_from(token_start), token_location));
+                        }
+                    } else if DECLARATION_AT_RULES.contains(&name.as_str()) {
+                        let at_rule_text = parser.slice_from(token_start).trim_end_matches('{').trim_end();
+                        let start = to_position(token_location);
+
+                        let mut style = CssStyle::new();
+                        style.tag = CssCondition::new(&name, &prelude).to_css_string();
+                        style.conditions = conditions.to_vec();
+                        let (attributes, is_closed) = parse_block(parser, |inner_parser| parse_attributes(inner_parser, errors));
+
+                        if !is_closed {
+                            errors.push(unclosed_block_error(parser.slice_from(token_start), token_location));
+                        }
+
+                        style.attributes = attributes;
+                        style.selector_range = Some(Range::new(start, advance_position(start, at_rule_text)));
+                        style.range = Some(Range::new(start, to_position(parser.current_source_location())));
+
+                        styles.push(style);
+                    }
+                },
+                Token::CurlyBracketBlock => {
+                    let (selector_start, selector_location) = prelude_start.unwrap_or((token_start, token_location));
+                    let selector_text = parser.slice(selector_start..token_start);
+                    let start = to_position(selector_location);
+
+                    let mut style = CssStyle::from_selector(selector_text);
+
+                    let (attributes, is_closed) = parse_block(parser, |inner_parser| parse_attributes(inner_parser, errors));
+
+                    if !is_closed {
+                        errors.push(unclosed_block_error(parser.slice_from(token_start), token_location));
+                    }
+
+                    style.attributes = attributes;
+                    style.conditions = conditions.to_vec();
+                    style.selector_range = Some(Range::new(start, advance_position(start, selector_text.trim_end())));
+                    style.range = Some(Range::new(start, to_position(parser.current_source_location())));
+
+                    styles.push(style);
+
+                    prelude_start = None;
+                },
+                Token::Semicolon => prelude_start = None,
+                token => {
+                    if let Some(message) = invalid_token_message(token) {
+                        errors.push((token_range(token_location, parser.slice_from(token_start)), message));
+                    }
+
+                    prelude_start.get_or_insert((token_start, token_location));
+                },
+            },
+
+            Err(_) => ()
+        }
+    }
+}
+
+/// Parse the contents of the block that was just started with `parse`.
+/// Returns what `parse` returned, and whether the block was closed with a `}` before the end of the stylesheet.
+fn parse_block<T: Default>(parser: &mut Parser, parse: impl FnOnce(&mut Parser) -> T) -> (T, bool) {
+    let mut contents_end: Option<SourcePosition> = None;
+
+    let result: Result<T, ParseError<()>> = parser.parse_nested_block(|inner_parser| {
+        let value = parse(inner_parser);
+        contents_end = Some(inner_parser.position());
+
+        Ok(value)
+    });
+
+    // the closing `}` isn't part of the contents, so a closed block ends after them
+    let is_closed = contents_end != Some(parser.position());
+
+    (result.unwrap_or_default(), is_closed)
+}
+
+/// The error for a rule whose block is never closed, covering the first token of the `rule_text`, i.e. the `{` or at-keyword at `rule_location`.
+fn unclosed_block_error(rule_text: &str, rule_location: SourceLocation) -> (Range, String) {
+    let first_token = rule_text.split(|character: char| character.is_whitespace() || character == '{').next().filter(|text| !text.is_empty()).unwrap_or("{");
+
+    (token_range(rule_location, first_token), String::from("Expected a `}` to close the block"))
+}
+
+/// Get why a browser would skip `token`, e.g. a `}` that doesn't close anything.
+/// Returns `None` if the token can be part of valid CSS.
+fn invalid_token_message(token: &Token) -> Option<String> {
+    match token {
+        Token::CloseCurlyBracket | Token::CloseParenthesis | Token::CloseSquareBracket => Some(format!("Unexpected `{}`", token.to_css_string())),
+        Token::BadString(_) => Some(String::from("Expected a closing quote before the end of the line")),
+        Token::BadUrl(_) => Some(String::from("Invalid `url()`")),
+        _ => None
+    }
+}
+
+/// Get why a browser would skip the first invalid token in `text`, see [`invalid_token_message`].
+/// Returns `None` if every token can be part of valid CSS.
+fn find_invalid_token(text: &str) -> Option<String> {
+    let mut parser_input = ParserInput::new(text);
+    let mut parser = Parser::new(&mut parser_input);
+
+    while let Ok(token) = parser.next() {
+        if let Some(message) = invalid_token_message(token) {
+            return Some(message)
+        }
+    }
+
+    None
+}
+
+fn token_range(location: SourceLocation, token_text: &str) -> Range {
+    let start = to_position(location);
+
+    Range::new(start, advance_position(start, token_text))
+}
+
+/// Read the prelude of an at-rule, stopping at the start of its block or the semicolon that ends it.
+/// Returns the normalized prelude text, and whether the at-rule has a block that can now be parsed with `parse_nested_block`.
+fn parse_at_rule_prelude(parser: &mut Parser) -> (String, bool) {
+    parser.skip_whitespace();
+
+    let prelude_start = parser.position();
+
+    loop {
+        // also finishes any block inside of the prelude, so the position is after it
+        parser.skip_whitespace();
+
+        let token_start = parser.position();
+
+        match parser.next() {
+            Ok(Token::CurlyBracketBlock) => return (normalize_selector_text(parser.slice(prelude_start..token_start)), true),
+            Ok(Token::Semicolon) | Err(_) => return (normalize_selector_text(parser.slice(prelude_start..token_start)), false),
+            Ok(_) => (),
+        }
+    }
+}
+
+/// Parse the declarations of a block, in the order they are written. Repeats of the same attribute are kept together as values of the first one.
+/// Anything that isn't valid CSS is skipped, and its range is added to `errors` with why it was skipped.
+fn parse_attributes(parser: &mut Parser, errors: &mut Vec<(Range, String)>) -> Vec<CssAttribute> {
+    let mut attributes: Vec<CssAttribute> = Vec::new();
+
+    let mut name: Option<(String, SourceLocation)> = None;
+
+    while !parser.is_exhausted() {
+        parser.skip_whitespace();
+
+        let token_start = parser.position();
+        let token_location = parser.current_source_location();
+
+        match parser.next() {
+            Ok(token) => match token {
+                Token::Ident(value) => name = Some((value.to_string(), token_location)),
+                Token::Colon => {
+                    let (name, name_location) = match name.take() {
+                        Some(value) => value,
+                        None => continue
+                    };
+
+                    let value_start = parser.position();
+
+                    if let Ok((attribute_value, important, range)) = parse_attribute_value(parser) {
+                        // custom properties can be empty
+                        if attribute_value.is_empty() && !name.starts_with("--") {
+                            errors.push((token_range(name_location, &name), format!("Expected a value for `{}`", name)));
+                        }
+
+                        // the value is trimmed, which would hide where a string ran into the end of a line
+                        if let Some(message) = find_invalid_token(parser.slice_from(value_start)) {
+                            errors.push((range, message));
+                        }
+
+                        let index = match attributes.iter().position(|attribute| attribute.name == name) {
+                            Some(value) => value,
+                            None => {
+                                let mut attribute = CssAttribute::new();
+                                attribute.name = name;
+                                attributes.push(attribute);
+
+                                attributes.len() - 1
+                            }
+                        };
+
+                        attributes[index].push_value(attribute_value, important, Some(range));
+                    }
+                }
+                token => {
+                    if let Some(message) = invalid_token_message(token) {
+                        errors.push((token_range(token_location, parser.slice_from(token_start)), message));
+                    }
+
+                    name = None
+                },
+            },
+
+            Err(_) => ()
+        }
+    }
+
+    attributes
+}
+
+/// The location of a single style inside of a stylesheet. All positions are byte indexes into the original string.
+#[derive(Debug, PartialEq, Clone)]
+struct StyleSpan {
+    key: (String, String),
+    start: usize,
+    body_start: usize,
+    body_end: usize,
+    end: usize,
+}
+
+/// The location of a conditional group rule inside of a stylesheet, from its at-keyword to its closing bracket. All positions are byte indexes into the original string.
+#[derive(Debug, PartialEq, Clone)]
+struct GroupSpan {
+    start: usize,
+    end: usize,
+}
+
+impl GroupSpan {
+    fn contains(&self, span: &StyleSpan) -> bool {
+        self.start <= span.start && span.end <= self.end
+    }
+}
+
+/// The location of a single declaration inside of a declaration block. All positions are byte indexes into the block.
+#[derive(Debug, PartialEq, Clone)]
+struct DeclarationSpan {
+    name: String,
+    value: String,
+    is_important: bool,
+    start: usize,
+    value_start: usize,
+    value_end: usize, // after the `!important`, if there is one
+    end: usize, // after the semicolon, if there is one
+    has_semicolon: bool,
+}
+
+/// Find where every declaration is inside of the declaration block `body`, in the order they are written.
+fn locate_declarations(body: &str) -> Vec<DeclarationSpan> {
+    let mut parser_input = ParserInput::new(body);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let mut declarations: Vec<DeclarationSpan> = Vec::new();
+    let mut name: Option<(String, usize)> = None;
+
+    while !parser.is_exhausted() {
+        parser.skip_whitespace();
+
+        let token_start = parser.position().byte_index();
+
+        match parser.next() {
+            Ok(Token::Ident(value)) => name = Some((value.to_string(), token_start)),
+            Ok(Token::Colon) => {
+                let (name, start) = match name.take() {
+                    Some(value) => value,
+                    None => continue
+                };
+
+                parser.skip_whitespace();
+
+                let value_start = parser.position().byte_index();
+
+                let (value, is_important, _) = match parse_attribute_value(&mut parser) {
+                    Ok(value) => value,
+                    Err(_) => continue
+                };
+
+                let end = parser.position().byte_index();
+                let has_semicolon = body[..end].ends_with(';');
+                let value_end = value_start + body[value_start..end].trim_end_matches(';').trim_end().len();
+                // without a semicolon the value runs to the end of the block, along with the whitespace before it
+                let end = if has_semicolon { end } else { value_end };
+
+                declarations.push(DeclarationSpan { name, value, is_important, start, value_start, value_end, end, has_semicolon });
+            },
+            Ok(_) => name = None,
+            Err(_) => ()
+        }
+    }
+
+    declarations
+}
+
+/// Find where every style is inside of `css_string`. The keys are found the same way as `parse_sheet` so the two line up with each other.
+fn locate_styles(css_string: &str) -> (Vec<StyleSpan>, Vec<GroupSpan>) {
+    let mut parser_input = ParserInput::new(css_string);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let mut spans: Vec<StyleSpan> = Vec::new();
+    let mut groups: Vec<GroupSpan> = Vec::new();
+
+    locate_rules(&mut parser, css_string, &Vec::new(), &mut spans, &mut groups);
+
+    (spans, groups)
+}
+
+fn locate_rules(parser: &mut Parser, css_string: &str, conditions: &[CssCondition], spans: &mut Vec<StyleSpan>, groups: &mut Vec<GroupSpan>) {
+    let mut start: Option<usize> = None;
+
+    while !parser.is_exhausted() {
+        parser.skip_whitespace();
+
+        let token_start = parser.position().byte_index();
+
+        let tag = match parser.next() {
+            Ok(Token::AtKeyword(name)) => {
+                let name = name.to_ascii_lowercase();
+
+                start = None;
+
+                let (prelude, has_block) = parse_at_rule_prelude(parser);
+
+                if !has_block {
+                    continue
+                }
+
+                if CONDITIONAL_AT_RULES.contains(&name.as_str()) {
+                    let mut nested_conditions = conditions.to_vec();
+                    nested_conditions.push(CssCondition::new(&name, &prelude));
+
+                    let _: Result<(), ParseError<()>> = parser.parse_nested_block(|inner_parser| {
+                        locate_rules(inner_parser, css_string, &nested_conditions, spans, groups);
+                        Ok(())
+                    });
+
+                    groups.push(GroupSpan { start: token_start, end: parser.position().byte_index() });
+
+                    continue
+                } else if DECLARATION_AT_RULES.contains(&name.as_str()) {
+                    CssCondition::new(&name, &prelude).to_css_string()
+                } else {
+                    continue
+                }
+            },
+            Ok(Token::CurlyBracketBlock) => {
+                let selector_start = start.unwrap_or(token_start);
+
+                normalize_selector_text(&css_string[selector_start..token_start])
+            },
+            Ok(Token::Semicolon) => {
+                start = None;
+                continue
+            },
+            Ok(_) => {
+                start.get_or_insert(token_start);
+                continue
+            },
+            Err(_) => continue
+        };
+
+        let body_start = parser.position().byte_index();
+
+        let _: Result<(), ParseError<()>> = parser.parse_nested_block(|_| Ok(()));
+
+        let end = parser.position().byte_index();
+        let body_end = if css_string[..end].ends_with('}') { end - 1 } else { end };
+
+        spans.push(StyleSpan {
+            key: (conditions_to_string(conditions), tag),
+            start: start.take().unwrap_or(token_start),
+            body_start,
+            body_end,
+            end,
+        });
+    }
+}
+
+/// The values of each attribute of a style, and whether each value is `!important`
+type MergedAttributes = HashMap<String, Vec<(String, bool)>>;
+
+/// Combine any styles that share the same key, keeping the order of the values for each attribute.
+fn merge_styles(styles: &[CssStyle]) -> HashMap<(String, String), MergedAttributes> {
+    let mut style_map: HashMap<(String, String), MergedAttributes> = HashMap::new();
+
+    for style in styles {
+        let attributes = style_map.entry(style.key()).or_default();
+
+        for attribute in &style.attributes {
+            let values = attribute.values.iter().enumerate().map(|(index, value)| (value.clone(), attribute.is_important(index)));
+
+            attributes.entry(attribute.name.clone()).or_default().extend(values);
+        }
+    }
+
+    style_map
+}
+
+/// Write the attributes of `style` as the inside of a declaration block, using `indentation` in front of each declaration and `closing_indentation` in front of the closing bracket.
+fn format_declarations(style: &CssStyle, indentation: &str, closing_indentation: &str) -> String {
+    let mut declarations = String::from("\n");
+
+    for attribute in &style.attributes {
+        for (index, value) in attribute.values.iter().enumerate() {
+            declarations.push_str(indentation);
+            declarations.push_str(&attribute.name);
+            declarations.push_str(": ");
+            declarations.push_str(value);
+            if attribute.is_important(index) {
+                declarations.push_str(" !important");
+            }
+            declarations.push_str(";\n");
+        }
+    }
+
+    declarations.push_str(closing_indentation);
+
+    declarations
+}
+
+/// Write a whole style, wrapped in each of its conditions, as it would be added to the end of a stylesheet.
+fn format_style(style: &CssStyle) -> String {
+    let mut style_string = String::new();
+
+    for (depth, condition) in style.conditions.iter().enumerate() {
+        style_string.push_str(&"\t".repeat(depth));
+        style_string.push_str(&condition.to_css_string());
+        style_string.push_str(" {\n");
+    }
+
+    let depth = style.conditions.len();
+
+    style_string.push_str(&"\t".repeat(depth));
+    style_string.push_str(&style.tag);
+    style_string.push_str(" {");
+    style_string.push_str(&format_declarations(style, &"\t".repeat(depth + 1), &"\t".repeat(depth)));
+    style_string.push_str("}\n");
+
+    for depth in (0..style.conditions.len()).rev() {
+        style_string.push_str(&"\t".repeat(depth));
+        style_string.push_str("}\n");
+    }
+
+    style_string
+}
+
+/// Get the indentation used by the first declaration inside of a declaration block, defaults to a tab.
+fn get_indentation(body: &str) -> String {
+    body
+    .lines()
+    .skip(1)
+    .find(|line| !line.trim().is_empty())
+    .map(|line| line.chars().take_while(|character| character.is_whitespace()).collect())
+    .unwrap_or(String::from("\t"))
+}
+
+/// Get the indentation in front of the closing bracket of a declaration block.
+fn get_closing_indentation(body: &str) -> String {
+    match body.rsplit_once('\n') {
+        Some((_, last_line)) if last_line.trim().is_empty() => last_line.to_string(),
+        _ => String::new()
+    }
+}
+
+/// Get the range to remove when deleting the rule from `start` to `end`. Whole lines are removed when the rule is on lines of its own,
+/// along with a blank line after it if there is also one before it, so the spacing between the remaining rules stays the same.
+fn get_removal_range(css_string: &str, start: usize, end: usize) -> (usize, usize) {
+    let mut start = start;
+    let mut end = end;
+
+    let before = &css_string[..start];
+    let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+
+    if before[line_start..].trim().is_empty() {
+        start = line_start;
+    }
+
+    let after = &css_string[end..];
+
+    if let Some(line_end) = after.find('\n') {
+        if after[..line_end].trim().is_empty() {
+            end += line_end + 1;
+
+            let after = &css_string[end..];
+
+            if let Some(blank_line_end) = after.find('\n') {
+                if after[..blank_line_end].trim().is_empty() && (start == 0 || css_string[..start].ends_with("\n\n")) {
+                    end += blank_line_end + 1;
+                }
+            }
+        }
+    }
+
+    // nothing but whitespace is left after it, so that goes too
+    if css_string[end..].trim().is_empty() {
+        end = css_string.len();
+    }
+
+    // the last style inside of a block shouldn't leave a blank line before the closing bracket
+    if css_string[..start].ends_with("\n\n") && css_string[end..].trim_start().starts_with('}') {
+        start -= 1;
+    }
+
+    (start, end)
+}
+
+/// Get the range to remove when deleting a declaration from `start` to `end`. The whole line is removed when the declaration is on a line of its own.
+fn get_declaration_removal_range(css_string: &str, start: usize, end: usize) -> (usize, usize) {
+    let mut start = start;
+    let mut end = end;
+
+    let line_start = css_string[..start].rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let is_line_start = css_string[line_start..start].trim().is_empty();
+
+    let after = &css_string[end..];
+    let line_end = after.find('\n').unwrap_or(after.len());
+
+    if is_line_start && after[..line_end].trim().is_empty() && line_end < after.len() {
+        start = line_start;
+        end += line_end + 1;
+    } else {
+        // the spaces before the next declaration on the same line
+        end += after.len() - after.trim_start_matches([' ', '\t']).len();
+    }
+
+    (start, end)
+}
+
+fn format_value(value: &str, is_important: bool) -> String {
+    if is_important {
+        format!("{} !important", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Get the replacements that change the declarations of the style at `span` into those of `new_style`, leaving every declaration that hasn't changed, and any comments, exactly as they were.
+/// Declarations are matched by their name and how many times that name has come before, counted in `seen` across every style with the same key.
+/// New declarations are added to the end of the block when it is the `is_last` style with its key.
+fn rewrite_declarations(css_string: &str, span: &StyleSpan, new_style: &CssStyle, seen: &mut HashMap<String, usize>, is_last: bool) -> Vec<(usize, usize, String)> {
+    let body = &css_string[span.body_start..span.body_end];
+    let declarations = locate_declarations(body);
 
-        Ok(metadata)
-    }
+    let mut replacements: Vec<(usize, usize, String)> = Vec::new();
 
+    if declarations.is_empty() {
+        if is_last && !new_style.attributes.is_empty() {
+            replacements.push((span.body_start, span.body_end, format_declarations(new_style, &get_indentation(body), &get_closing_indentation(body))));
+        }
 
-    /// For the provided mutable `self`, modify all the `CssStyle`'s. The styles will be updated based on the contents of `new_styles`, if a style is not present in `new_styles`, that is indicative that is has been deleted and will be removed. 
-    pub fn update_styles(&mut self, new_styles: Vec<CssStyle>) {
-        let mut new_styles_map: HashMap<String, CssStyle> = HashMap::new();
+        return replacements
+    }
 
-        for new_style in new_styles {
-            let existing_style = new_styles_map.entry(new_style.tag.clone()).or_insert(CssStyle {
-                tag: new_style.tag.clone(),
-                attributes: new_style.attributes.clone(),
-            });
+    let mut last_kept: Option<&DeclarationSpan> = None;
 
-            existing_style.update_attributes(new_style.attributes.clone())
-        }
+    for declaration in &declarations {
+        let occurrence = seen.entry(declaration.name.clone()).or_insert(0);
+        let index = *occurrence;
+        *occurrence += 1;
 
-        if let Some(styles) = &mut self.styles{
-            for original_style in styles {
-                if let Some(new_style) = new_styles_map.remove(&original_style.tag) {
-                    // Replace the original attribute with the new one
-                    original_style.update_or_insert(&new_style);
-                }
-            }
-        }
+        let new_value = new_style
+        .attributes
+        .iter()
+        .find(|attribute| attribute.name == declaration.name)
+        .and_then(|attribute| attribute.values.get(index).map(|value| (value, attribute.is_important(index))));
 
-        for (_, style) in new_styles_map.into_iter() {
-            if let Some(styles) = &mut self.styles {
-                styles.push(style);
-            } else {
-                self.styles = Some(vec![style]);
+        match new_value {
+            Some((value, is_important)) if value == &declaration.value && is_important == declaration.is_important => last_kept = Some(declaration),
+            Some((value, is_important)) => {
+                replacements.push((span.body_start + declaration.value_start, span.body_start + declaration.value_end, format_value(value, is_important)));
+                last_kept = Some(declaration);
+            },
+            None => {
+                let (start, end) = get_declaration_removal_range(css_string, span.body_start + declaration.start, span.body_start + declaration.end);
+                replacements.push((start, end, String::new()));
             }
         }
     }
 
-	pub fn create_metadata(metadata_path: &PathBuf, file_path: &PathBuf, id: &u32) -> Result<CssMetaData, String> {
-
-		match create_dir_and_file(&metadata_path) {
-			Ok(_) => (),
-			Err(error) => return Err(error)
-		};
-
-		let css_string = match fs::read_to_string(&file_path) {
-			Ok(value) => value,
-			Err(error) => return Err(format!("Error trying to read css file: ({:?}) {:?}", &file_path, error))
-		};
+    if !is_last {
+        return replacements
+    }
 
-		let mut metadata = CssMetaData::new();
+    let mut added: Vec<String> = Vec::new();
 
-        metadata.id = *id;
-		metadata.file_name = file_path.file_name().unwrap().to_str().unwrap().to_string();
-		metadata.absolute_path = file_path.to_str().unwrap().to_string();
-		metadata.last_updated = Utc::now();
+    for attribute in &new_style.attributes {
+        let count = seen.get(&attribute.name).copied().unwrap_or(0);
 
-		let mut parser_input = ParserInput::new(&css_string);
-		let mut parser = Parser::new(&mut parser_input);
+        for (index, value) in attribute.values.iter().enumerate().skip(count) {
+            added.push(format!("{}: {};", attribute.name, format_value(value, attribute.is_important(index))));
+        }
+    }
 
-		metadata.styles = parse_sheet(&mut parser).unwrap();
+    if added.is_empty() {
+        return replacements
+    }
 
-		match fs::write(&metadata_path, serde_json::to_string_pretty(&metadata).unwrap()) {
-			Ok(_) => return Ok(metadata),
-			Err(error) => return Err(format!("Error writing metadata to file: ({:?}) {:?}", &metadata_path, error))
-		};
-	}
-	
-	pub fn update_metadata(&mut self, metadata_path: &PathBuf) -> Result<WorkspaceCssFile, String> {
-        let file_path = PathBuf::from(&self.absolute_path);
-        
-        let mut new_metadata = self.clone();
+    // the last declaration can leave out its semicolon, which the new ones need after whichever declaration they follow. One that is removed doesn't matter
+    let missing_semicolon = last_kept.filter(|declaration| !declaration.has_semicolon);
 
-        new_metadata.last_updated = Utc::now();
+    match (body.rfind('\n'), last_kept) {
+        // added on their own lines, before the line the closing bracket is on
+        (Some(index), _) if body[index..].trim().is_empty() => {
+            let indentation = get_indentation(body);
+            let text: String = added.iter().map(|declaration| format!("{}{}\n", indentation, declaration)).collect();
 
-        let css_string = match fs::read_to_string(&file_path) {
-			Ok(value) => value,
-			Err(error) => return Err(format!("Error trying to read css file: ({:?}) {:?}", &file_path, error))
-		};
+            if let Some(declaration) = missing_semicolon {
+                replacements.push((span.body_start + declaration.value_end, span.body_start + declaration.value_end, String::from(";")));
+            }
 
-        let mut parser_input = ParserInput::new(&css_string);
-		let mut parser = Parser::new(&mut parser_input);
+            replacements.push((span.body_start + index + 1, span.body_start + index + 1, text));
+        },
+        (_, Some(declaration)) => {
+            let text: String = added.iter().map(|declaration| format!(" {}", declaration)).collect();
 
-        new_metadata.styles = parse_sheet(&mut parser).unwrap();
+            match missing_semicolon {
+                Some(_) => replacements.push((span.body_start + declaration.value_end, span.body_start + declaration.value_end, format!(";{}", text))),
+                None => replacements.push((span.body_start + declaration.end, span.body_start + declaration.end, text))
+            }
+        },
+        // every declaration was removed, so the new ones go where the first of them was
+        (_, None) => {
+            let position = span.body_start + declarations[0].start;
 
-        if let Some(styles) = new_metadata.styles.clone() {
-            self.update_styles(styles.clone());
-        } else {
-            self.styles = None
+            replacements.push((position, position, format!("{} ", added.join(" "))));
         }
+    }
 
-        match fs::write(&metadata_path, serde_json::to_string_pretty(&new_metadata).unwrap()) {
-			Ok(_) => (),
-			Err(error) => return Err(format!("Error writing metadata to file: ({:?}) {:?}", &metadata_path, error))
-		};
+    // the new declarations can go before the removal of a declaration after them
+    replacements.sort_by_key(|(start, end, _)| (*start, *end));
 
-        Ok(WorkspaceCssFile::parse(&new_metadata))
-	}
+    replacements
 }
 
-pub fn get_all_metadata(workspace_path: &PathBuf) -> Result<Vec<CssMetaData>, String> {
-    let css_metadata_path = workspace_path.join(CSS_METADATA_PATH);
-    let css_metadata_files = recursive_file_search(&css_metadata_path);
+/// Combine every style in `styles` with the provided `key` into a single style.
+fn combine_styles(key: &(String, String), styles: &[CssStyle]) -> CssStyle {
+    let mut combined_style: Option<CssStyle> = None;
 
-    let metadata = css_metadata_files
-    .iter()
-    .map(|file_path| {
-        CssMetaData::from_json(file_path)
-    })
-    .collect();
-
-    match metadata {
-        Ok(value) => Ok(value),
-        Err(error) => Err(error)
+    for style in styles.iter().filter(|style| &style.key() == key) {
+        match &mut combined_style {
+            Some(combined) => combined.attributes.extend(style.attributes.clone()),
+            None => combined_style = Some(style.clone()),
+        }
     }
+
+    combined_style.unwrap_or(CssStyle::new())
 }
 
-/// Get all the metadatas that are contained in the file_paths `Vec<PathBuf>`, this is done so we can create a css string from the contents of the returned metadatas
-/// Returns `Ok(Some(Vec<CssMetaData>))` if there are any 
-/// Returns `Ok(None)` if there's no metadata to return (Shouldn't happen unless the file_paths are external(?))
-/// Returns `Err(String)` if an error occurs trying to deserialize the metadata files at the start
-pub fn get_metadata_files(file_paths: &Vec<PathBuf>, workspace_path: &PathBuf) -> Result<Option<Vec<CssMetaData>>, String> {
-    let metadata_files = match get_all_metadata(workspace_path) {
-        Ok(value) => value,
-        Err(error) => return Err(error)
-    };
+/// Rewrite `css_string` so that it contains exactly the provided `styles`, this is used to write the changes made in a virtual file back to the original stylesheet.
+/// Styles that have not changed are left exactly as they were, styles that have changed only have the declarations that changed rewritten, so the order of the rest and any comments are kept,
+/// styles that no longer exist are removed and any new styles are added to the end of the sheet, inside of their conditions.
+pub fn write_styles(css_string: &str, styles: &[CssStyle]) -> String {
+    let mut parser_input = ParserInput::new(css_string);
+    let mut parser = Parser::new(&mut parser_input);
 
-    let metadata_collection: Vec<CssMetaData> = 
-    metadata_files
-    .into_iter()
-    .filter_map(|css_metadata| {
-        let path_buf = PathBuf::from(&css_metadata.absolute_path);
+    let original_styles = merge_styles(&parse_sheet(&mut parser).unwrap_or_default());
+    let new_styles = merge_styles(styles);
 
-        if file_paths.contains(&path_buf) {
-            Some(css_metadata)
-        } else {
-            None
-        }
-    })
-    .collect();
+    // (start, end, replacement)
+    let mut replacements: Vec<(usize, usize, String)> = Vec::new();
+    let mut seen: HashMap<(String, String), HashMap<String, usize>> = HashMap::new();
 
-    if !metadata_collection.is_empty() {
-        Ok(Some(metadata_collection))
-    } else {
-        Ok(None)
-    }
-}
+    let (spans, groups) = locate_styles(css_string);
 
+    // a conditional group rule that would be left without any rules is removed along with them
+    let empty_groups: Vec<&GroupSpan> = groups
+        .iter()
+        .filter(|group| {
+            let mut inner_spans = spans.iter().filter(|span| group.contains(span)).peekable();
 
-// TODO: Add more branching for more of the potential tokens as it currently only works with very basic css
-// Also sorts the styles and attributes in alphabetical order
-pub fn parse_sheet<'a>(parser: &mut Parser) -> Result<Option<Vec<CssStyle>>, ParseError<'a, String>> {
-    let mut styles: Vec<CssStyle> = Vec::new();
-    let mut style = CssStyle::new();
+            inner_spans.peek().is_some() && inner_spans.all(|span| !new_styles.contains_key(&span.key))
+        })
+        .collect();
 
-    while !parser.is_exhausted() {
-        match parser.next() {
-            Ok(token) => match token {
-                Token::Ident(value) => {
-                    style = CssStyle::new();
-                    style.tag = value.to_string()
-                },
-                Token::CurlyBracketBlock => {
-                    let attributes = parser.parse_nested_block(|inner_parser| {
-                        parse_attributes(inner_parser)
-                    }).unwrap();
+    let mut removed_groups: Vec<usize> = Vec::new();
 
-                    style.attributes = attributes;
+    for (index, span) in spans.iter().enumerate() {
+        if original_styles.get(&span.key) == new_styles.get(&span.key) {
+            continue
+        }
 
-                    styles.push(style.clone());
+        if new_styles.contains_key(&span.key) {
+            // a style can be written more than once, so its declarations are counted across all of them and new ones go into the last
+            let is_last = !spans[index + 1..].iter().any(|other| other.key == span.key);
+            let new_style = combine_styles(&span.key, styles);
+
+            replacements.extend(rewrite_declarations(css_string, span, &new_style, seen.entry(span.key.clone()).or_default(), is_last));
+        } else {
+            let (start, end) = match empty_groups.iter().filter(|group| group.contains(span)).min_by_key(|group| group.start) {
+                Some(group) if removed_groups.contains(&group.start) => continue,
+                Some(group) => {
+                    removed_groups.push(group.start);
+                    get_removal_range(css_string, group.start, group.end)
                 },
-                _ => (),
-            },
+                None => get_removal_range(css_string, span.start, span.end)
+            };
 
-            Err(_) => ()
+            if end == css_string.len() {
+                replacements.push((css_string[..start].trim_end().len(), end, String::from("\n")));
+            } else {
+                replacements.push((start, end, String::new()));
+            }
         }
     }
 
-    if styles.len() > 0 {
-        styles.sort_by_key(|style| style.tag.clone());
+    let mut new_css_string = String::new();
+    let mut position = 0;
+
+    for (start, end, replacement) in replacements {
+        // removing the trailing whitespace of the last style can reach back into the previous replacement
+        let start = start.max(position);
 
-        return Ok(Some(styles))
+        new_css_string.push_str(&css_string[position..start]);
+        new_css_string.push_str(&replacement);
+        position = end;
     }
 
-    Ok(None)
+    new_css_string.push_str(&css_string[position..]);
 
-}
+    let mut added_keys: Vec<(String, String)> = Vec::new();
 
-fn parse_attributes<'a>(parser: &mut Parser) -> Result<Vec<CssAttribute>, ParseError<'a, String>> {
-    let mut attribute_map: HashMap<String, CssAttribute> = HashMap::new();
-    
-    let mut attribute = CssAttribute::new();
-    
-    while !parser.is_exhausted() {
-        match parser.next() {
-            Ok(token) => match token {
-                Token::Ident(value) => {
-                    let new_value = value.to_string();
-
-                    match attribute_map.get_key_value(&new_value) {
-                        Some((_, val)) => attribute = val.clone(),
-                        None => {
-                            attribute = CssAttribute::new();
-                            attribute.name = new_value.clone();
-                        },
-                    }                    
-                },
-                Token::Colon => {
-                    let attribute_value = parse_attribute_value(parser).unwrap();
+    for style in styles {
+        let key = style.key();
 
-                    attribute.values.push(attribute_value);
+        if original_styles.contains_key(&key) || added_keys.contains(&key) {
+            continue
+        }
 
-                    attribute_map.insert(attribute.clone().name, attribute.clone());
-                }
-                _ => (),
-            },
+        if !new_css_string.is_empty() {
+            if !new_css_string.ends_with('\n') {
+                new_css_string.push('\n');
+            }
 
-            Err(_) => ()
+            new_css_string.push('\n');
         }
-    }
 
-    let mut attributes: Vec<CssAttribute> = attribute_map.values().cloned().collect();
-    attributes.sort_by_key(|attr| attr.name.clone());
+        new_css_string.push_str(&format_style(&combine_styles(&key, styles)));
+
+        added_keys.push(key);
+    }
 
-    Ok(attributes)
+    new_css_string
 }
 
-fn parse_attribute_value<'a>(parser: &mut Parser) -> Result<String, ParseError<'a, String>> {
-    let mut attribute_value = String::new();
+/// Read the value of a declaration up to the semicolon that ends it.
+/// Returns the exact source text of the value, whether it was marked as `!important` (which is not included in the text), and where the text is in the file.
+fn parse_attribute_value<'a>(parser: &mut Parser) -> Result<(String, bool, Range), ParseError<'a, String>> {
+    parser.skip_whitespace();
+
+    let value_start = parser.position();
+    let value_location = to_position(parser.current_source_location());
+    let mut important_start: Option<SourcePosition> = None;
+    let mut is_important = false;
+
+    let value_end = loop {
+        // also finishes any block or function inside of the value, so the position is after it
+        parser.skip_whitespace();
+
+        let token_start = parser.position();
 
-    while !parser.is_exhausted() {
         match parser.next() {
-            Ok(token) => match token {
-                Token::Ident(value) => {
-                    attribute_value = value.to_string();
-                },
-                Token::Dimension { has_sign: _, value, int_value: _, unit } => {
-                    attribute_value.push_str(&value.to_string());
-                    attribute_value.push_str(&unit);
-                }
-                Token::Semicolon => {
-                    break
-                }
-                _ => (),
+            Ok(Token::Semicolon) | Err(_) => break token_start,
+            Ok(Token::Delim('!')) => {
+                important_start = Some(token_start);
+                is_important = false;
+            },
+            Ok(Token::Ident(value)) if important_start.is_some() && value.eq_ignore_ascii_case("important") => is_important = true,
+            Ok(_) => {
+                important_start = None;
+                is_important = false;
             },
-
-            Err(_) => ()
         }
-    }
-    Ok(attribute_value)
+    };
+
+    let value_end = match important_start {
+        Some(position) if is_important => position,
+        _ => value_end
+    };
+
+    let value = parser.slice(value_start..value_end).trim().to_string();
+    let range = Range::new(value_location, advance_position(value_location, &value));
+
+    Ok((value, is_important, range))
 }
 
 
 /* #region Unit Tests */
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use chrono::DateTime;
     use cssparser::{Parser, ParserInput};
+    use tower_lsp::lsp_types::{Position, Range};
+
+    use crate::metadata::html_metadata::html_element::HtmlSymbol;
+
+    use super::{css_at_rule::{build_at_rules, CssCondition}, css_selector::parse_selector_list, find_symbol_ranges, parse_imports, parse_sheet, resolve_imports, symbol_at_css_position, write_styles, CssAttribute, CssFile, CssMetaData, CssStyle, SCHEMA_VERSION};
 
-    use super::{parse_sheet, CssAttribute, CssFile, CssMetaData, CssStyle};
+    fn range(start_line: u32, start_character: u32, end_line: u32, end_character: u32) -> Range {
+        Range::new(Position::new(start_line, start_character), Position::new(end_line, end_character))
+    }
 
     #[test]
     fn test_serialize_deserialize() {
         let attribute1 = CssAttribute{
             name: String::from("background-color"),
+            important: Vec::new(),
+            ranges: vec![Range::new(Position::new(1, 22), Position::new(1, 25))],
             values: vec![String::from("red")],
             source: None,
             is_overwritten: None, 
         };
         let attribute2 = CssAttribute{
             name: String::from("background-color"), 
+            important: Vec::new(),
+            ranges: Vec::new(),
             values: vec![String::from("green")],
             source: Some(2), 
             is_overwritten: Some(false),
         };
         let attribute3 = CssAttribute{
             name: String::from("font-size"),
+            important: Vec::new(),
+            ranges: Vec::new(),
             values: vec![String::from("11pt")],
             source: Some(2),
             is_overwritten: Some(true),
@@ -332,10 +1304,20 @@ mod tests {
         let style1 = CssStyle{
             tag: String::from("h1"),
             attributes: attributes1,
+            selectors: parse_selector_list("h1"),
+            conditions: Vec::new(),
+            range: Some(Range::new(Position::new(0, 0), Position::new(2, 1))),
+            selector_range: Some(Range::new(Position::new(0, 0), Position::new(0, 2))),
+            is_style_attribute: false,
         };
         let style2 = CssStyle{
-            tag: String::from("p"), 
+            tag: String::from(".card > p"), 
             attributes: attributes2,
+            selectors: parse_selector_list(".card > p"),
+            conditions: vec![CssCondition::new("media", "(max-width: 600px)")],
+            range: None,
+            selector_range: None,
+            is_style_attribute: false,
         };
 
         let styles: Vec<CssStyle> = vec![style1, style2];
@@ -344,15 +1326,21 @@ mod tests {
             id: 2, 
             file_name: String::from("base.css"), 
             absolute_path: String::from("D:/programming/web-dev/xd/.bhc/.shared/base.css"),
+            media: None,
+            conditions: Vec::new(),
         };
 
         let files = vec![file1];
 
         let metadata = CssMetaData{
+            schema_version: SCHEMA_VERSION,
             id: 0,
             file_name: String::from("test.css"), 
             absolute_path: String::from("D:/programming/web-dev/xd/css/test.css"), 
             last_updated: DateTime::from_timestamp(1710090300, 0).unwrap(), 
+            content_hash: None,
+            source_stamp: None,
+            at_rules: build_at_rules(&styles),
             styles: Some(styles), 
             imported_sheets: Some(files),
         };
@@ -387,30 +1375,36 @@ p {
 
         let mut metadata = CssMetaData::new();
 
-        metadata.styles = parse_sheet(&mut parser).unwrap();
+        metadata.styles = parse_sheet(&mut parser);
 
         let mut expected = CssMetaData::new();
-        let mut style1 = CssStyle::new();
-        style1.tag = String::from("h1");
+        let mut style1 = CssStyle::from_selector("h1");
         let mut attribute1 = CssAttribute::new();
         attribute1.name = String::from("background-color");
         attribute1.values = vec![String::from("red"), String::from("green")];
+        attribute1.ranges = vec![range(2, 22, 2, 25), range(3, 22, 3, 27)];
         let mut attribute2 = CssAttribute::new();
         attribute2.name = String::from("font-size");
         attribute2.values = vec![String::from("100pt")];
+        attribute2.ranges = vec![range(4, 15, 4, 20)];
         let mut attribute3 = CssAttribute::new();
         attribute3.name = String::from("xd");
         attribute3.values = vec![String::from("100px")];
+        attribute3.ranges = vec![range(5, 8, 5, 13)];
 
         style1.attributes = vec![attribute1, attribute2, attribute3];
+        style1.range = Some(range(1, 0, 6, 1));
+        style1.selector_range = Some(range(1, 0, 1, 2));
 
-        let mut style2 = CssStyle::new();
-        style2.tag = String::from("p");
+        let mut style2 = CssStyle::from_selector("p");
         let mut attribute4 = CssAttribute::new();
         attribute4.name = String::from("font-size");
         attribute4.values = vec![String::from("14pt")];
+        attribute4.ranges = vec![range(9, 15, 9, 19)];
 
         style2.attributes = vec![attribute4];
+        style2.range = Some(range(8, 0, 10, 1));
+        style2.selector_range = Some(range(8, 0, 8, 1));
 
         expected.styles = Some(vec![style1, style2]);
 
@@ -420,7 +1414,380 @@ p {
 
     }
 
-    
+    #[test]
+    fn test_parse_sheet_values() {
+        let css_string = r#"
+.card {
+    border: 1px solid red;
+    color: #fff !important;
+    background: rgb(0, 0, 0) url("images/bg.png");
+    width: calc(100% - 2px);
+    --spacing: 1.5;
+    margin: var(--spacing)
+}"#;
+
+        let mut parserinput = ParserInput::new(&css_string);
+        let mut parser = Parser::new(&mut parserinput);
+
+        let styles = parse_sheet(&mut parser).unwrap();
+
+        let values: Vec<(&str, &str, bool)> = styles[0]
+        .attributes
+        .iter()
+        .map(|attribute| (attribute.name.as_str(), attribute.values[0].as_str(), attribute.is_important(0)))
+        .collect();
+
+        // the attributes keep the order they are written in
+        assert_eq!(values, vec![
+            ("border", "1px solid red", false),
+            ("color", "#fff", true),
+            ("background", "rgb(0, 0, 0) url(\"images/bg.png\")", false),
+            ("width", "calc(100% - 2px)", false),
+            ("--spacing", "1.5", false),
+            ("margin", "var(--spacing)", false),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_sheet_positions() {
+        let css_string = "@media print {\n  .card,\n  #nav a {\n    color: red !important;\n  }\n}\n@font-face { font-family: Roboto; }";
+
+        let mut parserinput = ParserInput::new(css_string);
+        let mut parser = Parser::new(&mut parserinput);
+
+        let styles = parse_sheet(&mut parser).unwrap();
+
+        // @font-face, @media print .card, #nav a
+        assert_eq!(styles[0].selector_range, Some(range(6, 0, 6, 10)));
+        assert_eq!(styles[0].range, Some(range(6, 0, 6, 35)));
+        assert_eq!(styles[0].attributes[0].ranges, vec![range(6, 26, 6, 32)]);
+
+        assert_eq!(styles[1].selector_range, Some(range(1, 2, 2, 8)));
+        assert_eq!(styles[1].range, Some(range(1, 2, 4, 3)));
+        assert_eq!(styles[1].attributes[0].ranges, vec![range(3, 11, 3, 14)]);
+    }
+
+    #[test]
+    fn test_find_symbol_ranges() {
+        let css_string = "@font-face { font-family: card; }\n.card,\n.card-title > .card { color: red; }\n@media print {\n  #card.card { display: none; }\n}";
+
+        let ranges = find_symbol_ranges(css_string, &HtmlSymbol::Class(String::from("card")));
+
+        assert_eq!(ranges, vec![range(1, 1, 1, 5), range(2, 15, 2, 19), range(4, 8, 4, 12)]);
+        assert_eq!(find_symbol_ranges(css_string, &HtmlSymbol::Id(String::from("card"))), vec![range(4, 3, 4, 7)]);
+    }
+
+    #[test]
+    fn test_symbol_at_css_position() {
+        let css_string = ".card > h1:hover,\n#nav a {\n    color: red;\n}";
+
+        assert_eq!(symbol_at_css_position(css_string, Position::new(0, 3)), Some((HtmlSymbol::Class(String::from("card")), range(0, 1, 0, 5))));
+        assert_eq!(symbol_at_css_position(css_string, Position::new(0, 9)), Some((HtmlSymbol::Element(String::from("h1")), range(0, 8, 0, 10))));
+        assert_eq!(symbol_at_css_position(css_string, Position::new(1, 2)), Some((HtmlSymbol::Id(String::from("nav")), range(1, 1, 1, 4))));
+        assert_eq!(symbol_at_css_position(css_string, Position::new(0, 12)), None);
+        assert_eq!(symbol_at_css_position(css_string, Position::new(2, 6)), None);
+    }
+
+    #[test]
+    fn test_parse_sheet_selectors() {
+        let css_string = r#"
+.card > h1:hover,
+#nav a {
+    color: red;
+}
+
+[type=text] {
+    border-width: 1px;
+}"#;
+
+        let mut parserinput = ParserInput::new(&css_string);
+        let mut parser = Parser::new(&mut parserinput);
+
+        let styles = parse_sheet(&mut parser).unwrap();
+
+        let tags: Vec<&str> = styles.iter().map(|style| style.tag.as_str()).collect();
+
+        assert_eq!(tags, vec![".card > h1:hover, #nav a", "[type=text]"]);
+        assert_eq!(styles[0].selectors.len(), 2);
+        assert_eq!(styles[0].selectors[1].text, "#nav a");
+        assert_eq!(styles[1].selectors[0].compounds[0].attributes[0].name, "type");
+    }
+
+    #[test]
+    fn test_parse_sheet_at_rules() {
+        let css_string = r#"
+@charset "utf-8";
+
+h1 {
+    color: red;
+}
+
+@media (max-width: 600px) {
+    h1 {
+        color: blue;
+    }
+
+    @supports (display: grid) {
+        .grid {
+            display: grid;
+        }
+    }
+}
+
+@font-face {
+    font-family: Roboto;
+}
+
+@keyframes spin {
+    from { rotate: 0deg; }
+}"#;
+
+        let mut parserinput = ParserInput::new(&css_string);
+        let mut parser = Parser::new(&mut parserinput);
+
+        let styles = parse_sheet(&mut parser).unwrap();
+
+        let keys: Vec<(String, String)> = styles.iter().map(|style| style.key()).collect();
+
+        assert_eq!(keys, vec![
+            (String::new(), String::from("@font-face")),
+            (String::new(), String::from("h1")),
+            (String::from("@media (max-width: 600px)"), String::from("h1")),
+            (String::from("@media (max-width: 600px) @supports (display: grid)"), String::from(".grid")),
+        ]);
+
+        let at_rules = build_at_rules(&styles).unwrap();
+
+        assert_eq!(at_rules.len(), 1);
+        assert_eq!(at_rules[0].condition, CssCondition::new("media", "(max-width: 600px)"));
+        assert_eq!(at_rules[0].tags, vec![String::from("h1")]);
+        assert_eq!(at_rules[0].at_rules[0].condition, CssCondition::new("supports", "(display: grid)"));
+        assert_eq!(at_rules[0].at_rules[0].tags, vec![String::from(".grid")]);
+    }
+
+    #[test]
+    fn test_write_styles_at_rules() {
+        let css_string = "h1 {\n\tcolor: red;\n}\n\n@media (max-width: 600px) {\n\th1 {\n\t\tcolor: blue;\n\t}\n\n\tp {\n\t\tmargin: 0;\n\t}\n}\n";
+
+        let mut parserinput = ParserInput::new(&css_string);
+        let mut parser = Parser::new(&mut parserinput);
+
+        let mut styles = parse_sheet(&mut parser).unwrap();
+
+        // h1, @media h1, @media p
+        styles[1].attributes[0].values = vec![String::from("green")];
+        styles.remove(2);
+
+        let mut span = CssStyle::from_selector("span");
+        span.conditions = vec![CssCondition::new("media", "print")];
+        let mut attribute = CssAttribute::new();
+        attribute.name = String::from("display");
+        attribute.values = vec![String::from("none")];
+        span.attributes = vec![attribute];
+        styles.push(span);
+
+        let expected = "h1 {\n\tcolor: red;\n}\n\n@media (max-width: 600px) {\n\th1 {\n\t\tcolor: green;\n\t}\n}\n\n@media print {\n\tspan {\n\t\tdisplay: none;\n\t}\n}\n";
+
+        assert_eq!(write_styles(css_string, &styles), expected);
+    }
+
+    #[test]
+    fn test_write_styles_remove_groups() {
+        let remove = |css_string: &str, tag: &str| {
+            let mut parserinput = ParserInput::new(css_string);
+            let mut parser = Parser::new(&mut parserinput);
+
+            let styles: Vec<CssStyle> = parse_sheet(&mut parser).unwrap().into_iter().filter(|style| style.tag != tag).collect();
+
+            write_styles(css_string, &styles)
+        };
+
+        // the conditional group rules are removed with the last rule inside of them
+        assert_eq!(remove("h1 { color: red; }\n\n@media print {\n\tp { color: blue; }\n}\n", "p"), "h1 { color: red; }\n");
+        assert_eq!(remove("@supports (display: grid) {\n\t@media print {\n\t\tp { color: blue; }\n\t}\n}\n\nh1 { color: red; }\n", "p"), "h1 { color: red; }\n");
+        assert_eq!(remove("@media print {\n\th1 { color: red; }\n\tp { color: blue; }\n}\n", "p"), "@media print {\n\th1 { color: red; }\n}\n");
+
+        // whitespace after the last rule isn't left behind
+        assert_eq!(remove("h1 { color: red; }\n\np { color: blue; }  \n  ", "p"), "h1 { color: red; }\n");
+    }
+
+    #[test]
+    fn test_write_styles_condition_whitespace() {
+        let css_string = "@media (max-width: 600px) {\n\th1 { color: red; }\n}\n\np { margin: 0; }\n";
+
+        // the virtual file can write the condition with different whitespace
+        let mut parserinput = ParserInput::new("@media ( max-width:600px ) {\n\th1 { color: green; }\n}\n\np { margin: 0; }\n");
+        let mut parser = Parser::new(&mut parserinput);
+
+        let styles = parse_sheet(&mut parser).unwrap();
+
+        assert_eq!(styles[1].conditions, vec![CssCondition::new("media", "(max-width: 600px)")]);
+        assert_eq!(write_styles(css_string, &styles), "@media (max-width: 600px) {\n\th1 { color: green; }\n}\n\np { margin: 0; }\n");
+
+        assert_eq!(CssCondition::new("supports", "selector(a :hover) and (display:grid)").prelude, "selector(a :hover) and (display: grid)");
+    }
+
+    #[test]
+    fn test_write_styles() {
+        let css_string = r#"/* header */
+h1 {
+    background-color: red;
+    font-size: 100pt;
+}
+
+p {
+    font-size: 14pt;
+}
+
+span {
+    color: blue;
+}
+"#;
+
+        let mut h1 = CssStyle::from_selector("h1");
+        let mut attribute1 = CssAttribute::new();
+        attribute1.name = String::from("background-color");
+        attribute1.values = vec![String::from("green")];
+        let mut attribute2 = CssAttribute::new();
+        attribute2.name = String::from("font-size");
+        attribute2.values = vec![String::from("100pt")];
+        h1.attributes = vec![attribute1, attribute2];
+
+        let mut span = CssStyle::from_selector("span");
+        let mut attribute3 = CssAttribute::new();
+        attribute3.name = String::from("color");
+        attribute3.values = vec![String::from("blue")];
+        span.attributes = vec![attribute3];
+
+        let mut div = CssStyle::from_selector("div");
+        let mut attribute4 = CssAttribute::new();
+        attribute4.name = String::from("margin");
+        attribute4.values = vec![String::from("10px")];
+        div.attributes = vec![attribute4];
+
+        let expected = r#"/* header */
+h1 {
+    background-color: green;
+    font-size: 100pt;
+}
+
+span {
+    color: blue;
+}
+
+div {
+	margin: 10px;
+}
+"#;
+
+        assert_eq!(write_styles(css_string, &[h1, span, div]), expected);
+    }
+
+    #[test]
+    fn test_write_styles_declarations() {
+        let css_string = "h1 {\n\tmargin: 0; /* reset */\n\tmargin-top: 4px;\n\t/* brand colour */\n\tcolor: red;\n\tpadding: 1px\n}\n\np { margin: 0; color: blue; }\n";
+
+        let mut parserinput = ParserInput::new(css_string);
+        let mut parser = Parser::new(&mut parserinput);
+
+        let mut styles = parse_sheet(&mut parser).unwrap();
+
+        // h1 { margin, margin-top, color, padding }
+        styles[0].attributes[2].values = vec![String::from("green")];
+        styles[0].attributes.remove(1);
+
+        let mut attribute = CssAttribute::new();
+        attribute.name = String::from("display");
+        attribute.values = vec![String::from("block")];
+        attribute.important = vec![true];
+        styles[0].attributes.push(attribute);
+
+        // p { margin, color }
+        styles[1].attributes.remove(0);
+
+        // only the changed declarations are touched, so the shorthand stays before the longhands and the comments are kept
+        let expected = "h1 {\n\tmargin: 0; /* reset */\n\t/* brand colour */\n\tcolor: green;\n\tpadding: 1px;\n\tdisplay: block !important;\n}\n\np { color: blue; }\n";
+
+        assert_eq!(write_styles(css_string, &styles), expected);
+    }
+
+    #[test]
+    fn test_write_styles_replace_last_declaration() {
+        let replace_last = |css_string: &str| {
+            let mut parserinput = ParserInput::new(css_string);
+            let mut parser = Parser::new(&mut parserinput);
+
+            let mut styles = parse_sheet(&mut parser).unwrap();
+
+            let mut attribute = CssAttribute::new();
+            attribute.name = String::from("padding");
+            attribute.values = vec![String::from("1px")];
+            attribute.important = vec![false];
+
+            let last = styles[0].attributes.len() - 1;
+            styles[0].attributes[last] = attribute;
+
+            write_styles(css_string, &styles)
+        };
+
+        // the new declaration doesn't need a semicolon from the one it replaces
+        assert_eq!(replace_last("h1 { color: red }"), "h1 { padding: 1px; }");
+        assert_eq!(replace_last("h1 { color: red; }"), "h1 { padding: 1px; }");
+        assert_eq!(replace_last("h1 { margin: 0; color: red }"), "h1 { margin: 0; padding: 1px; }");
+        assert_eq!(replace_last("h1 { margin: 0; color: red; }"), "h1 { margin: 0; padding: 1px; }");
+        assert_eq!(replace_last("h1 {\n\tmargin: 0;\n\tcolor: red\n}"), "h1 {\n\tmargin: 0;\n\tpadding: 1px;\n}");
+        assert_eq!(replace_last("h1 {\n\tmargin: 0;\n\tcolor: red;\n}"), "h1 {\n\tmargin: 0;\n\tpadding: 1px;\n}");
+        assert_eq!(replace_last("h1 {\n\tcolor: red\n}"), "h1 {\n\tpadding: 1px;\n}");
+    }
+
+    #[test]
+    fn parse_imports_test() {
+        let css_string = r#"@charset "utf-8";
+@layer base, theme;
+@import url(reset.css);
+@import url("base.css") layer;
+@import "theme.css" layer(theme.dark) supports(display: grid) screen and (min-width: 600px);
+@import 'print.css' print;
+@import url("https://example.com/remote.css") all;
+h1 { color: red; }
+@import "ignored.css";"#;
+
+        let imports = parse_imports(css_string);
+
+        assert_eq!(imports, vec![
+            (String::from("reset.css"), vec![]),
+            (String::from("base.css"), vec![CssCondition::new("layer", "")]),
+            (String::from("theme.css"), vec![
+                CssCondition::new("layer", "theme.dark"),
+                CssCondition::new("supports", "(display: grid)"),
+                CssCondition::new("media", "screen and (min-width: 600px)"),
+            ]),
+            (String::from("print.css"), vec![CssCondition::new("media", "print")]),
+            (String::from("https://example.com/remote.css"), vec![]),
+        ]);
+
+        assert!(parse_imports("@layer base { h1 { color: red; } }\n@import \"late.css\";").is_empty());
+    }
 
+    #[test]
+    fn resolve_imports_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_resolve_imports_test");
+        let css_path = workspace_path.join("css");
+
+        fs::create_dir_all(&css_path).unwrap();
+        fs::write(workspace_path.join("main.css"), "@import \"css/a.css\";\n@import url(\"https://example.com/remote.css\");\nh1 { color: red; }").unwrap();
+        fs::write(css_path.join("a.css"), "@import \"b.css\" screen;").unwrap();
+        fs::write(css_path.join("b.css"), "@import \"a.css\";\n@import \"../main.css\";").unwrap();
+        fs::write(workspace_path.join("other.css"), "@import \"css/b.css\";").unwrap();
+
+        let resolved = resolve_imports(&[workspace_path.join("main.css"), workspace_path.join("other.css")]);
+
+        assert_eq!(resolved, vec![
+            css_path.join("b.css"),
+            css_path.join("a.css"),
+            workspace_path.join("main.css"),
+            workspace_path.join("other.css"),
+        ]);
+    }
 }
 /* #endregion */
\ No newline at end of file
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use super::css_style::CssStyle;
+
+/// At-rules that only group other rules under a condition, the styles inside of them are parsed like top level styles.
+pub const CONDITIONAL_AT_RULES: [&str; 5] = ["media", "supports", "layer", "container", "scope"];
+
+/// At-rules whose block is a list of declarations, these are stored as a style using the at-rule as its tag.
+pub const DECLARATION_AT_RULES: [&str; 6] = ["font-face", "page", "property", "counter-style", "font-palette-values", "viewport"];
+
+/// The condition of a conditional group rule, e.g. `@media (max-width: 600px)`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct CssCondition {
+    pub name: String,    // media, supports, layer, container or scope
+    pub prelude: String, // everything between the name and the block, e.g. `(max-width: 600px)`
+}
+
+/// A conditional group rule, and everything nested inside of it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CssAtRule {
+    pub condition: CssCondition,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>, // the tags of the styles directly inside this rule
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub at_rules: Vec<CssAtRule>,
+}
+
+impl CssCondition {
+    pub fn new(name: &str, prelude: &str) -> CssCondition {
+        // the prelude of `@scope` is a pair of selectors, where whitespace is part of what they mean
+        let prelude = if name == "scope" { prelude.to_string() } else { normalize_prelude(prelude) };
+
+        CssCondition {
+            name: name.to_string(),
+            prelude,
+        }
+    }
+
+    /// The condition as it is written in a stylesheet, without the block.
+    pub fn to_css_string(&self) -> String {
+        if self.prelude.is_empty() {
+            format!("@{}", self.name)
+        } else {
+            format!("@{} {}", self.name, self.prelude)
+        }
+    }
+}
+
+impl CssAtRule {
+    pub fn new(condition: CssCondition) -> CssAtRule {
+        CssAtRule {
+            condition,
+            tags: Vec::new(),
+            at_rules: Vec::new(),
+        }
+    }
+}
+
+/// Put the `prelude` of a conditional group rule in one form, so preludes that only differ in whitespace are the same, e.g. `( max-width:600px )` is `(max-width: 600px)`.
+/// Anything inside of `selector()` is left as it is, as whitespace is part of what a selector means.
+fn normalize_prelude(prelude: &str) -> String {
+    let prelude = prelude.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let mut normalized = String::new();
+    let mut selector_depth = 0;
+    let mut skip_space = false;
+
+    for character in prelude.chars() {
+        if selector_depth > 0 {
+            match character {
+                '(' => selector_depth += 1,
+                ')' => selector_depth -= 1,
+                _ => ()
+            };
+
+            normalized.push(character);
+            skip_space = false;
+            continue
+        }
+
+        match character {
+            ' ' if skip_space => continue,
+            '(' => {
+                if normalized.to_ascii_lowercase().ends_with("selector") {
+                    selector_depth = 1;
+                }
+
+                normalized.push('(');
+                skip_space = true;
+                continue
+            },
+            ')' => normalized.truncate(normalized.trim_end().len()),
+            ':' | ',' => {
+                normalized.truncate(normalized.trim_end().len());
+                normalized.push(character);
+                normalized.push(' ');
+                skip_space = true;
+                continue
+            },
+            _ => ()
+        };
+
+        normalized.push(character);
+        skip_space = false;
+    }
+
+    normalized
+}
+
+/// Get the text of every condition in `conditions` joined together, outermost first. Top level styles have no conditions and return an empty string.
+pub fn conditions_to_string(conditions: &[CssCondition]) -> String {
+    conditions.iter().map(|condition| condition.to_css_string()).collect::<Vec<String>>().join(" ")
+}
+
+/// Build the tree of conditional group rules from the conditions of each style.
+/// Returns `None` if none of the styles are inside of a conditional group rule.
+pub fn build_at_rules(styles: &[CssStyle]) -> Option<Vec<CssAtRule>> {
+    let mut at_rules: Vec<CssAtRule> = Vec::new();
+
+    for style in styles {
+        let mut current_rules = &mut at_rules;
+
+        for (depth, condition) in style.conditions.iter().enumerate() {
+            let position = match current_rules.iter().position(|rule| &rule.condition == condition) {
+                Some(value) => value,
+                None => {
+                    current_rules.push(CssAtRule::new(condition.clone()));
+                    current_rules.len() - 1
+                }
+            };
+
+            if depth == style.conditions.len() - 1 {
+                current_rules[position].tags.push(style.tag.clone());
+            }
+
+            current_rules = &mut current_rules[position].at_rules;
+        }
+    }
+
+    if at_rules.is_empty() {
+        None
+    } else {
+        Some(at_rules)
+    }
+}
@@ -1,10 +1,16 @@
 use ::serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::Range;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct CssAttribute {
     pub name: String,
     pub values: Vec<String>, // This needs to be a vector, because there might be some times when there are multiple values for the same style, which would just mean the last one is actually styled.
 
+    #[serde(default, skip_serializing_if = "has_no_important")]
+    pub important: Vec<bool>, // whether each of the values was marked as !important, in the same order as `values`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<Range>, // where each of the values is in the original file, in the same order as `values`
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub source: Option<u32>, // 0 if inline style in HTML, id otherwise. If it is missing, then it is an original from the file.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -17,6 +23,8 @@ impl CssAttribute {
         CssAttribute {
             name: String::new(),
             values: Vec::new(),
+            important: Vec::new(),
+            ranges: Vec::new(),
             source: None,
             is_overwritten: None,
         }
@@ -33,5 +41,38 @@ impl CssAttribute {
         
         // Replace the values of the current attribute with the new ones
         self.values = new_attribute.values.clone();
+        self.important = new_attribute.important.clone();
+        self.ranges = new_attribute.ranges.clone();
+    }
+
+    /// Add a value to the end of `values`, `important` is only stored when it is needed so attributes without any `!important` values stay equal.
+    /// The `range` is only kept while every value before it also has one, so `ranges` always lines up with the start of `values`
+    pub fn push_value(&mut self, value: String, important: bool, range: Option<Range>) {
+        if important {
+            self.important.resize(self.values.len(), false);
+            self.important.push(true);
+        }
+
+        if let Some(range) = range {
+            if self.ranges.len() == self.values.len() {
+                self.ranges.push(range);
+            }
+        }
+
+        self.values.push(value);
     }
+
+    /// Where the value at `index` is in the original file, if it is known
+    pub fn get_range(&self, index: usize) -> Option<Range> {
+        self.ranges.get(index).copied()
+    }
+
+    /// Whether the value at `index` was marked as `!important`
+    pub fn is_important(&self, index: usize) -> bool {
+        self.important.get(index).copied().unwrap_or(false)
+    }
+}
+
+fn has_no_important(important: &[bool]) -> bool {
+    !important.contains(&true)
 }
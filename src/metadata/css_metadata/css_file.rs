@@ -1,10 +1,17 @@
 use serde::{Deserialize, Serialize};
 
+use super::css_at_rule::CssCondition;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct CssFile {
-    pub id: u32,
+    pub id: u32, // 0 for an imported sheet that isn't in the workspace, or hasn't been given an id yet
     pub file_name: String,
     pub absolute_path: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>, // the media query of the link, none if it applies to all media
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<CssCondition>, // the layer, supports and media conditions of an @import, outermost first
 }
 
 impl CssFile {
@@ -13,6 +20,8 @@ impl CssFile {
             id: 0,
             file_name: String::new(),
             absolute_path: String::new(),
+            media: None,
+            conditions: Vec::new(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,563 @@
+use cssparser::{ParseError, Parser, ParserInput, ToCss, Token};
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::html_metadata::html_element::{HtmlElement, HtmlSymbol};
+
+/// A single complex selector from a selector list, e.g. `.card > h1:hover` in `.card > h1:hover, #nav a`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CssSelector {
+    pub text: String,
+    pub compounds: Vec<CompoundSelector>, // ordered from left to right, the last compound is the element the style applies to
+}
+
+/// A sequence of simple selectors that all apply to the same element, e.g. `h1.title:hover`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CompoundSelector {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combinator: Option<Combinator>, // how this compound relates to the one before it, None for the first compound
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub element: Option<String>, // the type selector, or `*`
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<AttributeSelector>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pseudo_classes: Vec<String>, // includes any arguments, e.g. `not(.hidden)`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pseudo_elements: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Combinator {
+    Descendant,        // `a b`
+    Child,             // `a > b`
+    NextSibling,       // `a + b`
+    SubsequentSibling, // `a ~ b`
+}
+
+/// The specificity of a selector as (ids, classes, elements), where classes include attributes and pseudo-classes, and elements include pseudo-elements.
+/// Compared in that order, so a selector with more ids always wins.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl std::ops::Add for Specificity {
+    type Output = Specificity;
+
+    fn add(self, other: Specificity) -> Specificity {
+        Specificity(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
+/// Pseudo-elements from CSS 2 that can still be written with a single colon, e.g. `p:first-line`
+const LEGACY_PSEUDO_ELEMENTS: [&str; 4] = ["before", "after", "first-line", "first-letter"];
+
+/// An attribute selector such as `[type=text]` or `[href^="https" i]`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AttributeSelector {
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>, // None if only checking the attribute exists
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modifier: Option<String>, // `i` or `s`
+}
+
+impl CssSelector {
+    pub fn new() -> CssSelector {
+        CssSelector {
+            text: String::new(),
+            compounds: Vec::new(),
+        }
+    }
+
+    /// The compound selector of the element the style is applied to.
+    pub fn subject(&self) -> Option<&CompoundSelector> {
+        self.compounds.last()
+    }
+
+    /// Calculate the specificity of this selector, following [Selectors Level 4](https://www.w3.org/TR/selectors-4/#specificity-rules).
+    pub fn specificity(&self) -> Specificity {
+        self.compounds.iter().fold(Specificity::default(), |total, compound| total + compound.specificity())
+    }
+
+    /// Whether this selector applies to every element that `other` applies to, whatever the state of the page is.
+    /// This is the case if they are the same selector, or this is a single compound whose parts are all part of the subject of `other`, e.g. `.card` covers `div.card:hover`.
+    pub fn covers(&self, other: &CssSelector) -> bool {
+        if self.text == other.text {
+            return true
+        }
+
+        let (compound, other_subject) = match (self.compounds.as_slice(), other.subject()) {
+            ([compound], Some(other_subject)) => (compound, other_subject),
+            _ => return false
+        };
+
+        // a pseudo-class only applies some of the time, and a pseudo-element is a different box to the element
+        if !compound.pseudo_classes.is_empty() || compound.pseudo_elements != other_subject.pseudo_elements {
+            return false
+        }
+
+        if other_subject.pseudo_classes.iter().any(|pseudo_class| LEGACY_PSEUDO_ELEMENTS.contains(&pseudo_class.to_ascii_lowercase().as_str())) {
+            return false
+        }
+
+        let element_covers = match &compound.element {
+            Some(name) if name != "*" => other_subject.element.as_ref().is_some_and(|other_name| name.eq_ignore_ascii_case(other_name)),
+            _ => true
+        };
+
+        element_covers
+            && compound.ids.iter().all(|id| other_subject.ids.contains(id))
+            && compound.classes.iter().all(|class| other_subject.classes.contains(class))
+            && compound.attributes.iter().all(|attribute| other_subject.attributes.contains(attribute))
+    }
+
+    /// Whether the `element` could be the one this selector applies to, only the subject is checked as the element's ancestors and siblings aren't known.
+    pub fn subject_matches(&self, element: &HtmlElement) -> bool {
+        match self.subject() {
+            Some(subject) => subject.matches_element(element),
+            None => false
+        }
+    }
+}
+
+impl CompoundSelector {
+    pub fn new() -> CompoundSelector {
+        CompoundSelector {
+            combinator: None,
+            element: None,
+            ids: Vec::new(),
+            classes: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_elements: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.element.is_none()
+            && self.ids.is_empty()
+            && self.classes.is_empty()
+            && self.attributes.is_empty()
+            && self.pseudo_classes.is_empty()
+            && self.pseudo_elements.is_empty()
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        let mut specificity = Specificity(self.ids.len() as u32, (self.classes.len() + self.attributes.len()) as u32, self.pseudo_elements.len() as u32);
+
+        if self.element.as_ref().is_some_and(|name| name != "*") {
+            specificity.2 += 1;
+        }
+
+        for pseudo_class in &self.pseudo_classes {
+            specificity = specificity + pseudo_class_specificity(pseudo_class);
+        }
+
+        specificity
+    }
+
+    /// Whether the `element` has everything this compound asks for. Pseudo-classes and pseudo-elements depend on the state of the page, so they are ignored.
+    pub fn matches_element(&self, element: &HtmlElement) -> bool {
+        if let Some(name) = &self.element {
+            if name != "*" && !name.eq_ignore_ascii_case(&element.name) {
+                return false
+            }
+        }
+
+        let element_classes: Vec<String> = element.classes().into_iter().map(|(class, _)| class).collect();
+
+        if !self.classes.iter().all(|class| element_classes.contains(class)) {
+            return false
+        }
+
+        let element_id = element.id().map(|(id, _)| id);
+
+        if !self.ids.iter().all(|id| element_id.as_ref() == Some(id)) {
+            return false
+        }
+
+        self.attributes.iter().all(|attribute| match element.get_attribute(&attribute.name.to_ascii_lowercase()) {
+            Some(element_attribute) => attribute.matches_value(&element_attribute.value),
+            None => false
+        })
+    }
+}
+
+impl AttributeSelector {
+    /// Whether an attribute with the given `value` is matched by this selector, the attribute is assumed to have the right name.
+    pub fn matches_value(&self, value: &str) -> bool {
+        let expected = match &self.value {
+            Some(expected) => expected,
+            None => return self.operator.is_none()
+        };
+
+        let (value, expected) = if self.modifier.as_deref() == Some("i") {
+            (value.to_lowercase(), expected.to_lowercase())
+        } else {
+            (value.to_string(), expected.clone())
+        };
+
+        match self.operator.as_deref() {
+            Some("=") => value == expected,
+            Some("~=") => value.split_ascii_whitespace().any(|part| part == expected),
+            Some("|=") => value == expected || value.starts_with(&format!("{}-", expected)),
+            Some("^=") => !expected.is_empty() && value.starts_with(&expected),
+            Some("$=") => !expected.is_empty() && value.ends_with(&expected),
+            Some("*=") => !expected.is_empty() && value.contains(&expected),
+            _ => false,
+        }
+    }
+}
+
+/// Get the text of a selector as it is stored in `CssStyle.tag`, comments are removed and all whitespace is collapsed into single spaces.
+pub fn normalize_selector_text(selector_text: &str) -> String {
+    let mut without_comments = String::new();
+    let mut remaining = selector_text;
+
+    while let Some(start) = remaining.find("/*") {
+        without_comments.push_str(&remaining[..start]);
+        without_comments.push(' ');
+
+        remaining = match remaining[start + 2..].find("*/") {
+            Some(end) => &remaining[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+
+    without_comments.push_str(remaining);
+
+    without_comments.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Parse a selector list such as `.card > h1:hover, #nav a` into each of its complex selectors.
+/// Anything that isn't understood is skipped, so this will never fail, at worst a selector will be missing some of its parts.
+pub fn parse_selector_list(selector_text: &str) -> Vec<CssSelector> {
+    let mut parser_input = ParserInput::new(selector_text);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let mut selectors: Vec<CssSelector> = Vec::new();
+    let mut selector = CssSelector::new();
+    let mut compound = CompoundSelector::new();
+    let mut combinator: Option<Combinator> = None;
+    let mut pending_whitespace = false;
+    let mut selector_start = parser.position();
+
+    loop {
+        let token_start = parser.position();
+
+        let token = match parser.next_including_whitespace() {
+            Ok(value) => value.clone(),
+            Err(_) => break,
+        };
+
+        match token {
+            Token::WhiteSpace(_) => {
+                if !compound.is_empty() {
+                    pending_whitespace = true;
+                }
+            },
+            Token::Comma => {
+                finish_compound(&mut selector, &mut compound, &mut combinator);
+                finish_selector(&mut selectors, &mut selector, parser.slice(selector_start..token_start));
+
+                combinator = None;
+                pending_whitespace = false;
+                selector_start = parser.position();
+            },
+            Token::Delim('>') | Token::Delim('+') | Token::Delim('~') => {
+                finish_compound(&mut selector, &mut compound, &mut combinator);
+
+                combinator = match token {
+                    Token::Delim('>') => Some(Combinator::Child),
+                    Token::Delim('+') => Some(Combinator::NextSibling),
+                    _ => Some(Combinator::SubsequentSibling),
+                };
+                pending_whitespace = false;
+            },
+            _ => {
+                if pending_whitespace {
+                    finish_compound(&mut selector, &mut compound, &mut combinator);
+
+                    combinator = Some(Combinator::Descendant);
+                    pending_whitespace = false;
+                }
+
+                parse_simple_selector(&mut parser, &token, &mut compound);
+            },
+        }
+    }
+
+    finish_compound(&mut selector, &mut compound, &mut combinator);
+    finish_selector(&mut selectors, &mut selector, parser.slice_from(selector_start));
+
+    selectors
+}
+
+/// Find every place the `symbol` is used in `selector_text`, including inside of pseudo-classes such as `:not(.hidden)`.
+/// Returns the byte offsets of each name, without the `.` or `#` in front of it.
+pub fn find_symbol_offsets(selector_text: &str, symbol: &HtmlSymbol) -> Vec<(usize, usize)> {
+    let mut parser_input = ParserInput::new(selector_text);
+    let mut parser = Parser::new(&mut parser_input);
+
+    let mut offsets: Vec<(usize, usize)> = Vec::new();
+
+    find_symbol_offsets_in_block(&mut parser, symbol, &mut offsets);
+
+    offsets
+}
+
+fn find_symbol_offsets_in_block(parser: &mut Parser, symbol: &HtmlSymbol, offsets: &mut Vec<(usize, usize)>) {
+    let mut previous_token: Option<Token> = None;
+
+    loop {
+        let token_start = parser.position().byte_index();
+
+        let token = match parser.next_including_whitespace() {
+            Ok(value) => value.clone(),
+            Err(_) => break,
+        };
+
+        let token_end = parser.position().byte_index();
+
+        match (&token, symbol) {
+            (Token::Ident(value), HtmlSymbol::Class(name)) if previous_token == Some(Token::Delim('.')) && value.as_ref() == name => {
+                offsets.push((token_start, token_end));
+            },
+            (Token::Ident(value), HtmlSymbol::Element(name)) if !matches!(previous_token, Some(Token::Delim('.')) | Some(Token::Colon)) && value.eq_ignore_ascii_case(name) => {
+                offsets.push((token_start, token_end));
+            },
+            (Token::IDHash(value) | Token::Hash(value), HtmlSymbol::Id(name)) if value.as_ref() == name => {
+                offsets.push((token_start + 1, token_end));
+            },
+            (Token::Function(_), _) => {
+                let _: Result<(), ParseError<()>> = parser.parse_nested_block(|inner_parser| {
+                    find_symbol_offsets_in_block(inner_parser, symbol, offsets);
+                    Ok(())
+                });
+            },
+            _ => (),
+        }
+
+        previous_token = Some(token);
+    }
+}
+
+fn finish_compound(selector: &mut CssSelector, compound: &mut CompoundSelector, combinator: &mut Option<Combinator>) {
+    if compound.is_empty() {
+        return
+    }
+
+    let mut finished_compound = std::mem::replace(compound, CompoundSelector::new());
+    finished_compound.combinator = if selector.compounds.is_empty() { None } else { combinator.take() };
+
+    selector.compounds.push(finished_compound);
+}
+
+fn finish_selector(selectors: &mut Vec<CssSelector>, selector: &mut CssSelector, selector_text: &str) {
+    if selector.compounds.is_empty() {
+        return
+    }
+
+    let mut finished_selector = std::mem::replace(selector, CssSelector::new());
+    finished_selector.text = normalize_selector_text(selector_text);
+
+    selectors.push(finished_selector);
+}
+
+/// Add the simple selector starting with `token` to the `compound`, consuming any extra tokens it needs from the `parser`.
+fn parse_simple_selector(parser: &mut Parser, token: &Token, compound: &mut CompoundSelector) {
+    match token {
+        Token::Ident(value) => compound.element = Some(value.to_string()),
+        Token::Delim('*') => compound.element = Some(String::from("*")),
+        Token::IDHash(value) | Token::Hash(value) => compound.ids.push(value.to_string()),
+        Token::Delim('.') => {
+            if let Ok(Token::Ident(value)) = parser.next_including_whitespace() {
+                compound.classes.push(value.to_string());
+            }
+        },
+        Token::SquareBracketBlock => {
+            let attribute: Result<AttributeSelector, ParseError<()>> = parser.parse_nested_block(|inner_parser| Ok(parse_attribute_selector(inner_parser)));
+
+            if let Ok(value) = attribute {
+                if !value.name.is_empty() {
+                    compound.attributes.push(value);
+                }
+            }
+        },
+        Token::Colon => {
+            let is_pseudo_element = parser.try_parse(|inner_parser| match inner_parser.next_including_whitespace() {
+                Ok(Token::Colon) => Ok(()),
+                _ => Err(()),
+            }).is_ok();
+
+            let pseudo = match parser.next_including_whitespace() {
+                Ok(Token::Ident(value)) => value.to_string(),
+                Ok(Token::Function(name)) => {
+                    let name = name.to_string();
+                    let arguments: Result<String, ParseError<()>> = parser.parse_nested_block(|inner_parser| {
+                        let start = inner_parser.position();
+
+                        while inner_parser.next_including_whitespace().is_ok() {}
+
+                        Ok(inner_parser.slice_from(start).trim().to_string())
+                    });
+
+                    format!("{}({})", name, arguments.unwrap_or_default())
+                },
+                _ => return,
+            };
+
+            if is_pseudo_element {
+                compound.pseudo_elements.push(pseudo);
+            } else {
+                compound.pseudo_classes.push(pseudo);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// `:is()`, `:not()` and `:has()` count as their most specific argument, `:where()` counts for nothing and every other pseudo-class counts as a class.
+fn pseudo_class_specificity(pseudo_class: &str) -> Specificity {
+    let (name, arguments) = match pseudo_class.split_once('(') {
+        Some((name, arguments)) => (name.to_ascii_lowercase(), arguments.strip_suffix(')').unwrap_or(arguments)),
+        None => (pseudo_class.to_ascii_lowercase(), ""),
+    };
+
+    match name.as_str() {
+        "where" => Specificity::default(),
+        "is" | "not" | "has" | "matches" => parse_selector_list(arguments).iter().map(|selector| selector.specificity()).max().unwrap_or_default(),
+        _ if LEGACY_PSEUDO_ELEMENTS.contains(&name.as_str()) => Specificity(0, 0, 1),
+        _ => Specificity(0, 1, 0),
+    }
+}
+
+fn parse_attribute_selector(parser: &mut Parser) -> AttributeSelector {
+    let mut attribute = AttributeSelector {
+        name: String::new(),
+        operator: None,
+        value: None,
+        modifier: None,
+    };
+
+    while let Ok(token) = parser.next() {
+        match token {
+            Token::Ident(value) | Token::QuotedString(value) => {
+                let value = value.to_string();
+
+                if attribute.name.is_empty() {
+                    attribute.name = value;
+                } else if attribute.operator.is_some() && attribute.value.is_none() {
+                    attribute.value = Some(value);
+                } else if attribute.value.is_some() {
+                    attribute.modifier = Some(value);
+                }
+            },
+            Token::Delim('=') => attribute.operator = Some(String::from("=")),
+            Token::IncludeMatch => attribute.operator = Some(String::from("~=")),
+            Token::DashMatch => attribute.operator = Some(String::from("|=")),
+            Token::PrefixMatch => attribute.operator = Some(String::from("^=")),
+            Token::SuffixMatch => attribute.operator = Some(String::from("$=")),
+            Token::SubstringMatch => attribute.operator = Some(String::from("*=")),
+            Token::Number { .. } | Token::Dimension { .. } if attribute.operator.is_some() && attribute.value.is_none() => {
+                attribute.value = Some(token.to_css_string());
+            },
+            _ => (),
+        }
+    }
+
+    attribute
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::html_metadata::html_element::{parse_elements, HtmlSymbol};
+
+    use super::{find_symbol_offsets, parse_selector_list, AttributeSelector, Combinator, Specificity};
+
+    #[test]
+    fn parse_selector_list_test() {
+        let selectors = parse_selector_list(".card > h1:hover,\n  #nav a[type=\"text\" i]::before");
+
+        assert_eq!(selectors.len(), 2);
+
+        assert_eq!(selectors[0].text, ".card > h1:hover");
+        assert_eq!(selectors[0].compounds.len(), 2);
+        assert_eq!(selectors[0].compounds[0].classes, vec![String::from("card")]);
+        assert_eq!(selectors[0].compounds[1].combinator, Some(Combinator::Child));
+        assert_eq!(selectors[0].compounds[1].element, Some(String::from("h1")));
+        assert_eq!(selectors[0].compounds[1].pseudo_classes, vec![String::from("hover")]);
+
+        assert_eq!(selectors[1].text, "#nav a[type=\"text\" i]::before");
+        assert_eq!(selectors[1].compounds[0].ids, vec![String::from("nav")]);
+        assert_eq!(selectors[1].compounds[1].combinator, Some(Combinator::Descendant));
+        assert_eq!(selectors[1].compounds[1].attributes, vec![AttributeSelector {
+            name: String::from("type"),
+            operator: Some(String::from("=")),
+            value: Some(String::from("text")),
+            modifier: Some(String::from("i")),
+        }]);
+        assert_eq!(selectors[1].compounds[1].pseudo_elements, vec![String::from("before")]);
+
+        let selectors = parse_selector_list("li:not(.active) ~ li:nth-child(2n + 1)");
+
+        assert_eq!(selectors[0].compounds[0].pseudo_classes, vec![String::from("not(.active)")]);
+        assert_eq!(selectors[0].compounds[1].combinator, Some(Combinator::SubsequentSibling));
+        assert_eq!(selectors[0].compounds[1].pseudo_classes, vec![String::from("nth-child(2n + 1)")]);
+    }
+
+    #[test]
+    fn subject_matches_test() {
+        let elements = parse_elements("<a id=\"home\" class=\"nav-link active\" href=\"https://example.com\" lang=\"en-GB\">Home</a>");
+        let link = &elements[0];
+
+        let matching = parse_selector_list("nav a.nav-link, #home:hover, [href^=https], a[lang|=en], .active.nav-link, *");
+        let not_matching = parse_selector_list("a.nav, #away, [href$=org], p.active, [target]");
+
+        assert!(matching.iter().all(|selector| selector.subject_matches(link)));
+        assert!(!not_matching.iter().any(|selector| selector.subject_matches(link)));
+    }
+
+    #[test]
+    fn find_symbol_offsets_test() {
+        let selector_text = ".btn, .btn-primary:not(.btn) > a#btn, btn";
+
+        assert_eq!(find_symbol_offsets(selector_text, &HtmlSymbol::Class(String::from("btn"))), vec![(1, 4), (24, 27)]);
+        assert_eq!(find_symbol_offsets(selector_text, &HtmlSymbol::Id(String::from("btn"))), vec![(33, 36)]);
+        assert_eq!(find_symbol_offsets(selector_text, &HtmlSymbol::Element(String::from("btn"))), vec![(38, 41)]);
+    }
+
+    #[test]
+    fn specificity_test() {
+        let specificity = |selector_text: &str| parse_selector_list(selector_text)[0].specificity();
+
+        assert_eq!(specificity("*"), Specificity(0, 0, 0));
+        assert_eq!(specificity("li::marker"), Specificity(0, 0, 2));
+        assert_eq!(specificity("p:first-line"), Specificity(0, 0, 2));
+        assert_eq!(specificity("ul li.active > a:hover"), Specificity(0, 2, 3));
+        assert_eq!(specificity("#nav [type=text]"), Specificity(1, 1, 0));
+        assert_eq!(specificity(".card:not(#main, p)"), Specificity(1, 1, 0));
+        assert_eq!(specificity(".card:where(#main)"), Specificity(0, 1, 0));
+    }
+
+    #[test]
+    fn covers_test() {
+        let selector = |selector_text: &str| parse_selector_list(selector_text).remove(0);
+
+        assert!(selector(".card").covers(&selector("div.card:hover")));
+        assert!(selector("*.card").covers(&selector("#main.card")));
+        assert!(selector(".card > p").covers(&selector(".card > p")));
+        assert!(!selector(".card").covers(&selector(".card::before")));
+        assert!(!selector(".card").covers(&selector(".card:after")));
+        assert!(!selector(".card:hover").covers(&selector(".card")));
+        assert!(!selector("div.card").covers(&selector(".card")));
+        assert!(!selector(".card p").covers(&selector("p")));
+    }
+}
@@ -1,24 +1,61 @@
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::Range;
 
-use super::css_attribute::CssAttribute;
+use super::{css_at_rule::{conditions_to_string, CssCondition}, css_attribute::CssAttribute, css_selector::{normalize_selector_text, parse_selector_list, CssSelector}};
 
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct CssStyle {
-    pub tag: String,
+    pub tag: String, // the full selector text, e.g. `.card > h1:hover, #nav a`
     pub attributes: Vec<CssAttribute>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selectors: Vec<CssSelector>, // one for each selector in the selector list
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<CssCondition>, // the conditional group rules the style is nested inside of, outermost first. Empty for top level styles
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>, // from the start of the selector to the closing bracket, None if the style didn't come from a file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector_range: Option<Range>,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub is_style_attribute: bool, // written in the `style` attribute of an element, which is its own level of the cascade above every selector
 }
 
 impl CssStyle {
     pub fn new() -> CssStyle {
         CssStyle{
             tag: String::new(),
-            attributes: Vec::new()
+            attributes: Vec::new(),
+            selectors: Vec::new(),
+            conditions: Vec::new(),
+            range: None,
+            selector_range: None,
+            is_style_attribute: false,
+        }
+    }
+
+    /// Create an empty style for the `selector_text`, parsing it into its selectors.
+    pub fn from_selector(selector_text: &str) -> CssStyle {
+        CssStyle {
+            tag: normalize_selector_text(selector_text),
+            attributes: Vec::new(),
+            selectors: parse_selector_list(selector_text),
+            conditions: Vec::new(),
+            range: None,
+            selector_range: None,
+            is_style_attribute: false,
         }
     }
 
+    /// The conditions and tag that identify this style, two styles with the same key are the same rule split over multiple places.
+    pub fn key(&self) -> (String, String) {
+        (conditions_to_string(&self.conditions), self.tag.clone())
+    }
+
     pub fn update_attributes(&mut self, new_attributes: Vec<CssAttribute>) {
         let mut new_attributes_map: HashMap<String, CssAttribute> = HashMap::new();
 
@@ -26,13 +63,17 @@ impl CssStyle {
             let existing_attribute = new_attributes_map.entry(new_attribute.name.clone()).or_insert(CssAttribute {
                 name: new_attribute.name.clone(),
                 values: Vec::new(),
+                important: Vec::new(),
+                ranges: Vec::new(),
                 source: None,
                 is_overwritten: None,
             });
 
             //TODO: Maybe need to update is_overwritten if the value is the same as the source. Would require extra logic.
 
-            existing_attribute.values.extend(new_attribute.values);
+            for (index, value) in new_attribute.values.iter().enumerate() {
+                existing_attribute.push_value(value.clone(), new_attribute.is_important(index), new_attribute.get_range(index));
+            }
             if let Some(source) = new_attribute.source {
                 existing_attribute.source = Some(source);
             }
@@ -59,6 +100,10 @@ impl CssStyle {
     }
 }
 
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
 #[cfg(test)]
 mod tests {
     use crate::metadata::css_metadata::css_attribute::CssAttribute;
@@ -72,6 +117,8 @@ mod tests {
 
         let old_attribute1 = CssAttribute {
             name: String::from("background-color"),
+            important: Vec::new(),
+            ranges: Vec::new(),
             values: vec![String::from("red")],
             source: None,
             is_overwritten: None,
@@ -79,6 +126,8 @@ mod tests {
 
         let old_attribute2 = CssAttribute {
             name: String::from("font-size"),
+            important: Vec::new(),
+            ranges: Vec::new(),
             values: vec![String::from("12pt")],
             source: Some(1),
             is_overwritten: Some(false),
@@ -88,6 +137,8 @@ mod tests {
 
         let new_attribute1 = CssAttribute {
             name: String::from("background-color"),
+            important: Vec::new(),
+            ranges: Vec::new(),
             values: vec![String::from("green")],
             source: None,
             is_overwritten: None,
@@ -104,6 +155,8 @@ mod tests {
 
         let new_attribute2 = CssAttribute {
             name: String::from("font-size"),
+            important: Vec::new(),
+            ranges: Vec::new(),
             values: vec![String::from("12pt"), String::from("14pt")],
             source: None,
             is_overwritten: None,
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
 
+use cssparser::{Parser, ParserInput};
 use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::Range;
 
-use super::css_metadata::{css_attribute::CssAttribute, CssMetaData};
+use super::{cascade::mark_overwritten, css_metadata::{css_at_rule::{conditions_to_string, CssCondition}, css_attribute::CssAttribute, css_style::CssStyle, parse_sheet, CssMetaData}, html_metadata::INLINE_STYLE_ID};
 
 #[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
 pub struct FormattedCssFile {
@@ -23,25 +25,37 @@ impl FormattedCssFile {
 	}
 
 	pub fn generate_formatted_file(metadata_files: &Vec<CssMetaData>) -> FormattedCssFile {
-		// Tag , Attributes
+		// (Conditions, Tag) , Attributes
 		let mut formatted_file = FormattedCssFile::new();
-		let mut css_map: HashMap<String, Vec<CssAttributeExtended>> = HashMap::new();
+		let mut css_map: HashMap<(Vec<CssCondition>, String), Vec<CssAttributeExtended>> = HashMap::new();
 	
-		for metadata_file in metadata_files {
+		for (sheet, metadata_file) in metadata_files.iter().enumerate() {
 			if let Some(styles) = &metadata_file.styles {
 				styles
 				.iter()
 				.for_each( |style| {
-					let key = style.tag.clone();
+					let key = (style.conditions.clone(), style.tag.clone());
 	
 					let existing_attributes = css_map.entry(key).or_insert(Vec::new());
 	
 					style.attributes.iter().for_each(|attribute| {
-						existing_attributes.append(&mut CssAttributeExtended::from_attribute(attribute.clone(), metadata_file.id));
+						let mut values = CssAttributeExtended::from_attribute(attribute.clone(), metadata_file.id);
+
+						values.iter_mut().for_each(|value| {
+							value.is_style_attribute = style.is_style_attribute;
+							value.sheet = sheet;
+						});
+
+						existing_attributes.append(&mut values);
 					});
 				})
 			}
 
+			// the styles inside of a HTML document can be split over many sheets, which are all the same file
+			if formatted_file.included_files.iter().any(|file| file.id == metadata_file.id) {
+				continue
+			}
+
 			formatted_file.included_files.push(FileMetaData {
 				id: metadata_file.id,
 				file_name: metadata_file.file_name.clone(),
@@ -51,7 +65,7 @@ impl FormattedCssFile {
 	
 		let mut css_vec: Vec<CssStyleExtended> = css_map
 		.iter_mut()
-		.map(|(key, value)| {
+		.map(|((conditions, tag), value)| {
 			value.sort_by_key(|attribute| attribute.name.clone());
 			
 			CssStyleExtended {
@@ -63,79 +77,167 @@ impl FormattedCssFile {
 							None
 						}
 					} else {
-						get_owner(&key, metadata_files)
+						get_owner(conditions, tag, metadata_files)
 					}
 				},
-				tag: key.clone(),
+				tag: tag.clone(),
 				attributes: value.clone(),
+				conditions: conditions.clone(),
 			}
 		})
 		.collect();
 	
-		css_vec.sort_by_key(|x| x.tag.clone());
+		// top level styles first, then each group of conditions
+		css_vec.sort_by_key(|x| x.key());
 	
 		formatted_file.styles = css_vec;
 
+		mark_overwritten(&mut formatted_file.styles);
+
 		formatted_file.update_lines();
 
 		formatted_file
 	}
+
+	/// Lay out every line of the file along with its owner. Styles that share conditions are nested together inside of a single block for each condition.
+	/// The lines of a condition are owned by nobody, as they can contain styles from many files.
+	fn layout_lines(&self) -> Vec<(String, Option<u32>)> {
+		let mut lines: Vec<(String, Option<u32>)> = Vec::new();
+		let mut open_conditions: &[CssCondition] = &[];
+
+		for style in &self.styles {
+			let shared = open_conditions
+			.iter()
+			.zip(style.conditions.iter())
+			.take_while(|(open, new)| open == new)
+			.count();
+
+			for depth in (shared..open_conditions.len()).rev() {
+				lines.push((format!("{}}}", "\t".repeat(depth)), None));
+			}
+
+			for (depth, condition) in style.conditions.iter().enumerate().skip(shared) {
+				lines.push((format!("{}{} {{", "\t".repeat(depth), condition.to_css_string()), None));
+			}
+
+			open_conditions = &style.conditions;
+
+			let indentation = "\t".repeat(style.conditions.len());
+
+			lines.push((format!("{}{} {{", indentation, style.tag), style.owner));
+
+			style
+			.attributes
+			.iter()
+			.for_each(|attribute| {
+				let important = if attribute.important { " !important" } else { "" };
+
+				lines.push((format!("{}\t{}: {}{};", indentation, attribute.name, attribute.value, important), Some(attribute.owner)));
+			});
+
+			lines.push((format!("{}}}", indentation), style.owner));
+		}
+
+		for depth in (0..open_conditions.len()).rev() {
+			lines.push((format!("{}}}", "\t".repeat(depth)), None));
+		}
+
+		lines
+	}
 	
 	pub fn to_css_string(&self) -> String {
 		let mut css_string = String::new();
-		let mut is_last_attribute = false;
 	
-		self.styles
+		self
+		.layout_lines()
 		.iter()
-		.for_each(|style| {
-			css_string.push_str(&style.tag);
-			css_string.push_str(" {\n\t");
-			style
-			.attributes
-			.iter()
-			.enumerate()
-			.for_each(|(attr_index, attribute)| {
-				if attr_index == style.attributes.len() - 1 { is_last_attribute = true }
-				
-				css_string.push_str(&attribute.name);
-				css_string.push_str(": ");
-				css_string.push_str(&attribute.value);
-				if is_last_attribute {
-					css_string.push_str(";\n"); //TODO: Add a function that tracks indentation
-				} else {
-					css_string.push_str(";\n\t");
-				}
-			});
-			css_string.push_str("}\n");
-			is_last_attribute = false;
+		.for_each(|(line, _)| {
+			css_string.push_str(line);
+			css_string.push('\n');
 		});
 	
 		css_string
 	}
 
-	pub fn update_lines(&mut self) {
-		self.lines.clear();
+	/// Split the edited `css_string` of a virtual file back into the styles that belong to each of the `included_files`.
+	/// Declarations keep the owner they were generated with, new declarations are given to the owner of the style (or attribute) they were added to,
+	/// and new styles are given to the last included stylesheet. Inline styles are never written back, so they are only given new styles if there is no stylesheet.
+	/// Every included file gets an entry, an empty `Vec<CssStyle>` means all of its styles were removed.
+	pub fn split_by_owner(&self, css_string: &str) -> HashMap<u32, Vec<CssStyle>> {
+		let mut owner_map: HashMap<u32, Vec<CssStyle>> = self
+		.included_files
+		.iter()
+		.map(|file| (file.id, Vec::new()))
+		.collect();
 
-		let mut line_count: u32 = 0;
+		let default_owner = match self.included_files.iter().rev().find(|file| file.id != INLINE_STYLE_ID).or(self.included_files.last()) {
+			Some(value) => value.id,
+			None => return owner_map
+		};
 
-		self
-		.styles
-		.iter()
-		.for_each(|style| {
-			self.lines.push(LineInformation::new_line(line_count, style.owner));
-			line_count += 1;
-			
-			style
-			.attributes
-			.iter()
-			.for_each(|attribute| {
-				self.lines.push(LineInformation::new_line(line_count, Some(attribute.owner)));
-				line_count += 1;
-			});
+		let mut parser_input = ParserInput::new(css_string);
+		let mut parser = Parser::new(&mut parser_input);
 
-			self.lines.push(LineInformation::new_line(line_count, style.owner));
-			line_count +=1;
-		});
+		let edited_styles = match parse_sheet(&mut parser) {
+			Some(value) => value,
+			None => return owner_map
+		};
+
+		// (style key, attribute name) -> how many values have been seen so far
+		let mut value_counts: HashMap<((String, String), String), usize> = HashMap::new();
+
+		for edited_style in edited_styles {
+			let key = edited_style.key();
+			let original_style = self.styles.iter().find(|style| style.key() == key);
+
+			for attribute in &edited_style.attributes {
+				for (value_index, value) in attribute.values.iter().enumerate() {
+					let important = attribute.is_important(value_index);
+					let index = value_counts.entry((key.clone(), attribute.name.clone())).or_insert(0);
+
+					let owner = get_attribute_owner(original_style, &attribute.name, *index).unwrap_or(default_owner);
+
+					*index += 1;
+
+					let styles = owner_map.entry(owner).or_default();
+
+					let style = match styles.iter().position(|style| style.key() == key) {
+						Some(position) => &mut styles[position],
+						None => {
+							let mut style = edited_style.clone();
+							style.attributes = Vec::new();
+							style.range = None;
+							style.selector_range = None;
+							styles.push(style);
+							styles.last_mut().unwrap()
+						}
+					};
+
+					match style.attributes.iter_mut().find(|existing| existing.name == attribute.name) {
+						Some(existing) => existing.push_value(value.clone(), important, None),
+						None => {
+							let mut new_attribute = attribute.clone();
+							new_attribute.values = Vec::new();
+							new_attribute.important = Vec::new();
+							new_attribute.ranges = Vec::new();
+							new_attribute.push_value(value.clone(), important, None);
+							style.attributes.push(new_attribute);
+						}
+					}
+				}
+			}
+		}
+
+		owner_map
+	}
+
+	pub fn update_lines(&mut self) {
+		self.lines = self
+		.layout_lines()
+		.iter()
+		.enumerate()
+		.map(|(line_number, (_, owner))| LineInformation::new_line(line_number as u32, *owner))
+		.collect();
 	}
 
 }
@@ -145,6 +247,16 @@ pub struct CssStyleExtended {
 	pub owner: Option<u32>,
 	pub tag: String,
     pub attributes: Vec<CssAttributeExtended>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub conditions: Vec<CssCondition>, // the conditional group rules the style is nested inside of, outermost first
+}
+
+impl CssStyleExtended {
+	/// The conditions and tag that identify this style, matches `CssStyle::key`
+	pub fn key(&self) -> (String, String) {
+		(conditions_to_string(&self.conditions), self.tag.clone())
+	}
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
@@ -153,10 +265,18 @@ pub struct CssAttributeExtended {
 	pub name: String,
     pub value: String,
 
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub important: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub source: Option<u32>, // 0 if inline style in HTML, id otherwise. If it is missing, then it is an original from the file.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub is_overwritten: Option<bool>, // will be None if it's not an imported style, true if the value is different to that of the source file, and false if it's the same.
+    pub is_overwritten: Option<bool>, // true if another declaration always wins the cascade against it, see `mark_overwritten`. None for declarations inside of at-rules and layers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>, // where the value is in the owner's file
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub is_style_attribute: bool, // see `CssStyle::is_style_attribute`
+    #[serde(skip)]
+    pub sheet: usize, // where the sheet it came from is in the order they cascade in, a HTML document can have many sheets under the one owner
 }
 
 impl CssAttributeExtended {
@@ -165,13 +285,18 @@ impl CssAttributeExtended {
 		attribute
 		.values
 		.iter()
-		.map(|value| {
+		.enumerate()
+		.map(|(index, value)| {
 			CssAttributeExtended {
 				owner: owner_id,
 				name: attribute.name.clone(),
 				value: value.clone(),
+				important: attribute.is_important(index),
 				source: attribute.source,
-				is_overwritten: attribute.is_overwritten
+				is_overwritten: attribute.is_overwritten,
+				range: attribute.get_range(index),
+				is_style_attribute: false,
+				sheet: 0,
 			}
 		})
 		.collect()
@@ -202,10 +327,10 @@ pub struct FileMetaData {
 	pub absolute_path: String,
 }
 
-fn get_owner(tag_name: &str, metadata_files: &Vec<CssMetaData>) -> Option<u32> {
+fn get_owner(conditions: &Vec<CssCondition>, tag_name: &str, metadata_files: &Vec<CssMetaData>) -> Option<u32> {
 	for metadata_file in metadata_files {
 		if let Some(styles) = &metadata_file.styles {
-			if styles.iter().any(|x| x.tag == tag_name) {
+			if styles.iter().any(|x| x.tag == tag_name && &x.conditions == conditions) {
 				return Some(metadata_file.id)
 			}
 		}
@@ -215,10 +340,167 @@ fn get_owner(tag_name: &str, metadata_files: &Vec<CssMetaData>) -> Option<u32> {
 }
 
 
+fn is_false(value: &bool) -> bool {
+	!value
+}
+
+/// Get the owner of the `index`th value of the attribute `attribute_name` inside of the `original_style`.
+/// If the value is new, it falls back to the owner of the last value of the same attribute, then the owner of the style, and then the owner of the last attribute in the style.
+fn get_attribute_owner(original_style: Option<&CssStyleExtended>, attribute_name: &str, index: usize) -> Option<u32> {
+	let style = original_style?;
+
+	let matching_attributes: Vec<&CssAttributeExtended> = style.attributes.iter().filter(|attribute| attribute.name == attribute_name).collect();
 
+	if let Some(attribute) = matching_attributes.get(index).or(matching_attributes.last()) {
+		return Some(attribute.owner)
+	}
+
+	if style.owner.is_some() {
+		return style.owner
+	}
+
+	style.attributes.last().map(|attribute| attribute.owner)
+}
 
 #[cfg(test)]
 mod tests {
+	use chrono::Utc;
+
+	use std::path::Path;
+
+	use crate::metadata::{css_metadata::{css_at_rule::CssCondition, css_attribute::CssAttribute, css_style::CssStyle, CssMetaData}, html_metadata::{get_document_stylesheets, INLINE_STYLE_ID}, migration::SCHEMA_VERSION};
+
+	use super::FormattedCssFile;
+
+	#[test]
+	fn generate_conditional_css_string_test() {
+		let mut attribute_1 = CssAttribute::new();
+		attribute_1.name = String::from("color");
+		attribute_1.values = vec![String::from("red")];
+
+		let mut attribute_2 = CssAttribute::new();
+		attribute_2.name = String::from("color");
+		attribute_2.values = vec![String::from("blue")];
+
+		let mut media_style = CssStyle { attributes: vec![attribute_2], ..CssStyle::from_selector("h1") };
+		media_style.conditions = vec![CssCondition::new("media", "(max-width: 600px)")];
+
+		let css_metadata_1 = CssMetaData {
+			schema_version: SCHEMA_VERSION,
+			id: 1,
+			file_name: String::from("base.css"),
+			absolute_path: String::from("/workspace/base.css"),
+			last_updated: Utc::now(),
+			content_hash: None,
+			source_stamp: None,
+			imported_sheets: None,
+			at_rules: None,
+			styles: Some(vec![CssStyle { attributes: vec![attribute_1], ..CssStyle::from_selector("h1") }])
+		};
+
+		let css_metadata_2 = CssMetaData {
+			schema_version: SCHEMA_VERSION,
+			id: 2,
+			file_name: String::from("responsive.css"),
+			absolute_path: String::from("/workspace/responsive.css"),
+			last_updated: Utc::now(),
+			content_hash: None,
+			source_stamp: None,
+			imported_sheets: None,
+			at_rules: None,
+			styles: Some(vec![media_style])
+		};
+
+		let formatted_file = FormattedCssFile::generate_formatted_file(&vec![css_metadata_2, css_metadata_1]);
+
+		let expected = "h1 {\n\tcolor: red;\n}\n@media (max-width: 600px) {\n\th1 {\n\t\tcolor: blue;\n\t}\n}\n";
+
+		assert_eq!(formatted_file.to_css_string(), expected);
+
+		let owners: Vec<Option<u32>> = formatted_file.lines.iter().map(|line| line.owner).collect();
+
+		assert_eq!(owners, vec![Some(1), Some(1), Some(1), None, Some(2), Some(2), Some(2), None]);
+	}
+
+	#[test]
+	fn split_by_owner_test() {
+		let mut attribute_1 = CssAttribute::new();
+		attribute_1.name = String::from("background-color");
+		attribute_1.values = vec![String::from("red")];
+
+		let mut attribute_2 = CssAttribute::new();
+		attribute_2.name = String::from("font-size");
+		attribute_2.values = vec![String::from("14pt")];
+
+		let css_metadata_1 = CssMetaData {
+			schema_version: SCHEMA_VERSION,
+			id: 1,
+			file_name: String::from("base.css"),
+			absolute_path: String::from("/workspace/base.css"),
+			last_updated: Utc::now(),
+			content_hash: None,
+			source_stamp: None,
+			imported_sheets: None,
+			at_rules: None,
+			styles: Some(vec![CssStyle { attributes: vec![attribute_1.clone()], ..CssStyle::from_selector("h1") }])
+		};
+
+		let css_metadata_2 = CssMetaData {
+			schema_version: SCHEMA_VERSION,
+			id: 2,
+			file_name: String::from("theme.css"),
+			absolute_path: String::from("/workspace/theme.css"),
+			last_updated: Utc::now(),
+			content_hash: None,
+			source_stamp: None,
+			imported_sheets: None,
+			at_rules: None,
+			styles: Some(vec![CssStyle { attributes: vec![attribute_2.clone()], ..CssStyle::from_selector("h1") }])
+		};
+
+		let formatted_file = FormattedCssFile::generate_formatted_file(&vec![css_metadata_1, css_metadata_2]);
+
+		let edited = "h1 {\n\tbackground-color: green;\n\tfont-size: 16pt;\n}\np {\n\tcolor: blue;\n}\n";
+
+		let owner_map = formatted_file.split_by_owner(edited);
+
+		attribute_1.values = vec![String::from("green")];
+		attribute_2.values = vec![String::from("16pt")];
+
+		let mut attribute_3 = CssAttribute::new();
+		attribute_3.name = String::from("color");
+		attribute_3.values = vec![String::from("blue")];
+
+		assert_eq!(owner_map.get(&1).unwrap(), &vec![CssStyle { attributes: vec![attribute_1], ..CssStyle::from_selector("h1") }]);
+		assert_eq!(owner_map.get(&2).unwrap(), &vec![
+			CssStyle { attributes: vec![attribute_2], ..CssStyle::from_selector("h1") },
+			CssStyle { attributes: vec![attribute_3], ..CssStyle::from_selector("p") },
+		]);
+	}
+
+	#[test]
+	fn split_by_owner_inline_test() {
+		let html_string = "<head>\n\t<link rel=\"stylesheet\" href=\"base.css\">\n\t<style>p { margin: 0; }</style>\n</head>";
+
+		let mut css_metadata = CssMetaData::new();
+		css_metadata.id = 1;
+		css_metadata.absolute_path = String::from("/workspace/base.css");
+		css_metadata.parse_contents("h1 { color: red; }");
+
+		// the inline styles come after the linked stylesheets
+		let formatted_file = FormattedCssFile::generate_formatted_file(&get_document_stylesheets(Path::new("/workspace/index.html"), html_string, vec![css_metadata]));
+
+		let edited = format!("{}span {{\n\tcolor: blue;\n}}\n", formatted_file.to_css_string());
+
+		let owner_map = formatted_file.split_by_owner(&edited);
+
+		let tags = |owner: u32| owner_map[&owner].iter().map(|style| style.tag.clone()).collect::<Vec<String>>();
+
+		// a new rule goes to the stylesheet, as inline styles aren't written back
+		assert_eq!(tags(1), vec![String::from("h1"), String::from("span")]);
+		assert_eq!(tags(INLINE_STYLE_ID), vec![String::from("p")]);
+	}
+
 	#[test]
     fn merge_css_metadata_test() {
 
@@ -1,61 +1,112 @@
-use std::{fs, path::PathBuf};
+pub mod html_element;
+pub mod html_link;
+
+use std::{collections::HashSet, path::{Path, PathBuf}};
 
 use chrono::{serde::ts_seconds, DateTime, Utc};
+use cssparser::{Parser, ParserInput};
 use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::{error::{path_to_strings, BhcError}, position::{offset_to_position, position_to_offset, translate_position}};
 
-use crate::file::{create_dir_and_file, get_css_file_paths};
+use self::{html_element::{parse_elements, HtmlElement, HtmlSymbol}, html_link::{parse_stylesheet_links, LinkTarget}};
 
-use super::{css_metadata::css_file::CssFile, workspace_metadata::{workspace_html_file::WorkspaceHtmlFile, WorkspaceMetaData}};
+use super::{check_staleness, css_metadata::{css_at_rule::CssCondition, css_file::CssFile, css_style::CssStyle, find_symbol_ranges, parse_sheet, resolve_imports, CssMetaData}, hash_contents, migration::SCHEMA_VERSION, read_metadata, read_source, write_metadata, workspace_metadata::{workspace_html_file::WorkspaceHtmlFile, WorkspaceMetaData}, FileStamp, Staleness};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct HtmlMetaData {
+	#[serde(default)]
+	pub schema_version: u32, // the layout the metadata was saved in, see `SCHEMA_VERSION`
+
 	pub id: u32,
 	pub file_name: String,
 	pub absolute_path: String,
 
 	#[serde(with = "ts_seconds")]
 	pub last_updated: DateTime<Utc>,
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub content_hash: Option<String>, // the hash of the document when its links were last read, see `hash_contents`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub source_stamp: Option<FileStamp>, // the stamp of the document when its links were last read from disk, None if they were read from an open document
 	
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub css_sheets: Option<Vec<CssFile>>,
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub inline_styles: Option<Vec<CssStyle>>, // the styles of every <style> element and style attribute, with ranges inside of the HTML document
 }
 
 impl HtmlMetaData {
 	pub fn new() -> HtmlMetaData {
 		HtmlMetaData {
+			schema_version: SCHEMA_VERSION,
 			id: 0,
 			file_name: String::new(),
 			absolute_path: String::new(),
 			last_updated: Utc::now(),
+			content_hash: None,
+			source_stamp: None,
 			css_sheets: None,
+			inline_styles: None,
 		}
 	}
 
-	/// Update the `HtmlMetaData.css_sheets` to contain all the necessary imported sheets.
-	pub fn update_css_sheets(&mut self, workspace_metadata: &WorkspaceMetaData) -> Result<WorkspaceHtmlFile, String> {
-		let html_string = match fs::read_to_string(&self.absolute_path) {
-			Ok(value) => value,
-			Err(error) => return Err(format!("Error occurred trying to open HTML file ({}): {:?}", self.absolute_path, error))
-		};
-		
-		let css_file_paths = match get_css_file_paths(&PathBuf::from(&self.absolute_path), &html_string) {
-			Ok(value) => value,
-			Err(error) => return Err(error)
-		};
+	/// Read the metadata saved at `file_path`.
+	/// Returns `Err(BhcError)` if the file could not be read or deserialized.
+	pub fn from_json(file_path: &Path) -> Result<HtmlMetaData, BhcError> {
+		read_metadata(file_path)
+	}
+
+	/// Update the `HtmlMetaData.css_sheets` to contain all the necessary imported sheets, and the `HtmlMetaData.inline_styles` to the styles written inside of the document.
+	pub fn update_css_sheets(&mut self, workspace_metadata: &WorkspaceMetaData) -> Result<WorkspaceHtmlFile, BhcError> {
+		let (html_string, stamp) = read_source(Path::new(&self.absolute_path))?;
+
+		let workspace_html_file = self.parse_contents(&html_string, workspace_metadata);
+		self.source_stamp = stamp;
+
+		Ok(workspace_html_file)
+	}
+
+	/// The same as [`HtmlMetaData::update_css_sheets`], but from `html_string` instead of what is on disk, e.g. from a document that is open but not saved.
+	/// Returns the `WorkspaceHtmlFile` with the ids of the stylesheets the document now links to.
+	pub fn parse_contents(&mut self, html_string: &str, workspace_metadata: &WorkspaceMetaData) -> WorkspaceHtmlFile {
+		self.content_hash = Some(hash_contents(html_string));
+		self.source_stamp = None;
+		self.inline_styles = parse_inline_styles(html_string);
+
+		let links = parse_stylesheet_links(&PathBuf::from(&self.absolute_path), html_string);
 		
-		if css_file_paths.is_empty() {
-			self.css_sheets = None;
-		} else {
-			for file_path in css_file_paths {
+		// the sheets are found again from scratch, so links that were removed, or to stylesheets that were deleted, aren't kept
+		self.css_sheets = None;
+
+		for link in links {
+			// only stylesheets in the workspace have metadata
+			let file_path = match link.target {
+				LinkTarget::Local(value) => value,
+				_ => continue
+			};
+
+			// the sheets the link imports are applied before it, with the media of the link
+			for file_path in resolve_imports(&[file_path]) {
 				if let Some(id) = workspace_metadata.get_css_file_id(&file_path) {
+					// stylesheets in the workspace have already had their paths checked
+					let (absolute_path, file_name) = match path_to_strings(&file_path) {
+						Ok(value) => value,
+						Err(_) => continue
+					};
+
 					if let Some(sheets) = &mut self.css_sheets {
 						sheets.retain(|sheet| sheet.id != id);
 					}
 
 					let css_file = CssFile { 
 						id: id, 
-						file_name: file_path.file_name().unwrap().to_str().unwrap().to_string(), 
-						absolute_path: file_path.to_str().unwrap().to_string() 
+						file_name, 
+						absolute_path,
+						media: link.media.clone(),
+						conditions: Vec::new(),
 					};
 
 					if let Some(sheets) = &mut self.css_sheets {
@@ -73,7 +124,7 @@ impl HtmlMetaData {
 			}
 		}
 
-		Ok(WorkspaceHtmlFile {
+		WorkspaceHtmlFile {
 			id: self.id.clone(),
 			file_name: self.file_name.clone(),
 			absolute_path: self.absolute_path.clone(),
@@ -84,30 +135,39 @@ impl HtmlMetaData {
 					None
 				}
 			}
-		})
+		}
+	}
 
+	/// Check whether the document has changed since its links were read, see [`check_staleness`].
+	/// Returns `Err(BhcError)` if the document could not be read.
+	pub fn check_staleness(&self) -> Result<Staleness, BhcError> {
+		check_staleness(Path::new(&self.absolute_path), self.content_hash.as_deref(), self.source_stamp.as_ref(), self.last_updated)
 	}
 
-	pub fn create_metadata(metadata_path: &PathBuf, file_path: &PathBuf, id: &u32) -> Result<HtmlMetaData, String> {
-		match create_dir_and_file(&metadata_path) {
-			Ok(_) => (),
-			Err(error) => return Err(error)
+	/// Check whether the stylesheets the document links to have to be found again, even though the document itself hasn't changed.
+	/// That is when one of them no longer has the same id at its path, e.g. it was deleted or moved, or is one of the `changed_sheets` that were parsed again, as what it imports could have changed.
+	pub fn has_changed_links(&self, workspace_metadata: &WorkspaceMetaData, changed_sheets: &HashSet<u32>) -> bool {
+		let sheets = match &self.css_sheets {
+			Some(value) => value,
+			None => return false
 		};
 
+		sheets.iter().any(|sheet| changed_sheets.contains(&sheet.id) || workspace_metadata.get_css_file_id(&PathBuf::from(&sheet.absolute_path)) != Some(sheet.id))
+	}
+
+	pub fn create_metadata(metadata_path: &Path, file_path: &Path, id: &u32) -> Result<HtmlMetaData, BhcError> {
 		let mut metadata = HtmlMetaData::new();
 
         metadata.id = *id;
-		metadata.file_name = file_path.file_name().unwrap().to_str().unwrap().to_string();
-		metadata.absolute_path = file_path.to_str().unwrap().to_string();
+		(metadata.absolute_path, metadata.file_name) = path_to_strings(file_path)?;
 		metadata.last_updated = Utc::now();
 
-		match fs::write(&metadata_path, serde_json::to_string_pretty(&metadata).unwrap()) {
-			Ok(_) => return Ok(metadata),
-			Err(error) => return Err(format!("Error writing metadata to file: ({:?}) {:?}", &metadata_path, error))
-		};
+		write_metadata(metadata_path, &metadata)?;
+
+		Ok(metadata)
 	}
 	
-	pub fn update_metadata(&mut self, metadata_path: &PathBuf, workspace_metadata: &WorkspaceMetaData) -> Result<WorkspaceHtmlFile, String> {
+	pub fn update_metadata(&mut self, metadata_path: &Path, workspace_metadata: &WorkspaceMetaData) -> Result<WorkspaceHtmlFile, BhcError> {
         let mut new_metadata = self.clone();
 
 		let new_workspace_metadata = match new_metadata.update_css_sheets(workspace_metadata) {
@@ -117,10 +177,280 @@ impl HtmlMetaData {
 
         new_metadata.last_updated = Utc::now();
 
-        match fs::write(&metadata_path, serde_json::to_string_pretty(&new_metadata).unwrap()) {
-			Ok(_) => return Ok(new_workspace_metadata),
-			Err(error) => return Err(format!("Error writing metadata to file: ({:?}) {:?}", &metadata_path, error))
+        write_metadata(metadata_path, &new_metadata)?;
+
+		Ok(new_workspace_metadata)
+	}
+
+}
+
+/// The owner of styles that are written inside of a HTML document, the same as the `source` of a `CssAttribute` for inline styles. CSS files start at 1 so it can't clash.
+pub const INLINE_STYLE_ID: u32 = 0;
+
+/// Put the `linked` stylesheets of the HTML document at `html_path` together with the styles written inside of it, in the order they cascade in, so they can be merged into a `FormattedCssFile`.
+/// `linked` is in the order from `get_css_file_paths`. Each `<style>` element is a stylesheet owned by [`INLINE_STYLE_ID`] that goes after the stylesheets linked before it,
+/// and each `style` attribute is a stylesheet of its own after all of them, as it is a level of the cascade above every selector.
+pub fn get_document_stylesheets(html_path: &Path, html_string: &str, linked: Vec<CssMetaData>) -> Vec<CssMetaData> {
+	let links = parse_stylesheet_links(&html_path.to_path_buf(), html_string);
+
+	let mut linked = linked;
+	let mut stylesheets: Vec<CssMetaData> = Vec::new();
+	let mut attribute_sheets: Vec<CssMetaData> = Vec::new();
+
+	for sheet in parse_inline_sheets(html_string) {
+		let css_metadata = get_inline_metadata(html_path, sheet.styles);
+
+		if sheet.is_style_attribute {
+			attribute_sheets.push(css_metadata);
+			continue
+		}
+
+		// a linked stylesheet comes after the sheets it imports, so everything up to the last one linked before the element goes first
+		let linked_before = links
+		.iter()
+		.filter(|link| link.href_range.start < sheet.start)
+		.filter_map(|link| match &link.target {
+			LinkTarget::Local(path) => linked.iter().position(|css_metadata| Path::new(&css_metadata.absolute_path) == path),
+			_ => None
+		})
+		.max()
+		.map_or(0, |index| index + 1);
+
+		stylesheets.extend(linked.drain(..linked_before));
+		stylesheets.push(css_metadata);
+	}
+
+	stylesheets.extend(linked);
+	stylesheets.extend(attribute_sheets);
+
+	stylesheets
+}
+
+/// Make a `CssMetaData` owned by [`INLINE_STYLE_ID`] for `styles` written inside of the HTML document at `html_path`.
+fn get_inline_metadata(html_path: &Path, styles: Vec<CssStyle>) -> CssMetaData {
+	let mut css_metadata = CssMetaData::new();
+	css_metadata.id = INLINE_STYLE_ID;
+	css_metadata.file_name = html_path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+	css_metadata.absolute_path = html_path.to_str().unwrap_or_default().to_string();
+	css_metadata.styles = Some(styles);
+
+	css_metadata
+}
+
+/// Get the styles of every `<style>` element and `style` attribute in `html_string`, with every declaration given a `source` of [`INLINE_STYLE_ID`].
+/// A `style` attribute becomes a style for a selector made from the element, e.g. `<p class="intro" style="color: red">` becomes `p.intro { color: red; }`, marked with `is_style_attribute`.
+/// Returns `None` if there are no inline styles.
+pub fn parse_inline_styles(html_string: &str) -> Option<Vec<CssStyle>> {
+	let styles: Vec<CssStyle> = parse_inline_sheets(html_string)
+	.into_iter()
+	.flat_map(|sheet| sheet.styles)
+	.collect();
+
+	if styles.is_empty() {
+		None
+	} else {
+		Some(styles)
+	}
+}
+
+/// The styles of a single `<style>` element or `style` attribute
+struct InlineSheet {
+	start: Position, // where its element starts in the document
+	is_style_attribute: bool,
+	styles: Vec<CssStyle>,
+}
+
+/// Get a sheet for every `<style>` element and `style` attribute in `html_string` that has any styles, in the order they are written. See [`parse_inline_styles`].
+fn parse_inline_sheets(html_string: &str) -> Vec<InlineSheet> {
+	let mut sheets: Vec<InlineSheet> = Vec::new();
+
+	for element in parse_elements(html_string) {
+		if let Some((start, end)) = style_block_range(html_string, &element) {
+
+			let media = element
+			.get_attribute("media")
+			.map(|media| media.value.trim().to_string())
+			.filter(|media| !media.is_empty() && !media.eq_ignore_ascii_case("all"));
+
+			let mut styles = parse_css(&html_string[start..end], offset_to_position(html_string, start));
+
+			if let Some(media) = &media {
+				for style in &mut styles {
+					style.conditions.insert(0, CssCondition::new("media", media));
+				}
+			}
+
+			if !styles.is_empty() {
+				sheets.push(InlineSheet { start: element.range.start, is_style_attribute: false, styles });
+			}
+		}
+
+		let style_attribute = match element.get_attribute("style") {
+			Some(value) => value,
+			None => continue
+		};
+
+		let value_start = match style_attribute.value_range {
+			Some(value) => value.start,
+			None => continue
 		};
+
+		// the declarations are wrapped in a rule to parse them, so the origin is moved back by the length of `*{`
+		let origin = Position::new(value_start.line, value_start.character.saturating_sub(2));
+
+		let styles: Vec<CssStyle> = parse_css(&format!("*{{{}}}", style_attribute.value), origin)
+		.into_iter()
+		.map(|style| {
+			let mut element_style = CssStyle::from_selector(&element_selector(&element));
+			element_style.attributes = style.attributes;
+			element_style.range = Some(element.range);
+			element_style.selector_range = Some(element.name_range);
+			element_style.is_style_attribute = true;
+
+			element_style
+		})
+		.collect();
+
+		if !styles.is_empty() {
+			sheets.push(InlineSheet { start: element.range.start, is_style_attribute: true, styles });
+		}
 	}
 
+	sheets
+}
+
+/// Get the byte range of the contents of the `<style>` element, the `element` is its start tag in `html_string`.
+/// Returns `None` if the element isn't a `<style>`.
+fn style_block_range(html_string: &str, element: &HtmlElement) -> Option<(usize, usize)> {
+	if element.name != "style" {
+		return None
+	}
+
+	let start = position_to_offset(html_string, element.range.end)?;
+
+	// the contents are raw text, so the first closing tag ends it
+	let end = match html_string[start..].to_ascii_lowercase().find("</style") {
+		Some(value) => start + value,
+		None => html_string.len()
+	};
+
+	Some((start, end))
+}
+
+/// Find the range of every selector that uses the `symbol` inside of the `<style>` elements of `html_string`. See [`find_symbol_ranges`].
+pub fn find_inline_symbol_ranges(html_string: &str, symbol: &HtmlSymbol) -> Vec<Range> {
+	let mut ranges: Vec<Range> = Vec::new();
+
+	for element in parse_elements(html_string) {
+		let (start, end) = match style_block_range(html_string, &element) {
+			Some(value) => value,
+			None => continue
+		};
+
+		let origin = offset_to_position(html_string, start);
+
+		ranges.extend(find_symbol_ranges(&html_string[start..end], symbol).into_iter().map(|range| Range::new(translate_position(range.start, origin), translate_position(range.end, origin))));
+	}
+
+	ranges
+}
+
+/// Parse `css_string` with every range moved to where it is in the HTML document, which `css_string` starts at `origin` in.
+fn parse_css(css_string: &str, origin: Position) -> Vec<CssStyle> {
+	let mut parser_input = ParserInput::new(css_string);
+	let mut parser = Parser::new(&mut parser_input);
+
+	let mut styles = match parse_sheet(&mut parser) {
+		Some(value) => value,
+		None => return Vec::new()
+	};
+
+	let translate_range = |range: Range| Range::new(translate_position(range.start, origin), translate_position(range.end, origin));
+
+	for style in &mut styles {
+		style.range = style.range.map(translate_range);
+		style.selector_range = style.selector_range.map(translate_range);
+
+		for attribute in &mut style.attributes {
+			attribute.ranges = attribute.ranges.iter().map(|range| translate_range(*range)).collect();
+			attribute.source = Some(INLINE_STYLE_ID);
+		}
+	}
+
+	styles
+}
+
+/// Make a selector that describes the `element`, using its id if it has one or its classes otherwise.
+fn element_selector(element: &HtmlElement) -> String {
+	let mut selector = element.name.clone();
+
+	if let Some((id, _)) = element.id() {
+		selector.push('#');
+		selector.push_str(&id);
+	} else {
+		for (class, _) in element.classes() {
+			selector.push('.');
+			selector.push_str(&class);
+		}
+	}
+
+	selector
+}
+
+#[cfg(test)]
+mod tests {
+	use tower_lsp::lsp_types::{Position, Range};
+
+	use std::collections::HashSet;
+
+	use crate::metadata::{css_metadata::{css_at_rule::CssCondition, css_file::CssFile}, workspace_metadata::{workspace_css_file::WorkspaceCssFile, WorkspaceMetaData}};
+
+	use super::{parse_inline_styles, HtmlMetaData, INLINE_STYLE_ID};
+
+	#[test]
+	fn parse_inline_styles_test() {
+		let html_string = "<head>\n\t<style>h1 { color: red; }</style>\n\t<style media=\"print\">\nh1 { color: black; }\n</STYLE>\n</head>\n<body>\n\t<p class=\"intro lead\" style=\"margin: 0; color: blue !important\">Text</p>\n</body>";
+
+		let styles = parse_inline_styles(html_string).unwrap();
+
+		let tags: Vec<&str> = styles.iter().map(|style| style.tag.as_str()).collect();
+
+		assert_eq!(tags, vec!["h1", "h1", "p.intro.lead"]);
+		assert_eq!(styles[0].attributes[0].ranges, vec![Range::new(Position::new(1, 20), Position::new(1, 23))]);
+		assert_eq!(styles[1].conditions, vec![CssCondition::new("media", "print")]);
+		assert_eq!(styles[1].attributes[0].ranges, vec![Range::new(Position::new(3, 12), Position::new(3, 17))]);
+
+		let paragraph = &styles[2];
+
+		assert_eq!(paragraph.attributes.iter().map(|attribute| attribute.name.as_str()).collect::<Vec<&str>>(), vec!["margin", "color"]);
+		assert_eq!(paragraph.attributes[1].ranges, vec![Range::new(Position::new(7, 48), Position::new(7, 52))]);
+		assert!(paragraph.attributes[1].is_important(0));
+		assert!(paragraph.attributes.iter().all(|attribute| attribute.source == Some(INLINE_STYLE_ID)));
+		assert!(paragraph.is_style_attribute);
+		assert!(!styles[0].is_style_attribute);
+
+		assert_eq!(parse_inline_styles("<p>No styles</p>"), None);
+	}
+
+	#[test]
+	fn has_changed_links_test() {
+		let mut workspace_metadata = WorkspaceMetaData::new();
+		let mut css_file = WorkspaceCssFile::new();
+		css_file.id = 1;
+		css_file.absolute_path = "/site/css/base.css".to_string();
+		workspace_metadata.add_css_file(css_file);
+
+		let mut html_metadata = HtmlMetaData::new();
+		assert!(!html_metadata.has_changed_links(&workspace_metadata, &HashSet::new()));
+
+		html_metadata.css_sheets = Some(vec![CssFile { id: 1, file_name: "base.css".to_string(), absolute_path: "/site/css/base.css".to_string(), media: None, conditions: Vec::new() }]);
+		assert!(!html_metadata.has_changed_links(&workspace_metadata, &HashSet::new()));
+
+		// the stylesheet was parsed again
+		assert!(html_metadata.has_changed_links(&workspace_metadata, &HashSet::from([1])));
+
+		// the stylesheet was deleted, or moved somewhere else
+		workspace_metadata.css_files[0].absolute_path = "/site/styles/base.css".to_string();
+		assert!(html_metadata.has_changed_links(&workspace_metadata, &HashSet::new()));
+	}
 }
@@ -0,0 +1,415 @@
+use std::{cell::Cell, collections::VecDeque, convert::Infallible, rc::Rc};
+
+use html5gum::{naive_next_state, Emitter, Error, Reader, State, Tokenizer};
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::position::{offset_to_position, range_contains};
+
+/// A start tag inside of a HTML document, along with where each of its parts are in the document.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HtmlElement {
+    pub name: String,
+    pub range: Range, // from the `<` to the `>` of the start tag
+    pub name_range: Range,
+    pub attributes: Vec<HtmlAttribute>, // in the order they are written, duplicates are kept
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HtmlAttribute {
+    pub name: String,
+    pub value: String,
+    pub name_range: Range,
+    pub value_range: Option<Range>, // the value without its quotes, None if the attribute has no value or the value is empty
+}
+
+impl HtmlElement {
+    /// Get the first attribute called `name`, the same one a browser would use
+    pub fn get_attribute(&self, name: &str) -> Option<&HtmlAttribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    /// Get each class in the `class` attribute along with its range
+    pub fn classes(&self) -> Vec<(String, Range)> {
+        match self.get_attribute("class") {
+            Some(attribute) => attribute.split_value(),
+            None => Vec::new()
+        }
+    }
+
+    /// Get the `id` of the element along with its range
+    pub fn id(&self) -> Option<(String, Range)> {
+        self.get_attribute("id")?.split_value().into_iter().next()
+    }
+}
+
+impl HtmlAttribute {
+    /// Split the value on whitespace, like the `class` attribute is, and get the range of each part, which can be on a later line than the value starts on.
+    pub fn split_value(&self) -> Vec<(String, Range)> {
+        let value_range = match self.value_range {
+            Some(value) => value,
+            None => return Vec::new()
+        };
+
+        let mut parts: Vec<(String, Range)> = Vec::new();
+        let mut part = String::new();
+        let mut part_start = value_range.start;
+        let mut position = value_range.start;
+
+        for character in self.value.chars() {
+            if character.is_ascii_whitespace() {
+                if !part.is_empty() {
+                    parts.push((std::mem::take(&mut part), Range::new(part_start, position)));
+                }
+
+                position = if character == '\n' { Position::new(position.line + 1, 0) } else { Position::new(position.line, position.character + 1) };
+            } else {
+                if part.is_empty() {
+                    part_start = position;
+                }
+
+                part.push(character);
+                position.character += character.len_utf16() as u32;
+            }
+        }
+
+        if !part.is_empty() {
+            parts.push((part, Range::new(part_start, position)));
+        }
+
+        parts
+    }
+}
+
+/// The part of a HTML element that is at a position in the document
+#[derive(Debug, PartialEq, Clone)]
+pub enum HtmlSymbol {
+    Element(String),
+    Class(String),
+    Id(String),
+}
+
+/// Find the element whose start tag contains `position`, and what part of the element it is.
+/// Returns `None` if the position isn't on the element name, or on one of the classes or ids of an element.
+pub fn symbol_at_position(elements: &[HtmlElement], position: Position) -> Option<(&HtmlElement, HtmlSymbol, Range)> {
+    let element = elements.iter().find(|element| range_contains(&element.range, position))?;
+
+    if range_contains(&element.name_range, position) {
+        return Some((element, HtmlSymbol::Element(element.name.clone()), element.name_range))
+    }
+
+    if let Some((class, range)) = element.classes().into_iter().find(|(_, range)| range_contains(range, position)) {
+        return Some((element, HtmlSymbol::Class(class), range))
+    }
+
+    if let Some((id, range)) = element.id().filter(|(_, range)| range_contains(range, position)) {
+        return Some((element, HtmlSymbol::Id(id), range))
+    }
+
+    None
+}
+
+/// Get every start tag in the `html_string`, in the order they are written.
+/// The html5gum tokenizer doesn't keep track of where tokens are, so the reader counts how far through the document the tokenizer is, and the emitter uses that to find each part of the tag.
+pub fn parse_elements(html_string: &str) -> Vec<HtmlElement> {
+    let offset = Rc::new(Cell::new(0));
+
+    let reader = OffsetReader {
+        input: html_string.as_bytes(),
+        offset: offset.clone(),
+    };
+
+    let emitter = OffsetEmitter {
+        offset,
+        ..OffsetEmitter::default()
+    };
+
+    Tokenizer::<OffsetReader, OffsetEmitter>::new_with_emitter(reader, emitter)
+    .infallible()
+    .map(|element| element.into_element(html_string))
+    .collect()
+}
+
+/// A start tag where every part is a byte offset into the document
+#[derive(Debug, Default)]
+struct OffsetElement {
+    name: String,
+    start: usize,
+    end: usize,
+    name_span: (usize, usize),
+    attributes: Vec<OffsetAttribute>,
+}
+
+#[derive(Debug, Default)]
+struct OffsetAttribute {
+    name: String,
+    value: String,
+    name_span: (usize, usize),
+    value_span: Option<(usize, usize)>,
+}
+
+impl OffsetElement {
+    fn into_element(self, html_string: &str) -> HtmlElement {
+        let to_range = |(start, end): (usize, usize)| Range::new(offset_to_position(html_string, start), offset_to_position(html_string, end));
+
+        HtmlElement {
+            name: self.name,
+            range: to_range((self.start, self.end)),
+            name_range: to_range(self.name_span),
+            attributes: self
+            .attributes
+            .into_iter()
+            .map(|attribute| HtmlAttribute {
+                name: attribute.name,
+                value: attribute.value,
+                name_range: to_range(attribute.name_span),
+                value_range: attribute.value_span.map(to_range),
+            })
+            .collect(),
+        }
+    }
+}
+
+/// Reads the document the same way as html5gum's `StringReader`, but also shares how many bytes have been read
+struct OffsetReader<'a> {
+    input: &'a [u8],
+    offset: Rc<Cell<usize>>,
+}
+
+impl<'a> OffsetReader<'a> {
+    fn consume(&mut self, length: usize) -> &'a [u8] {
+        let (consumed, remaining) = self.input.split_at(length);
+
+        self.input = remaining;
+        self.offset.set(self.offset.get() + length);
+
+        consumed
+    }
+}
+
+impl<'a> Reader for OffsetReader<'a> {
+    type Error = Infallible;
+
+    fn read_byte(&mut self) -> Result<Option<u8>, Self::Error> {
+        if self.input.is_empty() {
+            return Ok(None)
+        }
+
+        Ok(Some(self.consume(1)[0]))
+    }
+
+    fn read_until<'b>(&'b mut self, needle: &[u8], _: &'b mut [u8; 4]) -> Result<Option<&'b [u8]>, Self::Error> {
+        if self.input.is_empty() {
+            return Ok(None)
+        }
+
+        let length = match self.input.iter().position(|byte| needle.contains(byte)) {
+            Some(0) => 1,
+            Some(value) => value,
+            None => self.input.len(),
+        };
+
+        Ok(Some(self.consume(length)))
+    }
+
+    fn try_read_string(&mut self, string: &[u8], case_sensitive: bool) -> Result<bool, Self::Error> {
+        match self.input.get(..string.len()) {
+            Some(next) if next == string || (!case_sensitive && next.eq_ignore_ascii_case(string)) => {
+                self.consume(string.len());
+                Ok(true)
+            },
+            _ => Ok(false)
+        }
+    }
+}
+
+/// Emits only start tags, recording where each part of them is with the offset shared by the `OffsetReader`.
+/// Every time a piece of a tag is pushed, the reader has just read it, so the offset is at the end of that piece.
+#[derive(Debug, Default)]
+struct OffsetEmitter {
+    offset: Rc<Cell<usize>>,
+    current_element: Option<OffsetElement>, // None while inside of an end tag, comment or doctype
+    current_end_tag: String,
+    last_start_tag: String,
+    emitted_elements: VecDeque<OffsetElement>,
+}
+
+impl OffsetEmitter {
+    fn span_of(&self, pushed: &[u8], span: Option<(usize, usize)>) -> (usize, usize) {
+        let end = self.offset.get();
+
+        match span {
+            Some((start, _)) => (start, end),
+            None => (end.saturating_sub(pushed.len()), end),
+        }
+    }
+}
+
+impl Emitter for OffsetEmitter {
+    type Token = OffsetElement;
+
+    fn set_last_start_tag(&mut self, last_start_tag: Option<&[u8]>) {
+        self.last_start_tag = String::from_utf8_lossy(last_start_tag.unwrap_or_default()).to_string();
+    }
+
+    fn emit_eof(&mut self) {}
+
+    fn emit_error(&mut self, _: Error) {}
+
+    fn should_emit_errors(&mut self) -> bool {
+        false
+    }
+
+    fn pop_token(&mut self) -> Option<Self::Token> {
+        self.emitted_elements.pop_front()
+    }
+
+    fn emit_string(&mut self, _: &[u8]) {}
+
+    fn init_start_tag(&mut self) {
+        // the `<` and the first letter of the name have been read
+        self.current_element = Some(OffsetElement {
+            start: self.offset.get().saturating_sub(2),
+            ..OffsetElement::default()
+        });
+    }
+
+    fn init_end_tag(&mut self) {
+        self.current_element = None;
+        self.current_end_tag = String::new();
+    }
+
+    fn init_comment(&mut self) {
+        self.current_element = None;
+    }
+
+    fn emit_current_tag(&mut self) -> Option<State> {
+        match self.current_element.take() {
+            Some(mut element) => {
+                element.end = self.offset.get();
+
+                let next_state = naive_next_state(element.name.as_bytes());
+
+                self.last_start_tag = element.name.clone();
+                self.emitted_elements.push_back(element);
+
+                next_state
+            },
+            None => {
+                self.last_start_tag = String::new();
+
+                None
+            }
+        }
+    }
+
+    fn emit_current_comment(&mut self) {}
+
+    fn emit_current_doctype(&mut self) {}
+
+    fn set_self_closing(&mut self) {}
+
+    fn set_force_quirks(&mut self) {}
+
+    fn push_tag_name(&mut self, name: &[u8]) {
+        match &self.current_element {
+            Some(element) => {
+                let span = self.span_of(name, if element.name.is_empty() { None } else { Some(element.name_span) });
+
+                if let Some(element) = &mut self.current_element {
+                    element.name.push_str(&String::from_utf8_lossy(name));
+                    element.name_span = span;
+                }
+            },
+            None => self.current_end_tag.push_str(&String::from_utf8_lossy(name)),
+        }
+    }
+
+    fn push_comment(&mut self, _: &[u8]) {}
+
+    fn push_doctype_name(&mut self, _: &[u8]) {}
+
+    fn init_doctype(&mut self) {
+        self.current_element = None;
+    }
+
+    fn init_attribute(&mut self) {
+        if let Some(element) = &mut self.current_element {
+            element.attributes.push(OffsetAttribute::default());
+        }
+    }
+
+    fn push_attribute_name(&mut self, name: &[u8]) {
+        let span = match self.current_element.as_ref().and_then(|element| element.attributes.last()) {
+            Some(attribute) => self.span_of(name, if attribute.name.is_empty() { None } else { Some(attribute.name_span) }),
+            None => return
+        };
+
+        if let Some(attribute) = self.current_element.as_mut().and_then(|element| element.attributes.last_mut()) {
+            attribute.name.push_str(&String::from_utf8_lossy(name).to_ascii_lowercase());
+            attribute.name_span = span;
+        }
+    }
+
+    fn push_attribute_value(&mut self, value: &[u8]) {
+        let span = match self.current_element.as_ref().and_then(|element| element.attributes.last()) {
+            Some(attribute) => self.span_of(value, attribute.value_span),
+            None => return
+        };
+
+        if let Some(attribute) = self.current_element.as_mut().and_then(|element| element.attributes.last_mut()) {
+            attribute.value.push_str(&String::from_utf8_lossy(value));
+            attribute.value_span = Some(span);
+        }
+    }
+
+    fn set_doctype_public_identifier(&mut self, _: &[u8]) {}
+
+    fn set_doctype_system_identifier(&mut self, _: &[u8]) {}
+
+    fn push_doctype_public_identifier(&mut self, _: &[u8]) {}
+
+    fn push_doctype_system_identifier(&mut self, _: &[u8]) {}
+
+    fn current_is_appropriate_end_tag_token(&mut self) -> bool {
+        self.current_element.is_none() && !self.last_start_tag.is_empty() && self.current_end_tag == self.last_start_tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{Position, Range};
+
+    use super::{parse_elements, symbol_at_position, HtmlSymbol};
+
+    fn range(start_line: u32, start_character: u32, end_line: u32, end_character: u32) -> Range {
+        Range::new(Position::new(start_line, start_character), Position::new(end_line, end_character))
+    }
+
+    #[test]
+    fn parse_elements_test() {
+        let html_string = "<!DOCTYPE html>\n<html>\n<head>\n\t<link rel=stylesheet href='css/base.css'>\n\t<style>p > a { color: red; }</style>\n</head>\n<body>\n\t<div id=\"main\" class=\"card  btn-primary\">Text</div>\n</body>\n</html>";
+
+        let elements = parse_elements(html_string);
+
+        let names: Vec<&str> = elements.iter().map(|element| element.name.as_str()).collect();
+
+        assert_eq!(names, vec!["html", "head", "link", "style", "body", "div"]);
+
+        let link = &elements[2];
+
+        assert_eq!(link.range, range(3, 1, 3, 42));
+        assert_eq!(link.name_range, range(3, 2, 3, 6));
+        assert_eq!(link.get_attribute("rel").unwrap().value_range, Some(range(3, 11, 3, 21)));
+        assert_eq!(link.get_attribute("href").unwrap().value, "css/base.css");
+        assert_eq!(link.get_attribute("href").unwrap().value_range, Some(range(3, 28, 3, 40)));
+
+        let div = &elements[5];
+
+        assert_eq!(div.id(), Some((String::from("main"), range(7, 10, 7, 14))));
+        assert_eq!(div.classes(), vec![(String::from("card"), range(7, 23, 7, 27)), (String::from("btn-primary"), range(7, 29, 7, 40))]);
+
+        assert_eq!(symbol_at_position(&elements, Position::new(7, 30)).map(|(_, symbol, _)| symbol), Some(HtmlSymbol::Class(String::from("btn-primary"))));
+        assert_eq!(symbol_at_position(&elements, Position::new(7, 2)).map(|(_, symbol, _)| symbol), Some(HtmlSymbol::Element(String::from("div"))));
+        assert_eq!(symbol_at_position(&elements, Position::new(7, 17)), None);
+    }
+}
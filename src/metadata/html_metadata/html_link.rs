@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use tower_lsp::lsp_types::{Range, Url};
+
+use crate::file::find_absolute_path;
+
+use super::html_element::{parse_elements, HtmlElement};
+
+/// A `<link>` in a HTML document that applies a stylesheet to it
+#[derive(Debug, PartialEq, Clone)]
+pub struct StylesheetLink {
+    pub href: String,
+    pub href_range: Range, // the value of the href, or the attribute name if the value is empty
+    pub target: LinkTarget,
+    pub media: Option<String>, // None if the stylesheet applies to all media
+}
+
+/// Where the `href` of a stylesheet link points to
+#[derive(Debug, PartialEq, Clone)]
+pub enum LinkTarget {
+    Local(PathBuf), // a file on disk, which may or may not exist
+    Remote(String), // e.g. `https://cdn.example.com/style.css`
+    ProtocolRelative(String), // e.g. `//cdn.example.com/style.css`, which uses the protocol of the page
+    Data, // the stylesheet is inside of the `data:` URL itself
+}
+
+/// Get the links in `html_string` that a browser would apply as stylesheets, in the order they are written.
+/// A link has to have a `rel` of `stylesheet`, and is skipped if it is an alternate stylesheet, is `disabled`, has a `type` other than `text/css`, or has no `href`.
+/// Relative hrefs are resolved against `html_path`, which can be the document or the folder it is in.
+pub fn parse_stylesheet_links(html_path: &PathBuf, html_string: &str) -> Vec<StylesheetLink> {
+    parse_elements(html_string)
+    .iter()
+    .filter(|element| is_stylesheet_link(element))
+    .filter_map(|element| {
+        let href = element.get_attribute("href")?;
+        let value = href.value.trim();
+
+        if value.is_empty() {
+            return None
+        }
+
+        let media = element
+        .get_attribute("media")
+        .map(|media| media.value.trim().to_string())
+        .filter(|media| !media.is_empty() && !media.eq_ignore_ascii_case("all"));
+
+        Some(StylesheetLink {
+            href: value.to_string(),
+            href_range: href.value_range.unwrap_or(href.name_range),
+            target: classify_href(html_path, value),
+            media,
+        })
+    })
+    .collect()
+}
+
+fn is_stylesheet_link(element: &HtmlElement) -> bool {
+    if element.name != "link" || element.get_attribute("disabled").is_some() {
+        return false
+    }
+
+    let rel: Vec<String> = match element.get_attribute("rel") {
+        Some(value) => value.value.split_ascii_whitespace().map(|part| part.to_ascii_lowercase()).collect(),
+        None => return false
+    };
+
+    // alternate stylesheets are only applied when the user picks them
+    if !rel.contains(&String::from("stylesheet")) || rel.contains(&String::from("alternate")) {
+        return false
+    }
+
+    // the type can have parameters, e.g. `text/css; charset=utf-8`
+    match element.get_attribute("type") {
+        Some(value) => {
+            let mime_type = value.value.split(';').next().unwrap_or_default().trim();
+            mime_type.is_empty() || mime_type.eq_ignore_ascii_case("text/css")
+        },
+        None => true
+    }
+}
+
+/// Work out where `href` points to. Local paths have their query and fragment removed before being resolved against `html_path`.
+/// Also used for the URLs of `@import` rules, in which case `html_path` is the importing stylesheet.
+pub fn classify_href(html_path: &PathBuf, href: &str) -> LinkTarget {
+    if href.starts_with("//") {
+        return LinkTarget::ProtocolRelative(href.to_string())
+    }
+
+    // a scheme has at least two characters, so Windows paths such as `C:/css/base.css` aren't mistaken for one
+    if let Some((scheme, _)) = href.split_once(':') {
+        let is_scheme = scheme.len() > 1
+            && scheme.starts_with(|character: char| character.is_ascii_alphabetic())
+            && scheme.chars().all(|character| character.is_ascii_alphanumeric() || character == '+' || character == '-' || character == '.');
+
+        if is_scheme {
+            if scheme.eq_ignore_ascii_case("data") {
+                return LinkTarget::Data
+            }
+
+            if scheme.eq_ignore_ascii_case("file") {
+                if let Some(path) = Url::parse(href).ok().and_then(|url| url.to_file_path().ok()) {
+                    return LinkTarget::Local(path)
+                }
+            }
+
+            return LinkTarget::Remote(href.to_string())
+        }
+    }
+
+    let path = href.split(['?', '#']).next().unwrap_or_default();
+    let pathbuf = PathBuf::from(path);
+
+    LinkTarget::Local(find_absolute_path(html_path, &pathbuf).unwrap_or(pathbuf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tower_lsp::lsp_types::{Position, Range};
+
+    use super::{parse_stylesheet_links, LinkTarget};
+
+    #[test]
+    fn parse_stylesheet_links_test() {
+        let html_path = PathBuf::from("/workspace/html");
+
+        let html_string = r#"<link rel="icon" href="favicon.ico">
+<link rel="preload" href="font.woff2" as="font">
+<link rel="canonical" href="https://example.com/">
+<link rel="manifest" href="site.webmanifest">
+<link rel="Stylesheet" href="../css/base.css?v=2" media="print">
+<link rel="alternate stylesheet" href="dark.css" title="Dark">
+<link rel="stylesheet" href="disabled.css" disabled>
+<link rel="stylesheet" href="style.less" type="text/less">
+<link rel="stylesheet" href="https://cdn.example.com/style.css" media="all">
+<link rel="stylesheet" href="//cdn.example.com/style.css">
+<link rel="stylesheet" href="data:text/css,h1{color:red}">
+<link rel="stylesheet" href="">"#;
+
+        let links = parse_stylesheet_links(&html_path, html_string);
+
+        let targets: Vec<LinkTarget> = links.iter().map(|link| link.target.clone()).collect();
+
+        assert_eq!(targets, vec![
+            LinkTarget::Local(PathBuf::from("/workspace/css/base.css")),
+            LinkTarget::Remote(String::from("https://cdn.example.com/style.css")),
+            LinkTarget::ProtocolRelative(String::from("//cdn.example.com/style.css")),
+            LinkTarget::Data,
+        ]);
+
+        assert_eq!(links[0].href_range, Range::new(Position::new(4, 29), Position::new(4, 48)));
+        assert_eq!(links[0].media, Some(String::from("print")));
+        assert_eq!(links[1].media, None);
+    }
+}
@@ -0,0 +1,669 @@
+use std::{collections::{hash_map::Entry, HashMap}, ffi::OsStr, fs, io::ErrorKind, path::{Path, PathBuf}, sync::Arc};
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::{error::{path_to_strings, BhcError}, file::write_file_atomic, CSS_METADATA_PATH, EXT_CSS, EXT_HTML, HTML_METADATA_PATH, METADATA_PATH};
+
+use super::{css_metadata::CssMetaData, read_source, serialize_metadata, FileStamp, html_metadata::HtmlMetaData, workspace_metadata::{id_to_json_file_name, open_workspace_metadata, workspace_css_file::WorkspaceCssFile, workspace_html_file::WorkspaceHtmlFile, WorkspaceMetaData}};
+
+/// Every piece of metadata of a single workspace, held in memory so requests don't have to read `.bhc/.meta` again.
+/// The fields are what is saved on disk, the changes of documents that are open but not saved are kept apart in `unsaved` and are used in their place when reading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceIndex {
+	pub workspace_metadata: WorkspaceMetaData,
+	pub css_metadata: HashMap<u32, CssMetaData>,
+	pub html_metadata: HashMap<u32, HtmlMetaData>,
+	unsaved: UnsavedMetadata,
+}
+
+/// The metadata of the documents that are open with unsaved changes, by id. It is never written to disk, so nothing that is typed but not saved can end up there.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct UnsavedMetadata {
+	css_metadata: HashMap<u32, CssMetaData>,
+	html_metadata: HashMap<u32, HtmlMetaData>,
+	html_files: HashMap<u32, WorkspaceHtmlFile>, // which stylesheets each document links to
+}
+
+impl WorkspaceIndex {
+	/// Read the metadata of the workspace at `workspace_path`, and the metadata of every file it lists.
+	/// Files whose metadata is missing or can't be read are left out, the same as if they had never been indexed.
+	/// Returns `Err(BhcError)` if the workspace metadata itself could not be read.
+	pub fn load(workspace_path: &Path) -> Result<WorkspaceIndex, BhcError> {
+		let mut index = WorkspaceIndex {
+			workspace_metadata: open_workspace_metadata(&workspace_path.join(METADATA_PATH))?,
+			css_metadata: HashMap::new(),
+			html_metadata: HashMap::new(),
+			unsaved: UnsavedMetadata::default(),
+		};
+
+		let css_ids: Vec<u32> = index.workspace_metadata.css_files.iter().map(|css_file| css_file.id).collect();
+		let html_ids: Vec<u32> = index.workspace_metadata.html_files.iter().map(|html_file| html_file.id).collect();
+
+		for id in css_ids {
+			let _ = index.reload_css_metadata(workspace_path, &id);
+		}
+
+		for id in html_ids {
+			let _ = index.reload_html_metadata(workspace_path, &id);
+		}
+
+		Ok(index)
+	}
+
+	/// Read the CSS metadata with the `id` from disk again, e.g. after it has been written to.
+	/// Returns `Err(BhcError)` if it could not be read, in which case it is removed from the index.
+	pub fn reload_css_metadata(&mut self, workspace_path: &Path, id: &u32) -> Result<(), BhcError> {
+		match CssMetaData::from_json(&workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(id))) {
+			Ok(value) => {
+				self.css_metadata.insert(*id, value);
+				Ok(())
+			},
+			Err(error) => {
+				self.css_metadata.remove(id);
+				Err(error)
+			}
+		}
+	}
+
+	/// Read the HTML metadata with the `id` from disk again, e.g. after it has been written to.
+	/// Returns `Err(BhcError)` if it could not be read, in which case it is removed from the index.
+	pub fn reload_html_metadata(&mut self, workspace_path: &Path, id: &u32) -> Result<(), BhcError> {
+		match HtmlMetaData::from_json(&workspace_path.join(HTML_METADATA_PATH).join(id_to_json_file_name(id))) {
+			Ok(value) => {
+				self.html_metadata.insert(*id, value);
+				Ok(())
+			},
+			Err(error) => {
+				self.html_metadata.remove(id);
+				Err(error)
+			}
+		}
+	}
+
+	/// Update the metadata of the CSS or HTML file at `file_path` from `contents`, what has just been saved to it with the `stamp` it was read with, keeping everything in memory.
+	/// Updating a HTML document also updates which stylesheets it links to in the workspace metadata.
+	/// Nothing is written here, so the cache isn't held while waiting on the disk. The metadata files that changed are returned to be saved with [`write_metadata_files`].
+	/// Returns `Ok(None)` if the file has no metadata in the workspace, so there was nothing to update.
+	/// Returns `Err(BhcError)` if the metadata could not be serialized, in which case the index is left as it was.
+	pub fn update_file_metadata(&mut self, workspace_path: &Path, file_path: &Path, contents: &str, stamp: Option<FileStamp>) -> Result<Option<Vec<MetadataWrite>>, BhcError> {
+		let file_pathbuf = file_path.to_path_buf();
+
+		if let Some(id) = self.workspace_metadata.get_css_file_id(&file_pathbuf) {
+			let metadata_path = workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&id));
+
+			let mut css_metadata = match self.css_metadata.get(&id) {
+				Some(value) => value.clone(),
+				None => return Err(BhcError::metadata(file_path))
+			};
+
+			css_metadata.parse_contents(contents);
+			css_metadata.source_stamp = stamp;
+
+			// the imports could have changed, and they need the ids of the files they point to
+			css_metadata.update_import_ids(&self.workspace_metadata);
+
+			let write = MetadataWrite::new(metadata_path, &css_metadata)?;
+			self.css_metadata.insert(id, css_metadata);
+			// what was unsaved has now been saved
+			self.unsaved.css_metadata.remove(&id);
+
+			return Ok(Some(vec![write]))
+		}
+
+		if let Some(id) = self.workspace_metadata.get_html_file_id(&file_pathbuf) {
+			let metadata_path = workspace_path.join(HTML_METADATA_PATH).join(id_to_json_file_name(&id));
+
+			let mut html_metadata = match self.html_metadata.get(&id) {
+				Some(value) => value.clone(),
+				None => return Err(BhcError::metadata(file_path))
+			};
+
+			let html_file = html_metadata.parse_contents(contents, &self.workspace_metadata);
+			html_metadata.source_stamp = stamp;
+			html_metadata.last_updated = Utc::now();
+
+			let mut workspace_metadata = self.workspace_metadata.clone();
+
+			if let Some(index) = workspace_metadata.html_files.iter().position(|file| file.id == id) {
+				workspace_metadata.modify_html_file(&html_file, &index)?;
+			}
+
+			workspace_metadata.update_css_references();
+			workspace_metadata.last_updated = Utc::now();
+
+			let writes = vec![
+				MetadataWrite::new(metadata_path, &html_metadata)?,
+				MetadataWrite::new(workspace_path.join(METADATA_PATH), &workspace_metadata)?,
+			];
+
+			self.html_metadata.insert(id, html_metadata);
+			self.workspace_metadata = workspace_metadata;
+			self.unsaved.html_metadata.remove(&id);
+			self.unsaved.html_files.remove(&id);
+
+			return Ok(Some(writes))
+		}
+
+		Ok(None)
+	}
+
+	/// Check whether the CSS or HTML file at `file_path` has metadata in the workspace.
+	pub fn has_file_metadata(&self, file_path: &Path) -> bool {
+		let file_pathbuf = file_path.to_path_buf();
+
+		self.workspace_metadata.get_css_file_id(&file_pathbuf).is_some() || self.workspace_metadata.get_html_file_id(&file_pathbuf).is_some()
+	}
+
+	/// Derive the metadata of the CSS or HTML file at `file_path` from `contents` instead of what is on disk, e.g. a document that is open but not saved.
+	/// It is kept apart from the saved metadata until the document is saved or closed, see [`WorkspaceIndex::discard_document_contents`], so it is never written to disk.
+	/// Returns `false` if the file has no metadata in the workspace.
+	pub fn apply_document_contents(&mut self, file_path: &Path, contents: &str) -> bool {
+		let file_pathbuf = file_path.to_path_buf();
+
+		if let Some(id) = self.workspace_metadata.get_css_file_id(&file_pathbuf) {
+			if let Some(css_metadata) = self.get_css_metadata_by_id(&id) {
+				let mut css_metadata = css_metadata.clone();
+
+				css_metadata.parse_contents(contents);
+				css_metadata.update_import_ids(&self.workspace_metadata);
+
+				self.unsaved.css_metadata.insert(id, css_metadata);
+
+				return true
+			}
+		}
+
+		if let Some(id) = self.workspace_metadata.get_html_file_id(&file_pathbuf) {
+			if let Some(html_metadata) = self.get_html_metadata_by_id(&id) {
+				let mut html_metadata = html_metadata.clone();
+
+				let html_file = html_metadata.parse_contents(contents, &self.workspace_metadata);
+
+				self.unsaved.html_metadata.insert(id, html_metadata);
+				self.unsaved.html_files.insert(id, html_file);
+
+				return true
+			}
+		}
+
+		false
+	}
+
+	/// Throw away the unsaved changes to the CSS or HTML file at `file_path`, e.g. once it is closed without being saved, so its saved metadata is used again.
+	/// Returns `false` if it had none.
+	pub fn discard_document_contents(&mut self, file_path: &Path) -> bool {
+		let file_pathbuf = file_path.to_path_buf();
+
+		if let Some(id) = self.workspace_metadata.get_css_file_id(&file_pathbuf) {
+			return self.unsaved.css_metadata.remove(&id).is_some()
+		}
+
+		if let Some(id) = self.workspace_metadata.get_html_file_id(&file_pathbuf) {
+			self.unsaved.html_files.remove(&id);
+
+			return self.unsaved.html_metadata.remove(&id).is_some()
+		}
+
+		false
+	}
+
+	/// Get the HTML documents that have to be read before the file at `file_path` is added to or removed from the index, see [`WorkspaceIndex::add_file_metadata`].
+	/// For a stylesheet with metadata these are the documents that link to it, for a new stylesheet every document, as any of them could already link to it.
+	/// Returns nothing for other files.
+	pub fn get_linking_documents(&self, file_path: &Path) -> Vec<PathBuf> {
+		if file_path.extension().and_then(OsStr::to_str) != Some(EXT_CSS) {
+			return Vec::new()
+		}
+
+		let html_files = match self.workspace_metadata.get_css_file_id(&file_path.to_path_buf()) {
+			Some(id) => self.workspace_metadata.get_referencing_html_files(&id),
+			None => self.workspace_metadata.html_files.clone()
+		};
+
+		html_files.iter().map(|html_file| PathBuf::from(&html_file.absolute_path)).collect()
+	}
+
+	/// Create the metadata of the CSS or HTML file at `file_path` from `contents`, what it was read as with the `stamp` just after it was created in the workspace, and add it to the workspace metadata.
+	/// Every HTML document is linked again when a stylesheet is created, from what they were read as in `documents`, see [`WorkspaceIndex::get_linking_documents`]. Documents that are missing from it keep the links they had.
+	/// Nothing is written here, so the cache isn't held while waiting on the disk. The metadata files that changed are returned to be saved with [`write_metadata_files`].
+	/// Returns `Ok(None)` if it isn't a CSS or HTML file. A file that already has metadata is updated instead.
+	/// Returns `Err(BhcError)` if the metadata could not be serialized.
+	pub fn add_file_metadata(&mut self, workspace_path: &Path, file_path: &Path, contents: &str, stamp: Option<FileStamp>, documents: &SourceFiles) -> Result<Option<Vec<MetadataWrite>>, BhcError> {
+		if self.has_file_metadata(file_path) {
+			return self.update_file_metadata(workspace_path, file_path, contents, stamp)
+		}
+
+		let mut writes = Vec::new();
+
+		match file_path.extension().and_then(OsStr::to_str) {
+			Some(EXT_CSS) => {
+				let id = self.workspace_metadata.allocate_css_id();
+				let metadata_path = workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&id));
+
+				let mut css_metadata = CssMetaData::from_contents(file_path, &id, contents, stamp)?;
+
+				self.workspace_metadata.add_css_file(WorkspaceCssFile::parse(&css_metadata));
+				css_metadata.update_import_ids(&self.workspace_metadata);
+
+				writes.push(MetadataWrite::new(metadata_path, &css_metadata)?);
+				self.css_metadata.insert(id, css_metadata);
+
+				let html_ids: Vec<u32> = self.workspace_metadata.html_files.iter().map(|html_file| html_file.id).collect();
+
+				for html_id in html_ids {
+					if let Some(write) = self.update_html_metadata(workspace_path, &html_id, documents)? {
+						writes.push(write);
+					}
+				}
+			},
+			Some(EXT_HTML) => {
+				let id = self.workspace_metadata.allocate_html_id();
+				let metadata_path = workspace_path.join(HTML_METADATA_PATH).join(id_to_json_file_name(&id));
+
+				let mut html_metadata = HtmlMetaData::new();
+
+				html_metadata.id = id;
+				(html_metadata.absolute_path, html_metadata.file_name) = path_to_strings(file_path)?;
+
+				let html_file = html_metadata.parse_contents(contents, &self.workspace_metadata);
+				html_metadata.source_stamp = stamp;
+				html_metadata.last_updated = Utc::now();
+
+				self.workspace_metadata.add_html_file(html_file);
+
+				writes.push(MetadataWrite::new(metadata_path, &html_metadata)?);
+				self.html_metadata.insert(id, html_metadata);
+			},
+			_ => return Ok(None)
+		}
+
+		self.workspace_metadata.update_css_references();
+		self.workspace_metadata.last_updated = Utc::now();
+
+		writes.push(MetadataWrite::new(workspace_path.join(METADATA_PATH), &self.workspace_metadata)?);
+
+		Ok(Some(writes))
+	}
+
+	/// Remove the metadata of the CSS or HTML file at `file_path`, which has been deleted from the workspace, from the index and the workspace metadata.
+	/// The HTML documents that linked to a deleted stylesheet are linked again from what they were read as in `documents`, so they no longer include it.
+	/// Nothing is deleted or written here, the metadata files that changed are returned to be saved with [`write_metadata_files`].
+	/// Returns `Ok(None)` if the file had no metadata.
+	/// Returns `Err(BhcError)` if the metadata could not be serialized.
+	pub fn remove_file_metadata(&mut self, workspace_path: &Path, file_path: &Path, documents: &SourceFiles) -> Result<Option<Vec<MetadataWrite>>, BhcError> {
+		let file_pathbuf = file_path.to_path_buf();
+
+		let mut writes = Vec::new();
+
+		if let Some(id) = self.workspace_metadata.get_css_file_id(&file_pathbuf) {
+			writes.push(MetadataWrite::remove(workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&id))));
+
+			let html_ids: Vec<u32> = self.workspace_metadata.get_referencing_html_files(&id).iter().map(|html_file| html_file.id).collect();
+
+			self.workspace_metadata.css_files.retain(|css_file| css_file.id != id);
+			self.css_metadata.remove(&id);
+			self.unsaved.css_metadata.remove(&id);
+
+			for html_id in html_ids {
+				if let Some(write) = self.update_html_metadata(workspace_path, &html_id, documents)? {
+					writes.push(write);
+				}
+			}
+		} else if let Some(id) = self.workspace_metadata.get_html_file_id(&file_pathbuf) {
+			writes.push(MetadataWrite::remove(workspace_path.join(HTML_METADATA_PATH).join(id_to_json_file_name(&id))));
+
+			self.workspace_metadata.html_files.retain(|html_file| html_file.id != id);
+			self.html_metadata.remove(&id);
+			self.unsaved.html_metadata.remove(&id);
+			self.unsaved.html_files.remove(&id);
+		} else {
+			return Ok(None)
+		}
+
+		self.workspace_metadata.update_css_references();
+		self.workspace_metadata.last_updated = Utc::now();
+
+		writes.push(MetadataWrite::new(workspace_path.join(METADATA_PATH), &self.workspace_metadata)?);
+
+		Ok(Some(writes))
+	}
+
+	/// Update which stylesheets the HTML document with the `id` links to from what it was read as in `documents`, along with its entry in the workspace metadata.
+	/// The `html_files` of each stylesheet are left to the caller to rebuild with `WorkspaceMetaData::update_css_references`.
+	/// Returns `Ok(None)` if the document is missing from `documents`, in which case it is left as it was.
+	fn update_html_metadata(&mut self, workspace_path: &Path, id: &u32, documents: &SourceFiles) -> Result<Option<MetadataWrite>, BhcError> {
+		let metadata_path = workspace_path.join(HTML_METADATA_PATH).join(id_to_json_file_name(id));
+
+		let mut html_metadata = match self.html_metadata.get(id) {
+			Some(value) => value.clone(),
+			None => return Err(BhcError::metadata(&metadata_path))
+		};
+
+		let (contents, stamp) = match documents.get(Path::new(&html_metadata.absolute_path)) {
+			Some(value) => value,
+			None => return Ok(None)
+		};
+
+		let html_file = html_metadata.parse_contents(contents, &self.workspace_metadata);
+		html_metadata.source_stamp = *stamp;
+		html_metadata.last_updated = Utc::now();
+
+		if let Some(index) = self.workspace_metadata.html_files.iter().position(|file| &file.id == id) {
+			self.workspace_metadata.modify_html_file(&html_file, &index)?;
+		}
+
+		let write = MetadataWrite::new(metadata_path, &html_metadata)?;
+		self.html_metadata.insert(*id, html_metadata);
+
+		Ok(Some(write))
+	}
+
+	/// Get the metadata of the stylesheet at `file_path`, if it is in the workspace. Unsaved changes are included.
+	pub fn get_css_metadata(&self, file_path: &Path) -> Option<&CssMetaData> {
+		let id = self.workspace_metadata.get_css_file_id(&file_path.to_path_buf())?;
+
+		self.get_css_metadata_by_id(&id)
+	}
+
+	/// Get the metadata of the stylesheet with the `id`. Unsaved changes are included.
+	pub fn get_css_metadata_by_id(&self, id: &u32) -> Option<&CssMetaData> {
+		self.unsaved.css_metadata.get(id).or(self.css_metadata.get(id))
+	}
+
+	/// Get the metadata of the HTML document at `file_path`, if it is in the workspace. Unsaved changes are included.
+	pub fn get_html_metadata(&self, file_path: &Path) -> Option<&HtmlMetaData> {
+		let id = self.workspace_metadata.get_html_file_id(&file_path.to_path_buf())?;
+
+		self.get_html_metadata_by_id(&id)
+	}
+
+	/// Get the metadata of the HTML document with the `id`. Unsaved changes are included.
+	pub fn get_html_metadata_by_id(&self, id: &u32) -> Option<&HtmlMetaData> {
+		self.unsaved.html_metadata.get(id).or(self.html_metadata.get(id))
+	}
+
+	/// Get the workspace metadata with the stylesheets each document links to in its unsaved changes, rather than what is saved.
+	pub fn get_workspace_metadata(&self) -> WorkspaceMetaData {
+		let mut workspace_metadata = self.workspace_metadata.clone();
+
+		if self.unsaved.html_files.is_empty() {
+			return workspace_metadata
+		}
+
+		for html_file in &mut workspace_metadata.html_files {
+			if let Some(unsaved_html_file) = self.unsaved.html_files.get(&html_file.id) {
+				html_file.css_files = unsaved_html_file.css_files.clone();
+			}
+		}
+
+		workspace_metadata.update_css_references();
+
+		workspace_metadata
+	}
+
+	/// Get the metadata of every stylesheet at `file_paths`, in the same order so linked stylesheets keep the order they cascade in.
+	/// Stylesheets that aren't in the workspace are left out.
+	/// Returns `None` if none of them are.
+	pub fn get_metadata_files(&self, file_paths: &[PathBuf]) -> Option<Vec<CssMetaData>> {
+		let metadata_files: Vec<CssMetaData> = file_paths
+		.iter()
+		.filter_map(|file_path| self.get_css_metadata(file_path).cloned())
+		.collect();
+
+		if metadata_files.is_empty() {
+			None
+		} else {
+			Some(metadata_files)
+		}
+	}
+}
+
+/// A metadata file that has been changed in a `WorkspaceIndex`, along with the JSON it still has to be saved as
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataWrite {
+	pub metadata_path: PathBuf,
+	pub contents: Option<String>, // None if the metadata file has to be deleted
+}
+
+impl MetadataWrite {
+	/// Returns `Err(BhcError)` if the `metadata` could not be serialized.
+	pub fn new<T: Serialize>(metadata_path: PathBuf, metadata: &T) -> Result<MetadataWrite, BhcError> {
+		let contents = serialize_metadata(&metadata_path, metadata)?;
+
+		Ok(MetadataWrite { metadata_path, contents: Some(contents) })
+	}
+
+	/// Delete the metadata file at `metadata_path`, e.g. once the file it belongs to has been deleted.
+	pub fn remove(metadata_path: PathBuf) -> MetadataWrite {
+		MetadataWrite { metadata_path, contents: None }
+	}
+}
+
+/// Save every one of the `writes` to disk, in order. See [`write_file_atomic`].
+/// Returns `Err(BhcError)` at the first one that could not be written or deleted.
+pub fn write_metadata_files(writes: &[MetadataWrite]) -> Result<(), BhcError> {
+	for write in writes {
+		match &write.contents {
+			Some(contents) => write_file_atomic(&write.metadata_path, contents)?,
+			None => remove_metadata_file(&write.metadata_path)?
+		}
+	}
+
+	Ok(())
+}
+
+/// The contents of files and the stamps they were read with, by path, read before the cache is written to so it isn't held while waiting on the disk
+pub type SourceFiles = HashMap<PathBuf, (String, Option<FileStamp>)>;
+
+/// Read every one of the files at `file_paths`, see [`read_source`]. Files that could not be read are left out.
+pub fn read_source_files(file_paths: &[PathBuf]) -> SourceFiles {
+	file_paths
+	.iter()
+	.filter_map(|file_path| read_source(file_path).ok().map(|source| (file_path.clone(), source)))
+	.collect()
+}
+
+/// Delete the metadata file at `metadata_path`, it not existing is the same as it being deleted.
+fn remove_metadata_file(metadata_path: &Path) -> Result<(), BhcError> {
+	match fs::remove_file(metadata_path) {
+		Ok(_) => Ok(()),
+		Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+		Err(error) => Err(BhcError::io(metadata_path, error))
+	}
+}
+
+/// The `WorkspaceIndex` of every workspace, shared between the requests of the server.
+/// A workspace is read from disk the first time it is used, after that it is only changed through [`MetadataCache::write`] or replaced with [`MetadataCache::insert`].
+#[derive(Debug, Clone, Default)]
+pub struct MetadataCache {
+	workspaces: Arc<RwLock<HashMap<PathBuf, WorkspaceIndex>>>,
+}
+
+impl MetadataCache {
+	/// Run `read` with the index of the workspace at `workspace_path`. Any number of reads can happen at the same time.
+	/// Returns `Err(BhcError)` if the workspace wasn't indexed yet and its metadata could not be read.
+	pub async fn read<T>(&self, workspace_path: &Path, read: impl FnOnce(&WorkspaceIndex) -> T) -> Result<T, BhcError> {
+		if let Some(index) = self.workspaces.read().await.get(workspace_path) {
+			return Ok(read(index))
+		}
+
+		self.write(workspace_path, |index| read(index)).await
+	}
+
+	/// Run `write` with the index of the workspace at `workspace_path`, nothing else can use the cache until it is done.
+	/// Returns `Err(BhcError)` if the workspace wasn't indexed yet and its metadata could not be read.
+	pub async fn write<T>(&self, workspace_path: &Path, write: impl FnOnce(&mut WorkspaceIndex) -> T) -> Result<T, BhcError> {
+		let mut workspaces = self.workspaces.write().await;
+
+		// another request could have loaded it while waiting for the lock
+		let index = match workspaces.entry(workspace_path.to_path_buf()) {
+			Entry::Occupied(entry) => entry.into_mut(),
+			Entry::Vacant(entry) => match WorkspaceIndex::load(workspace_path) {
+				Ok(value) => entry.insert(value),
+				Err(error) => return Err(error)
+			}
+		};
+
+		Ok(write(index))
+	}
+
+	/// Replace the index of the workspace at `workspace_path`, e.g. once it has been initialized.
+	pub async fn insert(&self, workspace_path: &Path, index: WorkspaceIndex) {
+		self.workspaces.write().await.insert(workspace_path.to_path_buf(), index);
+	}
+
+	/// Forget the index of the workspace at `workspace_path`, e.g. once it has been closed. Its metadata stays on disk.
+	pub async fn remove(&self, workspace_path: &Path) {
+		self.workspaces.write().await.remove(workspace_path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{fs, path::Path};
+
+	use crate::{metadata::{css_metadata::CssMetaData, read_source, workspace_metadata::{create_workspace_metadata, id_to_json_file_name, workspace_css_file::WorkspaceCssFile}}, CSS_METADATA_PATH, METADATA_PATH};
+
+	use super::{read_source_files, write_metadata_files, MetadataCache, SourceFiles, WorkspaceIndex};
+
+	#[tokio::test]
+	async fn metadata_cache_test() {
+		let workspace_path = std::env::temp_dir().join("bhc_metadata_cache_test");
+		let css_path = workspace_path.join("base.css");
+		let metadata_path = workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&1));
+
+		let _ = fs::remove_dir_all(&workspace_path);
+		fs::create_dir_all(&workspace_path).unwrap();
+		fs::write(&css_path, "h1 { color: red; }").unwrap();
+
+		let css_metadata = CssMetaData::create_metadata(&metadata_path, &css_path, &1).unwrap();
+
+		let mut workspace_metadata = create_workspace_metadata(&workspace_path.join(METADATA_PATH), &workspace_path).unwrap();
+		workspace_metadata.add_css_file(WorkspaceCssFile::parse(&css_metadata));
+		workspace_metadata.update_metadata(&workspace_path.join(METADATA_PATH)).unwrap();
+
+		let cache = MetadataCache::default();
+
+		// the last updated time is saved in seconds, so only the styles can be compared
+		let cached = cache.read(&workspace_path, |index| index.get_css_metadata(&css_path).map(|metadata| metadata.styles.clone())).await.unwrap();
+		assert_eq!(cached, Some(css_metadata.styles.clone()));
+
+		// once indexed, the workspace isn't read from disk again
+		fs::remove_file(&metadata_path).unwrap();
+
+		let missing = workspace_path.join("missing.css");
+		let files = cache.read(&workspace_path, |index| index.get_metadata_files(&[missing.clone(), css_path.clone()])).await.unwrap().unwrap();
+		assert_eq!(files.iter().map(|metadata| metadata.id).collect::<Vec<u32>>(), vec![1]);
+
+		// reloading a file that no longer has metadata removes it
+		assert!(cache.write(&workspace_path, |index| index.reload_css_metadata(&workspace_path, &1)).await.unwrap().is_err());
+		assert_eq!(cache.read(&workspace_path, |index| index.get_metadata_files(std::slice::from_ref(&css_path))).await.unwrap(), None);
+
+		assert!(cache.read(&workspace_path.join("missing"), |_| ()).await.is_err());
+
+		// saving a stylesheet updates its metadata on disk and in the cache
+		fs::write(&css_path, "h1 { color: blue; }").unwrap();
+		CssMetaData::create_metadata(&metadata_path, &css_path, &1).unwrap();
+		cache.write(&workspace_path, |index| index.reload_css_metadata(&workspace_path, &1)).await.unwrap().unwrap();
+		fs::write(&css_path, "h2 { color: blue; }").unwrap();
+
+		let writes = cache.write(&workspace_path, |index| index.update_file_metadata(&workspace_path, &css_path, "h2 { color: blue; }", None)).await.unwrap().unwrap().unwrap();
+		assert_eq!(cache.write(&workspace_path, |index| index.update_file_metadata(&workspace_path, &missing, "", None)).await.unwrap().unwrap(), None);
+
+		let styles = cache.read(&workspace_path, |index| index.get_css_metadata(&css_path).and_then(|metadata| metadata.styles.clone())).await.unwrap().unwrap();
+		assert_eq!(styles[0].tag, "h2");
+
+		// the cache is updated straight away, the disk only once the writes are saved
+		assert_ne!(CssMetaData::from_json(&metadata_path).unwrap().styles, Some(styles.clone()));
+		write_metadata_files(&writes).unwrap();
+		assert_eq!(CssMetaData::from_json(&metadata_path).unwrap().styles, Some(styles));
+
+		fs::remove_file(&metadata_path).unwrap();
+
+		assert!(WorkspaceIndex::load(&workspace_path).unwrap().css_metadata.is_empty());
+	}
+
+	/// Add the file at `file_path` to the `index` the same as `Backend::add_file_metadata`, saving what changed straight away.
+	fn add_file(index: &mut WorkspaceIndex, workspace_path: &Path, file_path: &Path) {
+		let documents = read_source_files(&index.get_linking_documents(file_path));
+		let (contents, stamp) = read_source(file_path).unwrap();
+
+		write_metadata_files(&index.add_file_metadata(workspace_path, file_path, &contents, stamp, &documents).unwrap().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn add_and_remove_file_metadata_test() {
+		let workspace_path = std::env::temp_dir().join("bhc_file_events_test");
+		let html_path = workspace_path.join("index.html");
+		let css_path = workspace_path.join("css").join("base.css");
+
+		let _ = fs::remove_dir_all(&workspace_path);
+		fs::create_dir_all(css_path.parent().unwrap()).unwrap();
+		fs::write(&html_path, "<link rel=\"stylesheet\" href=\"css/base.css\">").unwrap();
+
+		create_workspace_metadata(&workspace_path.join(METADATA_PATH), &workspace_path).unwrap();
+
+		let mut index = WorkspaceIndex::load(&workspace_path).unwrap();
+
+		// the stylesheet doesn't exist yet, so the document links to nothing
+		add_file(&mut index, &workspace_path, &html_path);
+		assert_eq!(index.get_html_metadata(&html_path).unwrap().css_sheets, None);
+
+		fs::write(&css_path, "h1 { color: red; }").unwrap();
+
+		add_file(&mut index, &workspace_path, &css_path);
+		assert_eq!(index.add_file_metadata(&workspace_path, &workspace_path.join("readme.md"), "", None, &SourceFiles::new()).unwrap(), None);
+
+		let css_id = index.workspace_metadata.get_css_file_id(&css_path).unwrap();
+		let html_id = index.workspace_metadata.get_html_file_id(&html_path).unwrap();
+
+		assert_eq!(index.get_html_metadata(&html_path).unwrap().css_sheets.as_ref().map(|sheets| sheets[0].id), Some(css_id));
+		assert_eq!(index.workspace_metadata.get_referencing_html_files(&css_id).len(), 1);
+
+		// what is on disk matches the index
+		assert_eq!(WorkspaceIndex::load(&workspace_path).unwrap().workspace_metadata.css_files, index.workspace_metadata.css_files);
+
+		// unsaved changes only change what is read from the index, not what is saved
+		assert!(index.apply_document_contents(&html_path, "<p>No stylesheets</p>"));
+		assert_eq!(index.get_html_metadata(&html_path).unwrap().css_sheets, None);
+		assert_eq!(index.get_workspace_metadata().get_referencing_html_files(&css_id).len(), 0);
+		assert_eq!(index.workspace_metadata.get_referencing_html_files(&css_id).len(), 1);
+		assert!(!index.apply_document_contents(&workspace_path.join("readme.md"), ""));
+
+		// even once something else is saved
+		let other_html_path = workspace_path.join("about.html");
+		fs::write(&other_html_path, "<p>About</p>").unwrap();
+		add_file(&mut index, &workspace_path, &other_html_path);
+
+		let saved_index = WorkspaceIndex::load(&workspace_path).unwrap();
+		assert_eq!(saved_index.get_html_metadata(&html_path).unwrap().css_sheets.as_ref().map(|sheets| sheets[0].id), Some(css_id));
+		assert_eq!(saved_index.workspace_metadata.get_referencing_html_files(&css_id).len(), 1);
+
+		// closing the document without saving goes back to what is saved
+		assert!(index.discard_document_contents(&html_path));
+		assert!(!index.discard_document_contents(&html_path));
+		assert_eq!(index.get_html_metadata(&html_path).unwrap().css_sheets.as_ref().map(|sheets| sheets[0].id), Some(css_id));
+		assert_eq!(index.get_workspace_metadata(), index.workspace_metadata);
+
+		fs::remove_file(&css_path).unwrap();
+
+		let documents = read_source_files(&index.get_linking_documents(&css_path));
+		assert_eq!(documents.len(), 1);
+
+		let writes = index.remove_file_metadata(&workspace_path, &css_path, &documents).unwrap().unwrap();
+		assert_eq!(index.remove_file_metadata(&workspace_path, &css_path, &documents).unwrap(), None);
+
+		// nothing is deleted until the writes are saved
+		let css_metadata_path = workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&css_id));
+		assert!(css_metadata_path.exists());
+		write_metadata_files(&writes).unwrap();
+		assert!(!css_metadata_path.exists());
+
+		assert!(index.workspace_metadata.css_files.is_empty());
+		assert_eq!(WorkspaceIndex::load(&workspace_path).unwrap().workspace_metadata.html_files, index.workspace_metadata.html_files);
+		assert_eq!(index.get_html_metadata(&html_path).unwrap().css_sheets, None);
+		assert_eq!(index.workspace_metadata.get_html_file_by_id(&html_id).unwrap().css_files, None);
+	}
+}
@@ -0,0 +1,81 @@
+use std::{fs::{self, File, OpenOptions, TryLockError}, path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use crate::{error::BhcError, METADATA_LOCK_PATH};
+
+/// How long to wait for another editor to finish changing the metadata before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to check whether the other editor has finished
+const LOCK_RETRY: Duration = Duration::from_millis(50);
+
+/// An advisory lock on the `.bhc/.meta` folder of a workspace, so only one server changes its metadata at a time, e.g. with two editor windows open on the same folder.
+/// The lock is released when this is dropped, or by the operating system if the server crashes.
+#[derive(Debug)]
+pub struct MetadataLock {
+	_file: File,
+}
+
+impl MetadataLock {
+	/// Wait until no other server or request holds the lock on the metadata of the workspace at `workspace_path`, then take it.
+	/// Returns `Err(BhcError::Locked)` if it is still held after `LOCK_TIMEOUT`, or `Err(BhcError)` if the lock file could not be opened.
+	pub async fn acquire(workspace_path: &Path) -> Result<MetadataLock, BhcError> {
+		let lock_path = workspace_path.join(METADATA_LOCK_PATH);
+		let file = match open_lock_file(&lock_path) {
+			Ok(value) => value,
+			Err(error) => return Err(error)
+		};
+
+		let started = Instant::now();
+
+		loop {
+			match file.try_lock() {
+				Ok(_) => return Ok(MetadataLock { _file: file }),
+				Err(TryLockError::WouldBlock) if started.elapsed() < LOCK_TIMEOUT => tokio::time::sleep(LOCK_RETRY).await,
+				Err(TryLockError::WouldBlock) => return Err(BhcError::Locked(lock_path)),
+				Err(TryLockError::Error(error)) => return Err(BhcError::io(&lock_path, error))
+			}
+		}
+	}
+}
+
+fn open_lock_file(lock_path: &PathBuf) -> Result<File, BhcError> {
+	if let Some(directory) = lock_path.parent() {
+		if let Err(error) = fs::create_dir_all(directory) {
+			return Err(BhcError::io(directory, error))
+		}
+	}
+
+	// the contents are never read, so it is never truncated
+	match OpenOptions::new().create(true).truncate(false).write(true).open(lock_path) {
+		Ok(value) => Ok(value),
+		Err(error) => Err(BhcError::io(lock_path, error))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use super::MetadataLock;
+
+	#[tokio::test]
+	async fn metadata_lock_test() {
+		let workspace_path = std::env::temp_dir().join("bhc_metadata_lock_test");
+		let _ = fs::remove_dir_all(&workspace_path);
+
+		let lock = MetadataLock::acquire(&workspace_path).await.unwrap();
+
+		// a second lock has to wait for the first to be dropped
+		let waiting = tokio::spawn({
+			let workspace_path = workspace_path.clone();
+			async move { MetadataLock::acquire(&workspace_path).await.map(|_| ()) }
+		});
+
+		tokio::time::sleep(std::time::Duration::from_millis(120)).await;
+		assert!(!waiting.is_finished());
+
+		drop(lock);
+
+		assert!(waiting.await.unwrap().is_ok());
+	}
+}
@@ -0,0 +1,269 @@
+use std::{fs, io::ErrorKind, path::{Path, PathBuf}};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{error::BhcError, CSS_METADATA_PATH, HTML_METADATA_PATH, METADATA_PATH};
+
+use super::{css_metadata::CssMetaData, html_metadata::HtmlMetaData, read_metadata, workspace_metadata::WorkspaceMetaData, write_metadata};
+
+/// The version of the layout that metadata is saved in. Increase it when a change to the metadata means what older versions saved can't be used as it is, and add a migration for it to `MIGRATIONS`.
+/// Metadata saved before there was a version is version 0.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The kinds of metadata file a migration is given, as each has its own layout
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MetadataKind {
+	Workspace, // `meta.json`
+	Css, // `css/<id>.json`
+	Html, // `html/<id>.json`
+}
+
+/// Upgrades the JSON of a metadata file by one version. Returns `Err(String)` with the reason if the file can't be upgraded.
+type Migration = fn(MetadataKind, &mut Value) -> Result<(), String>;
+
+/// The migration at each index upgrades metadata from that version to the next one
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+	migrate_v0_to_v1,
+];
+
+/// Styles saved before there was a version have no ranges, selectors or content hash, so the files are parsed again the next time the workspace is initialized.
+fn migrate_v0_to_v1(kind: MetadataKind, metadata: &mut Value) -> Result<(), String> {
+	if kind == MetadataKind::Workspace {
+		return Ok(())
+	}
+
+	let metadata = match metadata.as_object_mut() {
+		Some(value) => value,
+		None => return Err(String::from("it isn't a JSON object"))
+	};
+
+	metadata.insert(String::from("last_updated"), Value::from(0));
+	metadata.remove("content_hash");
+
+	Ok(())
+}
+
+/// Upgrade the JSON of a metadata file of the given `kind` to `SCHEMA_VERSION`, running every migration from the version it was saved in.
+/// Returns `Ok(true)` if it was upgraded, `Ok(false)` if it was already up to date, or `Err(String)` with the reason if it can't be upgraded, e.g. it was saved by a newer version of the server.
+pub fn migrate_value(kind: MetadataKind, metadata: &mut Value) -> Result<bool, String> {
+	if !metadata.is_object() {
+		return Err(String::from("it isn't a JSON object"))
+	}
+
+	let version = match metadata.get("schema_version") {
+		Some(value) => match value.as_u64() {
+			Some(value) => value as usize,
+			None => return Err(format!("its schema_version isn't a number: {}", value))
+		},
+		None => 0
+	};
+
+	if version > SCHEMA_VERSION as usize {
+		return Err(format!("it was saved by a newer version of the server, with schema version {}", version))
+	}
+
+	if version == SCHEMA_VERSION as usize {
+		return Ok(false)
+	}
+
+	for migration in &MIGRATIONS[version..] {
+		migration(kind, metadata)?;
+	}
+
+	metadata["schema_version"] = Value::from(SCHEMA_VERSION);
+
+	Ok(true)
+}
+
+/// Upgrade the metadata of the workspace at `workspace_path` to `SCHEMA_VERSION`, before anything reads it.
+/// The version of each file is checked on its own, as a file can be left behind by an upgrade that was interrupted, or written by an older version of the server after `meta.json` was upgraded.
+/// Every file is upgraded and checked in memory before any of them are written, and `meta.json` is written last, so it is only up to date once everything else is.
+/// The metadata of a file that can't be read, e.g. it is corrupt, is deleted so it is created again, the same as in [`reconcile_workspace`](super::reconcile::reconcile_workspace), rather than every file losing its id.
+/// Returns `Ok(Vec<BhcError>)` with why each of those metadata files couldn't be read.
+/// Returns `Err(BhcError)` if `meta.json` can't be read, or any of the metadata can't be upgraded, in which case it should be rebuilt with [`rebuild_metadata`].
+pub fn migrate_workspace(workspace_path: &Path) -> Result<Vec<BhcError>, BhcError> {
+	let metadata_path = workspace_path.join(METADATA_PATH);
+
+	// a workspace that was never initialized has nothing to upgrade
+	if !metadata_path.is_file() {
+		return Ok(Vec::new())
+	}
+
+	let mut workspace_metadata: Value = read_metadata(&metadata_path)?;
+
+	let is_upgraded = match migrate_value(MetadataKind::Workspace, &mut workspace_metadata) {
+		Ok(value) => value,
+		Err(reason) => return Err(BhcError::Migration(metadata_path, reason))
+	};
+
+	if is_upgraded {
+		check_layout::<WorkspaceMetaData>(&metadata_path, &workspace_metadata)?;
+	}
+
+	let mut upgraded_files: Vec<(PathBuf, Value)> = Vec::new();
+	let mut unreadable: Vec<BhcError> = Vec::new();
+
+	for (kind, directory) in [(MetadataKind::Css, CSS_METADATA_PATH), (MetadataKind::Html, HTML_METADATA_PATH)] {
+		for file_path in get_json_files(&workspace_path.join(directory))? {
+			let mut metadata: Value = match read_metadata(&file_path) {
+				Ok(value) => value,
+				Err(error) => {
+					match fs::remove_file(&file_path) {
+						Ok(_) => (),
+						Err(error) => return Err(BhcError::io(&file_path, error))
+					};
+
+					unreadable.push(error);
+					continue
+				}
+			};
+
+			match migrate_value(kind, &mut metadata) {
+				Ok(true) => (),
+				Ok(false) => continue,
+				Err(reason) => return Err(BhcError::Migration(file_path, reason))
+			};
+
+			match kind {
+				MetadataKind::Html => check_layout::<HtmlMetaData>(&file_path, &metadata)?,
+				_ => check_layout::<CssMetaData>(&file_path, &metadata)?
+			};
+
+			upgraded_files.push((file_path, metadata));
+		}
+	}
+
+	for (file_path, metadata) in upgraded_files {
+		write_metadata(&file_path, &metadata)?;
+	}
+
+	if is_upgraded {
+		write_metadata(&metadata_path, &workspace_metadata)?;
+	}
+
+	Ok(unreadable)
+}
+
+/// Delete all of the metadata of the workspace at `workspace_path`, so it is created again from scratch, e.g. when it can't be upgraded.
+/// The lock file is kept, as another editor could be waiting on it.
+/// Returns `Err(BhcError)` if any of it could not be deleted.
+pub fn rebuild_metadata(workspace_path: &Path) -> Result<(), BhcError> {
+	for directory in [CSS_METADATA_PATH, HTML_METADATA_PATH] {
+		let directory = workspace_path.join(directory);
+
+		match fs::remove_dir_all(&directory) {
+			Ok(_) => (),
+			Err(error) if error.kind() == ErrorKind::NotFound => (),
+			Err(error) => return Err(BhcError::io(&directory, error))
+		};
+	}
+
+	let metadata_path = workspace_path.join(METADATA_PATH);
+
+	match fs::remove_file(&metadata_path) {
+		Ok(_) => Ok(()),
+		Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+		Err(error) => Err(BhcError::io(&metadata_path, error))
+	}
+}
+
+/// Check that the upgraded `metadata` can be read as a `T`, so a missing migration is caught before anything is written.
+fn check_layout<T: DeserializeOwned>(file_path: &Path, metadata: &Value) -> Result<(), BhcError> {
+	match T::deserialize(metadata) {
+		Ok(_) => Ok(()),
+		Err(error) => Err(BhcError::json(file_path, error))
+	}
+}
+
+/// Get every JSON file directly inside of `directory`, which not existing is the same as it being empty.
+fn get_json_files(directory: &Path) -> Result<Vec<PathBuf>, BhcError> {
+	let entries = match fs::read_dir(directory) {
+		Ok(value) => value,
+		Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(error) => return Err(BhcError::io(directory, error))
+	};
+
+	Ok(entries
+	.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+	.filter(|file_path| file_path.extension().is_some_and(|extension| extension == "json"))
+	.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use serde_json::{json, Value};
+
+	use crate::{metadata::{css_metadata::CssMetaData, workspace_metadata::{id_to_json_file_name, open_workspace_metadata}}, CSS_METADATA_PATH, METADATA_LOCK_PATH, METADATA_PATH};
+
+	use super::{migrate_value, migrate_workspace, rebuild_metadata, MetadataKind, SCHEMA_VERSION};
+
+	#[test]
+	fn migrate_value_test() {
+		let mut css_metadata = json!({ "id": 1, "last_updated": 1700000000, "content_hash": "cbf29ce484222325" });
+
+		assert_eq!(migrate_value(MetadataKind::Css, &mut css_metadata), Ok(true));
+		assert_eq!(css_metadata, json!({ "schema_version": SCHEMA_VERSION, "id": 1, "last_updated": 0 }));
+
+		// metadata that is already up to date is left alone
+		assert_eq!(migrate_value(MetadataKind::Css, &mut css_metadata), Ok(false));
+
+		assert!(migrate_value(MetadataKind::Workspace, &mut json!({ "schema_version": SCHEMA_VERSION + 1 })).is_err());
+		assert!(migrate_value(MetadataKind::Html, &mut Value::from("")).is_err());
+	}
+
+	#[test]
+	fn migrate_workspace_test() {
+		let workspace_path = std::env::temp_dir().join("bhc_migrate_workspace_test");
+		let css_metadata_path = workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&1));
+
+		let _ = fs::remove_dir_all(&workspace_path);
+		fs::create_dir_all(workspace_path.join(CSS_METADATA_PATH)).unwrap();
+
+		// the layout from before there was a version
+		fs::write(workspace_path.join(METADATA_PATH), json!({ "workspace_path": workspace_path, "last_updated": 1700000000, "html_files": [], "css_files": [] }).to_string()).unwrap();
+		fs::write(&css_metadata_path, json!({ "id": 1, "file_name": "base.css", "absolute_path": "/base.css", "last_updated": 1700000000, "styles": [{ "tag": "h1", "attributes": [{ "name": "color", "values": ["red"] }] }] }).to_string()).unwrap();
+
+		migrate_workspace(&workspace_path).unwrap();
+
+		let css_metadata = CssMetaData::from_json(&css_metadata_path).unwrap();
+
+		assert_eq!(open_workspace_metadata(&workspace_path.join(METADATA_PATH)).unwrap().schema_version, SCHEMA_VERSION);
+		assert_eq!(css_metadata.schema_version, SCHEMA_VERSION);
+		assert_eq!(css_metadata.last_updated.timestamp(), 0);
+
+		// a file left behind when `meta.json` is already up to date is still upgraded
+		fs::write(&css_metadata_path, json!({ "id": 1, "file_name": "base.css", "absolute_path": "/base.css", "last_updated": 1700000000, "content_hash": "cbf29ce484222325" }).to_string()).unwrap();
+
+		migrate_workspace(&workspace_path).unwrap();
+
+		let css_metadata = CssMetaData::from_json(&css_metadata_path).unwrap();
+
+		assert_eq!(css_metadata.schema_version, SCHEMA_VERSION);
+		assert_eq!(css_metadata.content_hash, None);
+
+		// metadata that can't be read is deleted to be created again, without stopping the rest from being upgraded
+		let corrupt_metadata_path = workspace_path.join(CSS_METADATA_PATH).join(id_to_json_file_name(&2));
+
+		fs::write(&corrupt_metadata_path, "{ \"id\": 2,").unwrap();
+		fs::write(&css_metadata_path, json!({ "id": 1, "file_name": "base.css", "absolute_path": "/base.css", "last_updated": 1700000000 }).to_string()).unwrap();
+
+		assert_eq!(migrate_workspace(&workspace_path).unwrap().len(), 1);
+		assert!(!corrupt_metadata_path.exists());
+		assert_eq!(CssMetaData::from_json(&css_metadata_path).unwrap().schema_version, SCHEMA_VERSION);
+
+		// metadata from a newer version can't be upgraded, so it is rebuilt
+		fs::write(workspace_path.join(METADATA_PATH), json!({ "schema_version": SCHEMA_VERSION + 1 }).to_string()).unwrap();
+		fs::write(workspace_path.join(METADATA_LOCK_PATH), "").unwrap();
+
+		assert!(migrate_workspace(&workspace_path).is_err());
+
+		rebuild_metadata(&workspace_path).unwrap();
+
+		assert!(!workspace_path.join(METADATA_PATH).exists());
+		assert!(!workspace_path.join(CSS_METADATA_PATH).exists());
+		assert!(workspace_path.join(METADATA_LOCK_PATH).exists());
+	}
+}
@@ -0,0 +1,258 @@
+use std::{fs, io::ErrorKind, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+use crate::error::BhcError;
+
+use super::{hash_contents, read_metadata, workspace_metadata::{json_file_name_to_id, WorkspaceMetaData}, write_metadata, GroupedFiles};
+
+/// The part of a `CssMetaData` or `HtmlMetaData` that is needed to find the file it belongs to
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct IndexedFile {
+	pub id: u32,
+	pub absolute_path: String,
+
+	#[serde(default)]
+	pub content_hash: Option<String>,
+
+	#[serde(skip)]
+	pub metadata_path: PathBuf, // where the metadata is saved
+}
+
+impl IndexedFile {
+	/// Read the metadata saved at `metadata_path`.
+	/// Returns `Err(BhcError)` if it could not be read or deserialized.
+	pub fn from_json(metadata_path: &Path) -> Result<IndexedFile, BhcError> {
+		let mut indexed_file: IndexedFile = read_metadata(metadata_path)?;
+		indexed_file.metadata_path = metadata_path.to_path_buf();
+
+		Ok(indexed_file)
+	}
+}
+
+/// How the metadata of a workspace differs from the files that are on disk
+#[derive(Debug, PartialEq, Default)]
+pub struct Reconciliation {
+	pub moved: Vec<(IndexedFile, PathBuf)>, // metadata of a file that no longer exists, and the new file with the same contents that it was moved to
+	pub orphaned: Vec<IndexedFile>, // metadata of a file that no longer exists, and wasn't moved anywhere
+}
+
+/// Compare the `indexed` files against the `files` that are on disk.
+/// A file that no longer exists is paired with a file that has no metadata yet if their content hashes match, so it keeps its id. Each new file can only be paired once.
+pub fn reconcile(indexed: &[IndexedFile], files: &[PathBuf]) -> Reconciliation {
+	let mut reconciliation = Reconciliation::default();
+
+	let missing: Vec<&IndexedFile> = indexed.iter().filter(|file| !files.contains(&PathBuf::from(&file.absolute_path))).collect();
+
+	if missing.is_empty() {
+		return reconciliation
+	}
+
+	// only files without metadata can be where a file moved to, and they are only read if something is missing
+	let mut new_files: Vec<(&PathBuf, Option<String>)> = files
+	.iter()
+	.filter(|file_path| !indexed.iter().any(|file| &PathBuf::from(&file.absolute_path) == *file_path))
+	.map(|file_path| (file_path, fs::read_to_string(file_path).ok().map(|contents| hash_contents(&contents))))
+	.collect();
+
+	for file in missing {
+		let moved_to = file.content_hash.as_ref().and_then(|hash| new_files.iter().position(|(_, new_hash)| new_hash.as_ref() == Some(hash)));
+
+		match moved_to {
+			Some(position) => reconciliation.moved.push((file.clone(), new_files.remove(position).0.clone())),
+			None => reconciliation.orphaned.push(file.clone())
+		}
+	}
+
+	reconciliation
+}
+
+/// Bring the metadata of the workspace in line with the files on disk, before the metadata of each file is created or updated.
+/// Moved files have their metadata pointed at where they are now, keeping their ids. The metadata of deleted files is removed, along with their entries in the `workspace_metadata`.
+/// The `grouped_files` are updated to match, so the metadata of deleted files isn't read afterwards.
+/// Metadata files that can't be read, e.g. they are corrupt, are deleted along with their entries, so their files are given new metadata as if they had never been indexed.
+/// Returns `Ok(Vec<BhcError>)` with why each of those metadata files couldn't be read.
+/// Returns `Err(BhcError)` if a metadata file could not be written or deleted.
+pub fn reconcile_workspace(grouped_files: &mut GroupedFiles, workspace_metadata: &mut WorkspaceMetaData) -> Result<Vec<BhcError>, BhcError> {
+	let (indexed_css_files, unreadable_css_files) = read_indexed_files(&mut grouped_files.json_files.css_files);
+	let (indexed_html_files, unreadable_html_files) = read_indexed_files(&mut grouped_files.json_files.html_files);
+
+	workspace_metadata.css_files.retain(|css_file| !unreadable_css_files.iter().any(|(id, _)| *id == Some(css_file.id)));
+	workspace_metadata.html_files.retain(|html_file| !unreadable_html_files.iter().any(|(id, _)| *id == Some(html_file.id)));
+
+	let css_reconciliation = reconcile(&indexed_css_files, &grouped_files.css_files);
+	let html_reconciliation = reconcile(&indexed_html_files, &grouped_files.html_files);
+
+	for (file, new_path) in &css_reconciliation.moved {
+		move_metadata(&file.metadata_path, new_path)?;
+
+		if let Some(css_file) = workspace_metadata.css_files.iter_mut().find(|css_file| css_file.id == file.id) {
+			css_file.absolute_path = new_path.to_str().unwrap_or_default().to_string();
+			css_file.file_name = file_name(new_path);
+		}
+	}
+
+	for (file, new_path) in &html_reconciliation.moved {
+		move_metadata(&file.metadata_path, new_path)?;
+
+		if let Some(html_file) = workspace_metadata.html_files.iter_mut().find(|html_file| html_file.id == file.id) {
+			html_file.absolute_path = new_path.to_str().unwrap_or_default().to_string();
+			html_file.file_name = file_name(new_path);
+		}
+	}
+
+	for file in css_reconciliation.orphaned.iter().chain(html_reconciliation.orphaned.iter()) {
+		match fs::remove_file(&file.metadata_path) {
+			Ok(_) => (),
+			Err(error) if error.kind() == ErrorKind::NotFound => (),
+			Err(error) => return Err(BhcError::io(&file.metadata_path, error))
+		};
+	}
+
+	let orphaned_paths: Vec<&PathBuf> = css_reconciliation.orphaned.iter().chain(html_reconciliation.orphaned.iter()).map(|file| &file.metadata_path).collect();
+
+	grouped_files.json_files.css_files.retain(|metadata_path| !orphaned_paths.contains(&metadata_path));
+	grouped_files.json_files.html_files.retain(|metadata_path| !orphaned_paths.contains(&metadata_path));
+
+	// entries for files that no longer exist are dropped too, even if their metadata was already gone
+	workspace_metadata.css_files.retain(|css_file| grouped_files.css_files.contains(&PathBuf::from(&css_file.absolute_path)));
+	workspace_metadata.html_files.retain(|html_file| grouped_files.html_files.contains(&PathBuf::from(&html_file.absolute_path)));
+
+	Ok(unreadable_css_files.into_iter().chain(unreadable_html_files).map(|(_, error)| error).collect())
+}
+
+/// Read the metadata saved at each of the `metadata_paths`.
+/// One that can't be read is deleted and removed from the `metadata_paths`, rather than stopping the rest from being read.
+/// Returns the metadata that was read, and the id and error of each that wasn't.
+fn read_indexed_files(metadata_paths: &mut Vec<PathBuf>) -> (Vec<IndexedFile>, Vec<(Option<u32>, BhcError)>) {
+	let mut indexed_files: Vec<IndexedFile> = Vec::new();
+	let mut unreadable: Vec<(Option<u32>, BhcError)> = Vec::new();
+
+	metadata_paths.retain(|metadata_path| match IndexedFile::from_json(metadata_path) {
+		Ok(value) => {
+			indexed_files.push(value);
+			true
+		},
+		Err(error) => {
+			// if it can't be deleted either, a new id is still given to the file, as it is no longer reserved
+			let _ = fs::remove_file(metadata_path);
+
+			unreadable.push((json_file_name_to_id(metadata_path), error));
+			false
+		}
+	});
+
+	(indexed_files, unreadable)
+}
+
+/// Point the metadata saved at `metadata_path` at the file's new location, `new_path`, leaving everything else as it is.
+fn move_metadata(metadata_path: &Path, new_path: &Path) -> Result<(), BhcError> {
+	let mut metadata: serde_json::Value = read_metadata(metadata_path)?;
+
+	metadata["absolute_path"] = serde_json::Value::from(new_path.to_str().unwrap_or_default());
+	metadata["file_name"] = serde_json::Value::from(file_name(new_path));
+
+	write_metadata(metadata_path, &metadata)
+}
+
+fn file_name(file_path: &Path) -> String {
+	file_path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{fs, path::{Path, PathBuf}};
+
+	use crate::metadata::{css_metadata::CssMetaData, hash_contents, workspace_metadata::{id_to_json_file_name, workspace_css_file::WorkspaceCssFile, WorkspaceMetaData}, GroupedFiles};
+
+	use super::{reconcile, reconcile_workspace, IndexedFile};
+
+	fn indexed_file(id: u32, absolute_path: &Path, contents: &str) -> IndexedFile {
+		IndexedFile {
+			id,
+			absolute_path: absolute_path.to_str().unwrap().to_string(),
+			content_hash: Some(hash_contents(contents)),
+			metadata_path: PathBuf::from(id_to_json_file_name(&id)),
+		}
+	}
+
+	#[test]
+	fn reconcile_test() {
+		let workspace_path = std::env::temp_dir().join("bhc_reconcile_test");
+		let kept_path = workspace_path.join("kept.css");
+		let moved_path = workspace_path.join("css").join("moved.css");
+		let new_path = workspace_path.join("new.css");
+
+		fs::create_dir_all(moved_path.parent().unwrap()).unwrap();
+		fs::write(&kept_path, "h1 { color: red; }").unwrap();
+		fs::write(&moved_path, "p { margin: 0; }").unwrap();
+		fs::write(&new_path, "p { margin: 1px; }").unwrap();
+
+		let indexed = vec![
+			indexed_file(1, &kept_path, "h1 { color: red; }"),
+			indexed_file(2, &workspace_path.join("moved.css"), "p { margin: 0; }"),
+			indexed_file(3, &workspace_path.join("deleted.css"), "p { margin: 0; }"),
+		];
+
+		let reconciliation = reconcile(&indexed, &[kept_path.clone(), moved_path.clone(), new_path.clone()]);
+
+		// each new file can only be where one file moved to
+		assert_eq!(reconciliation.moved, vec![(indexed[1].clone(), moved_path.clone())]);
+		assert_eq!(reconciliation.orphaned, vec![indexed[2].clone()]);
+	}
+
+	#[test]
+	fn reconcile_workspace_test() {
+		let workspace_path = std::env::temp_dir().join("bhc_reconcile_workspace_test");
+		let old_path = workspace_path.join("base.css");
+		let new_path = workspace_path.join("css").join("base.css");
+		let deleted_path = workspace_path.join("deleted.css");
+
+		let _ = fs::remove_dir_all(&workspace_path);
+		fs::create_dir_all(new_path.parent().unwrap()).unwrap();
+
+		let mut workspace_metadata = WorkspaceMetaData::new();
+		let mut grouped_files = GroupedFiles::new();
+
+		for (id, css_path) in [(1, &old_path), (2, &deleted_path)] {
+			let metadata_path = workspace_path.join(id_to_json_file_name(&id));
+
+			fs::write(css_path, format!("h{} {{ color: red; }}", id)).unwrap();
+
+			let css_metadata = CssMetaData::create_metadata(&metadata_path, css_path, &id).unwrap();
+
+			workspace_metadata.add_css_file(WorkspaceCssFile::parse(&css_metadata));
+			grouped_files.json_files.css_files.push(metadata_path);
+		}
+
+		// metadata that has been corrupted doesn't stop the rest from being reconciled
+		let corrupt_path = workspace_path.join("corrupt.css");
+		let corrupt_metadata_path = workspace_path.join(id_to_json_file_name(&3));
+
+		fs::write(&corrupt_path, "h3 { color: red; }").unwrap();
+		workspace_metadata.add_css_file(WorkspaceCssFile::parse(&CssMetaData::create_metadata(&corrupt_metadata_path, &corrupt_path, &3).unwrap()));
+		fs::write(&corrupt_metadata_path, "{ \"id\": 3, ").unwrap();
+		grouped_files.json_files.css_files.push(corrupt_metadata_path.clone());
+
+		fs::rename(&old_path, &new_path).unwrap();
+		fs::remove_file(&deleted_path).unwrap();
+
+		grouped_files.css_files.push(new_path.clone());
+		grouped_files.css_files.push(corrupt_path.clone());
+
+		let unreadable = reconcile_workspace(&mut grouped_files, &mut workspace_metadata).unwrap();
+
+		assert_eq!(unreadable.len(), 1);
+		assert_eq!(workspace_metadata.get_css_file_id(&new_path), Some(1));
+		assert_eq!(workspace_metadata.get_css_file_id(&corrupt_path), None);
+		assert_eq!(workspace_metadata.css_files.len(), 1);
+		assert_eq!(grouped_files.json_files.css_files, vec![workspace_path.join(id_to_json_file_name(&1))]);
+		assert!(!workspace_path.join(id_to_json_file_name(&2)).exists());
+		assert!(!corrupt_metadata_path.exists());
+
+		let css_metadata = CssMetaData::from_json(&workspace_path.join(id_to_json_file_name(&1))).unwrap();
+
+		assert_eq!(PathBuf::from(&css_metadata.absolute_path), new_path);
+		assert!(css_metadata.styles.is_some());
+	}
+}
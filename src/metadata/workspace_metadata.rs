@@ -1,16 +1,21 @@
 pub mod workspace_css_file;
 pub mod workspace_html_file;
 
-use std::{fs::{self, File}, path::PathBuf};
+use std::{collections::HashSet, path::{Path, PathBuf}};
 use chrono::{serde::ts_seconds, DateTime, Utc};
 use serde::{self, Deserialize, Serialize};
 
-use crate::file::create_dir_and_file;
+use crate::error::BhcError;
+
+use super::{migration::SCHEMA_VERSION, read_metadata, write_metadata, GroupedJsonFiles};
 
 use self::{workspace_css_file::WorkspaceCssFile, workspace_html_file::WorkspaceHtmlFile};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct WorkspaceMetaData {
+    #[serde(default)]
+    pub schema_version: u32, // the layout the metadata was saved in, see `SCHEMA_VERSION`
+
     pub workspace_path: String,
 
     #[serde(with = "ts_seconds")]
@@ -18,48 +23,53 @@ pub struct WorkspaceMetaData {
 
     pub html_files: Vec<WorkspaceHtmlFile>,
     pub css_files: Vec<WorkspaceCssFile>,
+
+    #[serde(default)]
+    pub max_css_id: u32, // the highest id ever given to a CSS file, so the id of a deleted file is never given out again
+    #[serde(default)]
+    pub max_html_id: u32, // the highest id ever given to a HTML file
 }
 
 impl WorkspaceMetaData {
     pub fn new() -> WorkspaceMetaData {
         WorkspaceMetaData {
+            schema_version: SCHEMA_VERSION,
             workspace_path: String::new(),
             last_updated: Utc::now(),
             html_files: Vec::new(),
             css_files: Vec::new(),
+            max_css_id: 0,
+            max_html_id: 0,
         }
     }
 
-    pub fn get_next_available_css_id(&self) -> u32 {
-        let mut smallest_id: u32 = 1;
+    /// Give out the id for a new CSS file, one higher than any id a CSS file has had. Ids are never reused, so nothing that still points at a deleted file can end up pointing at the new one.
+    pub fn allocate_css_id(&mut self) -> u32 {
+        self.max_css_id = next_id(self.max_css_id, self.css_files.iter().map(|css_file| css_file.id));
 
-        self.css_files
-        .iter()
-        .for_each(|x| {
-            if x.id == smallest_id {
-                smallest_id += 1;
-            } else {
-                return
-            }
-        });
+        self.max_css_id
+    }
+
+    /// Give out the id for a new HTML file, see [`WorkspaceMetaData::allocate_css_id`].
+    pub fn allocate_html_id(&mut self) -> u32 {
+        self.max_html_id = next_id(self.max_html_id, self.html_files.iter().map(|html_file| html_file.id));
 
-        smallest_id
+        self.max_html_id
     }
 
-    pub fn get_next_available_html_id(&self) -> u32 {
-        let mut smallest_id: u32 = 1;
+    /// Make sure the ids of metadata files that are on disk are never given out, even if they have no entry in the workspace metadata, so a new file can't overwrite them.
+    pub fn reserve_ids(&mut self, json_files: &GroupedJsonFiles) {
+        self.max_css_id = json_files.css_files.iter().filter_map(|metadata_path| json_file_name_to_id(metadata_path)).fold(self.max_css_id, u32::max);
+        self.max_html_id = json_files.html_files.iter().filter_map(|metadata_path| json_file_name_to_id(metadata_path)).fold(self.max_html_id, u32::max);
+    }
 
-        self.html_files
-        .iter()
-        .for_each(|x| {
-            if x.id == smallest_id {
-                smallest_id += 1;
-            } else {
-                return
-            }
-        });
+    /// Find an id that is given to more than one CSS file, or more than one HTML file, as they would share the same metadata file.
+    /// Returns `None` if every id is unique.
+    pub fn find_duplicate_id(&self) -> Option<u32> {
+        let css_ids: Vec<u32> = self.css_files.iter().map(|css_file| css_file.id).collect();
+        let html_ids: Vec<u32> = self.html_files.iter().map(|html_file| html_file.id).collect();
 
-        smallest_id
+        find_duplicate(&css_ids).or(find_duplicate(&html_ids))
     }
 
     pub fn get_css_file_id(&self, absolute_path: &PathBuf) -> Option<u32> {
@@ -98,7 +108,7 @@ impl WorkspaceMetaData {
         self.html_files.push(html_file_metadata)
     }
 
-    pub fn modify_html_file(&mut self, new_metadata: &WorkspaceHtmlFile, index: &usize) -> Result<(), String> {
+    pub fn modify_html_file(&mut self, new_metadata: &WorkspaceHtmlFile, index: &usize) -> Result<(), BhcError> {
         if let Some(metadata) = self.html_files.get_mut(*index) { 
             metadata.id = new_metadata.id;
             metadata.file_name = new_metadata.file_name.clone();
@@ -108,30 +118,87 @@ impl WorkspaceMetaData {
             Ok(())
 
         } else {
-            Err(String::from("Error trying to update Workspace HTML File: Index out of bounds"))
+            Err(BhcError::metadata(Path::new(&new_metadata.absolute_path)))
+        }
+    }
+
+    /// Rebuild `WorkspaceCssFile.html_files` from the `css_files` of every HTML file, so each stylesheet knows which documents link to it.
+    pub fn update_css_references(&mut self) {
+        for css_file in &mut self.css_files {
+            let html_ids: Vec<u32> = self
+            .html_files
+            .iter()
+            .filter(|html_file| html_file.css_files.as_ref().is_some_and(|css_ids| css_ids.contains(&css_file.id)))
+            .map(|html_file| html_file.id)
+            .collect();
+
+            css_file.html_files = if html_ids.is_empty() { None } else { Some(html_ids) };
         }
     }
 
+    /// Get the HTML files that link to the CSS file with the given `id`
+    pub fn get_referencing_html_files(&self, id: &u32) -> Vec<WorkspaceHtmlFile> {
+        let html_ids = match self.get_css_file_by_id(id).and_then(|css_file| css_file.html_files) {
+            Some(value) => value,
+            None => return Vec::new()
+        };
+
+        self.html_files.iter().filter(|html_file| html_ids.contains(&html_file.id)).cloned().collect()
+    }
+
+    /// Get the files that share styles with the CSS files in `css_ids`, following the links between them once.
+    /// This is every HTML file that links to one of them, and every CSS file linked from those HTML files along with the ones in `css_ids`.
+    pub fn get_linked_files(&self, css_ids: &[u32]) -> (Vec<WorkspaceCssFile>, Vec<WorkspaceHtmlFile>) {
+        let mut linked_css_ids: Vec<u32> = css_ids.to_vec();
+        let mut html_files: Vec<WorkspaceHtmlFile> = Vec::new();
+
+        for id in css_ids {
+            for html_file in self.get_referencing_html_files(id) {
+                if html_files.contains(&html_file) {
+                    continue
+                }
+
+                for css_id in html_file.css_files.iter().flatten() {
+                    if !linked_css_ids.contains(css_id) {
+                        linked_css_ids.push(*css_id);
+                    }
+                }
+
+                html_files.push(html_file);
+            }
+        }
+
+        let css_files = linked_css_ids.iter().filter_map(|id| self.get_css_file_by_id(id)).collect();
+
+        (css_files, html_files)
+    }
+
     /// Save the WorkspaceMetaData back to `meta.json`
     /// Returns `Ok(())` if it succeeds
-    /// Returns `Err(String)` if it is unable to save the file. Effectively meaning the extension won't work... 
-    pub fn update_metadata(&mut self, file_path: &PathBuf) -> Result<(), String> {
-        
-        match create_dir_and_file(&file_path) {
-			Ok(_) => (),
-			Err(error) => return Err(error)
-		};
-
+    /// Returns `Err(BhcError)` if it is unable to save the file. Effectively meaning the extension won't work... 
+    pub fn update_metadata(&mut self, file_path: &Path) -> Result<(), BhcError> {
 		self.last_updated = Utc::now();
 
-		match fs::write(&file_path, serde_json::to_string_pretty(&self).unwrap()) {
-			Ok(_) => return Ok(()),
-			Err(error) => return Err(format!("Error writing workspace metadata to file: ({:?}) {:?}", &file_path, error))
-		};
-
+		write_metadata(file_path, self)
     }
 }
 
+/// Get the id of the metadata file at `metadata_path`, which is named after it.
+/// Returns `None` if it isn't named `<id>.json`.
+pub fn json_file_name_to_id(metadata_path: &Path) -> Option<u32> {
+    metadata_path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok())
+}
+
+fn next_id(max_id: u32, ids: impl Iterator<Item = u32>) -> u32 {
+    ids.fold(max_id, u32::max) + 1
+}
+
+fn find_duplicate(ids: &[u32]) -> Option<u32> {
+    let mut seen: HashSet<u32> = HashSet::new();
+
+    ids.iter().find(|id| !seen.insert(**id)).copied()
+}
+
 pub fn id_to_json_file_name(id: &u32) -> String {
     let mut file_name = String::from(id.to_string());
     file_name.push_str(".json");
@@ -139,35 +206,8 @@ pub fn id_to_json_file_name(id: &u32) -> String {
     file_name
 }
 
-pub fn create_workspace_metadata(metadata_path: &PathBuf, workspace_path: &PathBuf) -> Result<WorkspaceMetaData, String> {
-    let metadata_dir = match metadata_path.parent() {
-        Some(value) => value.to_path_buf(),
-        None => {
-            return Err(format!("Error occurred trying to get the workspace metadata folder: {:?}", &metadata_path))
-        }
-    };
-
-    match File::open(&metadata_path) {
-        Ok(_) => (),
-        Err(_) => {
-            match fs::create_dir_all(&metadata_dir){
-                Ok(_) =>  {
-                    match File::create(&metadata_path) {
-                        Ok(_) => (),
-                        Err(error) => return Err(format!("Couldn't create necessary directories: ({:?}) {:?}", &metadata_path, error))
-                    }
-                },
-                Err(error) => return Err(format!("Couldn't create metadata file: ({:?}) {:?}", &metadata_path, error))
-            };
-        }
-    };
-
-    match fs::write(&metadata_path, create_default_metadata(workspace_path)) {
-        Ok(_) => (),
-        Err(error) => {
-            return Err(format!("Error trying to initialize metadata file: ({:?}) {:?}", &metadata_path, error))
-        }
-    };
+pub fn create_workspace_metadata(metadata_path: &PathBuf, workspace_path: &Path) -> Result<WorkspaceMetaData, BhcError> {
+    write_metadata(metadata_path, &create_default_metadata(workspace_path)?)?;
 
     match open_workspace_metadata(&metadata_path) {
         Ok(value) => Ok(value),
@@ -176,54 +216,140 @@ pub fn create_workspace_metadata(metadata_path: &PathBuf, workspace_path: &PathB
    
 }
 
-pub fn open_workspace_metadata(metadata_path: &PathBuf) -> Result<WorkspaceMetaData, String> {
-    let metadata_json_string = match fs::read_to_string(&metadata_path) {
-        Ok(value) => value,
-        Err(error) => return Err(format!("Error reading metadata file to string: ({:?}) {:?}", &metadata_path, error))
-    };
-
-
-    let metadata_json: WorkspaceMetaData = match serde_json::from_str(&metadata_json_string) {
-        Ok(value) => value,
-        Err(error) => return Err(format!("Error deserializing metadata json: ({:?})\n JSON String = {}\n {:?}",&metadata_path, metadata_json_string, error))
-    };
+/// Read the `meta.json` saved at `metadata_path`.
+/// Returns `Err(BhcError::DuplicateId)` if two files share an id, or `Err(BhcError)` if it could not be read or deserialized.
+pub fn open_workspace_metadata(metadata_path: &Path) -> Result<WorkspaceMetaData, BhcError> {
+    let workspace_metadata: WorkspaceMetaData = read_metadata(metadata_path)?;
 
-    Ok(metadata_json)
+    match workspace_metadata.find_duplicate_id() {
+        Some(id) => Err(BhcError::DuplicateId(metadata_path.to_path_buf(), id)),
+        None => Ok(workspace_metadata)
+    }
 }
 
-fn create_default_metadata(workspace_path: &PathBuf) -> String {
+fn create_default_metadata(workspace_path: &Path) -> Result<WorkspaceMetaData, BhcError> {
     let mut metadata = WorkspaceMetaData::new();
-    metadata.workspace_path = workspace_path.to_str().unwrap().to_string();
+    metadata.workspace_path = match workspace_path.to_str() {
+        Some(value) => value.to_string(),
+        None => return Err(BhcError::path(workspace_path))
+    };
     metadata.last_updated = Utc::now();
 
-    serde_json::to_string_pretty(&metadata).unwrap()
+    Ok(metadata)
 }
 
 
 #[cfg(test)]
 mod test {
-    use super::{WorkspaceCssFile, WorkspaceMetaData};
+    use std::path::PathBuf;
+
+    use crate::metadata::GroupedJsonFiles;
+
+    use super::{WorkspaceCssFile, WorkspaceHtmlFile, WorkspaceMetaData};
 
 
     #[test]
-    fn get_next_available_css_id_test() {
+    fn allocate_css_id_test() {
+        let mut metadata = WorkspaceMetaData::new();
+
+        for id in [3, 1, 4] {
+            let mut css_file = WorkspaceCssFile::new();
+            css_file.id = id;
+            metadata.css_files.push(css_file);
+        }
+
+        // the ids don't have to be in order, and gaps aren't filled
+        assert_eq!(metadata.allocate_css_id(), 5);
+
+        // the id of a deleted file isn't given out again
+        metadata.css_files.retain(|css_file| css_file.id != 4);
+        assert_eq!(metadata.allocate_css_id(), 6);
+
+        let mut json_files = GroupedJsonFiles::new();
+        json_files.css_files.push(PathBuf::from("/workspace/.bhc/.meta/css/9.json"));
+        metadata.reserve_ids(&json_files);
+
+        assert_eq!(metadata.allocate_css_id(), 10);
+        assert_eq!(metadata.allocate_html_id(), 1);
+    }
+
+    #[test]
+    fn find_duplicate_id_test() {
+        let mut metadata = WorkspaceMetaData::new();
+
+        for id in [1, 2] {
+            let mut css_file = WorkspaceCssFile::new();
+            css_file.id = id;
+            metadata.css_files.push(css_file);
+
+            let mut html_file = WorkspaceHtmlFile::new();
+            html_file.id = 2;
+            metadata.html_files.push(html_file);
+        }
+
+        // a CSS and HTML file can share an id, as their metadata is saved in different folders
+        assert_eq!(metadata.css_files[1].id, metadata.html_files[0].id);
+        assert_eq!(metadata.find_duplicate_id(), Some(2));
+
+        metadata.html_files[1].id = 1;
+
+        assert_eq!(metadata.find_duplicate_id(), None);
+    }
+
+    #[test]
+    fn update_css_references_test() {
         let mut metadata = WorkspaceMetaData::new();
 
         let mut css_file_1 = WorkspaceCssFile::new();
         css_file_1.id = 1;
         let mut css_file_2 = WorkspaceCssFile::new();
         css_file_2.id = 2;
-        let mut css_file_3 = WorkspaceCssFile::new();
-        css_file_3.id = 3;
-        let mut css_file_4 = WorkspaceCssFile::new();
-        css_file_4.id = 4;
 
-        metadata.css_files = vec![css_file_1, css_file_2, css_file_3, css_file_4];
+        let mut html_file_1 = WorkspaceHtmlFile::new();
+        html_file_1.id = 1;
+        html_file_1.css_files = Some(vec![1, 2]);
+        let mut html_file_2 = WorkspaceHtmlFile::new();
+        html_file_2.id = 2;
+        html_file_2.css_files = Some(vec![1]);
+
+        metadata.css_files = vec![css_file_1, css_file_2];
+        metadata.html_files = vec![html_file_1, html_file_2.clone()];
+
+        metadata.update_css_references();
+
+        assert_eq!(metadata.css_files[0].html_files, Some(vec![1, 2]));
+        assert_eq!(metadata.css_files[1].html_files, Some(vec![1]));
 
-        assert_eq!(5, metadata.get_next_available_css_id());
+        metadata.html_files[0].css_files = None;
+        metadata.update_css_references();
 
-        metadata.css_files[3].id = 5;
+        assert_eq!(metadata.css_files[1].html_files, None);
+        assert_eq!(metadata.get_referencing_html_files(&1), vec![html_file_2]);
+    }
+
+    #[test]
+    fn get_linked_files_test() {
+        let mut metadata = WorkspaceMetaData::new();
+
+        for id in 1..=3 {
+            let mut css_file = WorkspaceCssFile::new();
+            css_file.id = id;
+            metadata.css_files.push(css_file);
+        }
+
+        let mut html_file_1 = WorkspaceHtmlFile::new();
+        html_file_1.id = 1;
+        html_file_1.css_files = Some(vec![1, 2]);
+        let mut html_file_2 = WorkspaceHtmlFile::new();
+        html_file_2.id = 2;
+        html_file_2.css_files = Some(vec![3]);
+
+        metadata.html_files = vec![html_file_1.clone(), html_file_2];
+        metadata.update_css_references();
 
-        assert_eq!(4, metadata.get_next_available_css_id());
+        let (css_files, html_files) = metadata.get_linked_files(&[2]);
+
+        assert_eq!(css_files.iter().map(|file| file.id).collect::<Vec<u32>>(), vec![2, 1]);
+        assert_eq!(html_files, vec![html_file_1]);
     }
-}
\ No newline at end of file
+}
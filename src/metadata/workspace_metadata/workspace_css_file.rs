@@ -10,7 +10,7 @@ pub struct WorkspaceCssFile {
     pub is_shared: bool, // is the file in the .bhc/.shared/ folder
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub html_files: Option<Vec<u32>>, // none if is_shared, or no html files reference it
+    pub html_files: Option<Vec<u32>>, // the ids of the html files that link to it, none if no html files reference it
 }
 
 impl WorkspaceCssFile {
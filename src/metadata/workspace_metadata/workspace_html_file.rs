@@ -1,8 +1,8 @@
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{metadata::html_metadata::HtmlMetaData, HTML_METADATA_PATH};
+use crate::{error::BhcError, metadata::{html_metadata::HtmlMetaData, read_metadata}, HTML_METADATA_PATH};
 
 use super::{id_to_json_file_name, WorkspaceMetaData};
 
@@ -41,18 +41,10 @@ impl WorkspaceHtmlFile {
         }
     }
 
-	pub fn update(&self, workspace_metadata: &WorkspaceMetaData) -> Result<WorkspaceHtmlFile, String> {
+	pub fn update(&self, workspace_metadata: &WorkspaceMetaData) -> Result<WorkspaceHtmlFile, BhcError> {
 		let metadata_path = PathBuf::from(&workspace_metadata.workspace_path).join(HTML_METADATA_PATH).join(id_to_json_file_name(&self.id));
 
-		let html_file_contents = match fs::read_to_string(&metadata_path) {
-			Ok(value) => value,
-			Err(error) => return Err(format!("Error trying to read HTML Metadata File ({}): {:?}", &self.absolute_path, error))
-		};
-
-		let mut html_metadata: HtmlMetaData = match serde_json::from_str(&html_file_contents) {
-			Ok(value) => value,
-			Err(error) => return Err(format!("Error trying to deserialize HTML Metadata File ({}): {:?}", &self.absolute_path, error))
-		};
+		let mut html_metadata: HtmlMetaData = read_metadata(&metadata_path)?;
 
 		// Updates the HtmlMetaData and returns a new version of WorkspaceHtmlFile to reflect its contents
 		let workspace_html_file = match html_metadata.update_metadata(&metadata_path, workspace_metadata) {
@@ -0,0 +1,111 @@
+use cssparser::SourceLocation;
+use tower_lsp::lsp_types::{Position, Range};
+
+/// Convert a cssparser location into an LSP position, cssparser columns start at 1 where LSP characters start at 0.
+pub fn to_position(location: SourceLocation) -> Position {
+    Position::new(location.line, location.column.saturating_sub(1))
+}
+
+/// Get the position at the end of `text`, if it was written starting at `start`. Characters are counted in UTF-16 code units, the same as LSP and cssparser.
+pub fn advance_position(start: Position, text: &str) -> Position {
+    match text.rsplit_once('\n') {
+        Some((before, last_line)) => Position::new(start.line + before.matches('\n').count() as u32 + 1, last_line.encode_utf16().count() as u32),
+        None => Position::new(start.line, start.character + text.encode_utf16().count() as u32),
+    }
+}
+
+/// Get the position of the byte `offset` inside of `text`. Offsets past the end of the text are clamped to the end.
+pub fn offset_to_position(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    advance_position(Position::new(0, 0), &text[..offset])
+}
+
+/// Get the byte offset of `position` inside of `text`.
+/// Returns `None` if the line doesn't exist, a character past the end of a line is clamped to the end of that line.
+pub fn position_to_offset(text: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+
+    for _ in 0..position.line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+
+    let line = text[line_start..].split('\n').next().unwrap_or_default();
+    let mut utf16_count: u32 = 0;
+
+    for (index, character) in line.char_indices() {
+        if utf16_count >= position.character {
+            return Some(line_start + index)
+        }
+
+        utf16_count += character.len_utf16() as u32;
+    }
+
+    Some(line_start + line.trim_end_matches('\r').len())
+}
+
+/// Move a `position` inside of a piece of text to where it is in the document that the text starts at `origin` in, e.g. the contents of a `<style>` element.
+pub fn translate_position(position: Position, origin: Position) -> Position {
+    if position.line == 0 {
+        Position::new(origin.line, origin.character + position.character)
+    } else {
+        Position::new(origin.line + position.line, position.character)
+    }
+}
+
+/// Whether `position` is inside of `range`, the end of the range is included so the cursor can be just after the last character.
+pub fn range_contains(range: &Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+/// Get the word under `position`, and its range. A word is made of the characters allowed in CSS identifiers, e.g. `btn-primary` or `font-size`.
+pub fn word_at_position(text: &str, position: Position) -> Option<(String, Range)> {
+    let offset = position_to_offset(text, position)?;
+
+    let is_word_character = |character: char| character.is_alphanumeric() || character == '-' || character == '_';
+
+    let start = text[..offset].char_indices().rev().take_while(|(_, character)| is_word_character(*character)).last().map(|(index, _)| index).unwrap_or(offset);
+    let end = text[offset..].char_indices().find(|(_, character)| !is_word_character(*character)).map(|(index, _)| offset + index).unwrap_or(text.len());
+
+    if start == end {
+        return None
+    }
+
+    Some((text[start..end].to_string(), Range::new(offset_to_position(text, start), offset_to_position(text, end))))
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{Position, Range};
+
+    use super::{offset_to_position, position_to_offset, translate_position, word_at_position};
+
+    #[test]
+    fn position_offset_test() {
+        let text = "h1 {\n\tcolor: red;\n}\n";
+
+        assert_eq!(offset_to_position(text, 7), Position::new(1, 2));
+        assert_eq!(position_to_offset(text, Position::new(1, 2)), Some(7));
+        assert_eq!(position_to_offset(text, Position::new(1, 100)), Some(17));
+        assert_eq!(position_to_offset(text, Position::new(10, 0)), None);
+    }
+
+    #[test]
+    fn translate_position_test() {
+        assert_eq!(translate_position(Position::new(0, 4), Position::new(3, 10)), Position::new(3, 14));
+        assert_eq!(translate_position(Position::new(2, 4), Position::new(3, 10)), Position::new(5, 4));
+    }
+
+    #[test]
+    fn word_at_position_test() {
+        let text = "<div class=\"card btn-primary\">";
+
+        assert_eq!(word_at_position(text, Position::new(0, 20)), Some((String::from("btn-primary"), Range::new(Position::new(0, 17), Position::new(0, 28)))));
+        assert_eq!(word_at_position(text, Position::new(0, 16)), Some((String::from("card"), Range::new(Position::new(0, 12), Position::new(0, 16)))));
+        assert_eq!(word_at_position(text, Position::new(0, 0)), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, ffi::OsStr, path::PathBuf};
+
+use tower_lsp::lsp_types::{Location, Range, ReferenceParams, Url};
+
+use crate::{
+    error::{uri_to_path, BhcError},
+    definition::get_symbol_locations,
+    document_store::read_file,
+    metadata::{
+        css_metadata::symbol_at_css_position,
+        html_metadata::html_element::{parse_elements, HtmlElement, HtmlSymbol},
+    },
+    workspace::FileScope,
+    Backend, EXT_CSS,
+};
+
+impl Backend {
+    /// Get every use in the workspace's HTML documents of the class, id or element in the selector under the cursor of the CSS file in `params`.
+    /// Only the HTML documents that link to the stylesheet are searched, using the `html_files` of its `WorkspaceCssFile`.
+    /// Returns `Ok(None)` if the cursor isn't on a selector, or nothing uses it.
+    /// Returns `Err(BhcError)` if the stylesheet or the workspace metadata could not be read.
+    pub async fn get_references(&self, params: &ReferenceParams) -> Result<Option<Vec<Location>>, BhcError> {
+        let position = params.text_document_position.position;
+        let uri = &params.text_document_position.text_document.uri;
+
+        let file_path = match uri_to_path(uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        if file_path.extension().and_then(OsStr::to_str) != Some(EXT_CSS) {
+            return Ok(None)
+        }
+
+        let css_string = match self.read_document(&file_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let symbol = match symbol_at_css_position(&css_string, position) {
+            Some((symbol, _)) => symbol,
+            None => return Ok(None)
+        };
+
+        // a loose stylesheet isn't linked from any known document
+        let html_paths: Vec<PathBuf> = match self.get_file_scope(&file_path).await {
+            FileScope::Workspace(workspace_path) => {
+                let workspace_metadata = match self.get_workspace_metadata(&workspace_path).await {
+                    Ok(value) => value,
+                    Err(error) => return Err(error)
+                };
+
+                match workspace_metadata.get_css_file_id(&file_path) {
+                    Some(id) => workspace_metadata
+                    .get_referencing_html_files(&id)
+                    .iter()
+                    .map(|html_file| PathBuf::from(&html_file.absolute_path))
+                    .collect(),
+                    None => Vec::new()
+                }
+            },
+            FileScope::Loose => Vec::new()
+        };
+
+        let open_documents = self.documents.get_texts().await;
+
+        let mut locations = get_html_locations(&html_paths, &symbol, &open_documents);
+
+        if params.context.include_declaration {
+            locations.extend(get_symbol_locations(&[file_path], &symbol, &open_documents));
+        }
+
+        if locations.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(locations))
+        }
+    }
+}
+
+/// Get the location of every use of the `symbol` in the HTML documents at `html_paths`. Documents that can't be read are skipped.
+/// Documents in `open_documents` are read from there, so the locations match what is in the editor.
+pub fn get_html_locations(html_paths: &[PathBuf], symbol: &HtmlSymbol, open_documents: &HashMap<PathBuf, String>) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+
+    for html_path in html_paths {
+        let html_string = match read_file(open_documents, html_path) {
+            Some(value) => value,
+            None => continue
+        };
+
+        let uri = match Url::from_file_path(html_path) {
+            Ok(value) => value,
+            Err(_) => continue
+        };
+
+        for range in find_element_ranges(&parse_elements(&html_string), symbol) {
+            locations.push(Location::new(uri.clone(), range));
+        }
+    }
+
+    locations
+}
+
+/// Find the range of every use of the `symbol` in the `elements`. For classes and ids this is the name inside of the attribute value, for elements it is the tag name.
+pub fn find_element_ranges(elements: &[HtmlElement], symbol: &HtmlSymbol) -> Vec<Range> {
+    let mut ranges: Vec<Range> = Vec::new();
+
+    for element in elements {
+        match symbol {
+            HtmlSymbol::Element(name) => {
+                // element names in selectors aren't case sensitive
+                if element.name.eq_ignore_ascii_case(name) {
+                    ranges.push(element.name_range);
+                }
+            },
+            HtmlSymbol::Class(name) => {
+                ranges.extend(element.classes().into_iter().filter(|(class, _)| class == name).map(|(_, range)| range));
+            },
+            HtmlSymbol::Id(name) => {
+                if let Some((_, range)) = element.id().filter(|(id, _)| id == name) {
+                    ranges.push(range);
+                }
+            },
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use tower_lsp::lsp_types::{Position, Range, Url};
+
+    use crate::metadata::html_metadata::html_element::{parse_elements, HtmlSymbol};
+
+    use super::{find_element_ranges, get_html_locations};
+
+    #[test]
+    fn find_element_ranges_test() {
+        let html_string = "<DIV class=\"card\" id=\"main\">\n\t<p class=\"card-title card\">Title</p>\n</DIV>";
+
+        let elements = parse_elements(html_string);
+
+        assert_eq!(
+            find_element_ranges(&elements, &HtmlSymbol::Class(String::from("card"))),
+            vec![Range::new(Position::new(0, 12), Position::new(0, 16)), Range::new(Position::new(1, 22), Position::new(1, 26))]
+        );
+        assert_eq!(find_element_ranges(&elements, &HtmlSymbol::Id(String::from("main"))), vec![Range::new(Position::new(0, 22), Position::new(0, 26))]);
+        assert_eq!(find_element_ranges(&elements, &HtmlSymbol::Element(String::from("div"))), vec![Range::new(Position::new(0, 1), Position::new(0, 4))]);
+        assert!(find_element_ranges(&elements, &HtmlSymbol::Id(String::from("card"))).is_empty());
+    }
+
+    #[test]
+    fn get_html_locations_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_references_test");
+        let html_path = workspace_path.join("index.html");
+
+        fs::create_dir_all(&workspace_path).unwrap();
+        fs::write(&html_path, "<div class=\"card\"></div>").unwrap();
+
+        let locations = get_html_locations(&[html_path.clone(), workspace_path.join("missing.html")], &HtmlSymbol::Class(String::from("card")), &HashMap::new());
+
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].uri, Url::from_file_path(&html_path).unwrap());
+        assert_eq!(locations[0].range, Range::new(Position::new(0, 12), Position::new(0, 16)));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, ffi::OsStr, path::{Path, PathBuf}};
+
+use tower_lsp::lsp_types::{Location, Position, PrepareRenameResponse, Range, RenameParams, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit};
+
+use crate::{
+    error::{uri_to_path, BhcError},
+    definition::get_symbol_locations,
+    document_store::read_file,
+    metadata::{
+        css_metadata::symbol_at_css_position,
+        html_metadata::{find_inline_symbol_ranges, html_element::{parse_elements, symbol_at_position, HtmlSymbol}},
+        workspace_metadata::WorkspaceMetaData,
+    },
+    references::get_html_locations,
+    workspace::FileScope,
+    Backend, EXT_CSS, EXT_HTML,
+};
+
+impl Backend {
+    /// Check that the class or id under the cursor can be renamed, and get the range of its name.
+    /// Returns `Ok(None)` if the cursor is on an element name, or anything else that isn't a class or id.
+    /// Returns `Err(BhcError)` if the document could not be read.
+    pub async fn get_prepare_rename(&self, params: &TextDocumentPositionParams) -> Result<Option<PrepareRenameResponse>, BhcError> {
+        let file_path = match uri_to_path(&params.text_document.uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let file_string = match self.read_document(&file_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        match get_renameable_symbol(&file_path, &file_string, params.position) {
+            Some((_, range)) => Ok(Some(PrepareRenameResponse::Range(range))),
+            None => Ok(None)
+        }
+    }
+
+    /// Rename the class or id under the cursor in every stylesheet and HTML document that share styles with the document in `params`.
+    /// The files are found with the links in the `WorkspaceMetaData`, so classes that only share a prefix with the renamed one are left alone.
+    /// Returns `Ok(None)` if there is nothing to rename at the position.
+    /// Returns `Err(BhcError)` if the new name isn't a valid class or id, or the document or workspace metadata could not be read.
+    pub async fn get_rename(&self, params: &RenameParams) -> Result<Option<WorkspaceEdit>, BhcError> {
+        let uri = &params.text_document_position.text_document.uri;
+
+        let file_path = match uri_to_path(uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let file_string = match self.read_document(&file_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        let symbol = match get_renameable_symbol(&file_path, &file_string, params.text_document_position.position) {
+            Some((symbol, _)) => symbol,
+            None => return Ok(None)
+        };
+
+        if !is_valid_identifier(&params.new_name) {
+            return Err(BhcError::InvalidName(params.new_name.clone()))
+        }
+
+        let scope = self.get_file_scope(&file_path).await;
+
+        // a loose file has no links in any metadata, so only it and the stylesheets it links to are renamed
+        let workspace_metadata = match &scope {
+            FileScope::Workspace(workspace_path) => match self.get_workspace_metadata(workspace_path).await {
+                Ok(value) => value,
+                Err(error) => return Err(error)
+            },
+            FileScope::Loose => WorkspaceMetaData::new()
+        };
+
+        let mut css_paths: Vec<PathBuf> = Vec::new();
+        let mut html_paths: Vec<PathBuf> = Vec::new();
+
+        let css_ids: Vec<u32> = if file_path.extension().and_then(OsStr::to_str) == Some(EXT_HTML) {
+            html_paths.push(file_path.clone());
+
+            let linked_paths = self.get_linked_stylesheet_paths(&scope, &file_path, &file_string).await;
+
+            for css_path in &linked_paths {
+                if workspace_metadata.get_css_file_id(css_path).is_none() {
+                    css_paths.push(css_path.clone());
+                }
+            }
+
+            linked_paths.iter().filter_map(|css_path| workspace_metadata.get_css_file_id(css_path)).collect()
+        } else {
+            match workspace_metadata.get_css_file_id(&file_path) {
+                Some(id) => vec![id],
+                None => {
+                    css_paths.push(file_path.clone());
+                    Vec::new()
+                }
+            }
+        };
+
+        let (css_files, html_files) = workspace_metadata.get_linked_files(&css_ids);
+
+        for css_file in css_files {
+            let css_path = PathBuf::from(&css_file.absolute_path);
+
+            if !css_paths.contains(&css_path) {
+                css_paths.push(css_path);
+            }
+        }
+
+        for html_file in html_files {
+            let html_path = PathBuf::from(&html_file.absolute_path);
+
+            if !html_paths.contains(&html_path) {
+                html_paths.push(html_path);
+            }
+        }
+
+        // the edits are applied to what is in the editor, so unsaved documents are read from there
+        let changes = get_rename_edits(&css_paths, &html_paths, &symbol, &params.new_name, &self.documents.get_texts().await);
+
+        if changes.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(WorkspaceEdit::new(changes)))
+        }
+    }
+}
+
+/// Find the class or id at `position` in `file_string`, the contents of the CSS or HTML file at `file_path`, and the range of its name.
+/// Returns `None` if the file isn't CSS or HTML, or the position isn't on a class or id.
+fn get_renameable_symbol(file_path: &Path, file_string: &str, position: Position) -> Option<(HtmlSymbol, Range)> {
+    let extension = file_path.extension().and_then(OsStr::to_str);
+
+    let symbol = match extension {
+        Some(EXT_CSS) => symbol_at_css_position(file_string, position),
+        Some(EXT_HTML) => symbol_at_position(&parse_elements(file_string), position).map(|(_, symbol, range)| (symbol, range)),
+        _ => None
+    };
+
+    // element names are part of HTML itself, so they can't be renamed
+    match symbol {
+        Some((HtmlSymbol::Element(_), _)) | None => None,
+        Some(value) => Some(value)
+    }
+}
+
+/// Get the edits that rename the `symbol` to `new_name` in every selector of the stylesheets at `css_paths`, and every `class` or `id` of the HTML documents at `html_paths`.
+/// Files in `open_documents` are read from there, see [`get_symbol_locations`].
+pub fn get_rename_edits(css_paths: &[PathBuf], html_paths: &[PathBuf], symbol: &HtmlSymbol, new_name: &str, open_documents: &HashMap<PathBuf, String>) -> HashMap<Url, Vec<TextEdit>> {
+    let mut locations: Vec<Location> = get_symbol_locations(css_paths, symbol, open_documents);
+    locations.extend(get_html_locations(html_paths, symbol, open_documents));
+    locations.extend(get_inline_style_locations(html_paths, symbol, open_documents));
+
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+
+    for location in locations {
+        changes.entry(location.uri).or_default().push(TextEdit::new(location.range, new_name.to_string()));
+    }
+
+    changes
+}
+
+/// Find every selector that uses the `symbol` inside of the `<style>` elements of the HTML documents at `html_paths`.
+/// Files in `open_documents` are read from there, see [`get_symbol_locations`].
+fn get_inline_style_locations(html_paths: &[PathBuf], symbol: &HtmlSymbol, open_documents: &HashMap<PathBuf, String>) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+
+    for html_path in html_paths {
+        let html_string = match read_file(open_documents, html_path) {
+            Some(value) => value,
+            None => continue
+        };
+
+        let uri = match Url::from_file_path(html_path) {
+            Ok(value) => value,
+            Err(_) => continue
+        };
+
+        for range in find_inline_symbol_ranges(&html_string, symbol) {
+            locations.push(Location::new(uri.clone(), range));
+        }
+    }
+
+    locations
+}
+
+/// Whether `name` can be used as a class or id in both HTML and CSS without escaping it, e.g. `btn-primary` or `_nav`.
+pub fn is_valid_identifier(name: &str) -> bool {
+    let is_name_character = |character: char| character.is_alphanumeric() || character == '-' || character == '_';
+
+    // after two dashes anything can follow, otherwise the name can't start with a digit
+    let start_is_valid = name.starts_with("--") || match name.strip_prefix('-').unwrap_or(name).chars().next() {
+        Some(character) => character.is_alphabetic() || character == '_',
+        None => false
+    };
+
+    start_is_valid && name.chars().all(is_name_character)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use tower_lsp::lsp_types::{Position, Range, TextEdit, Url};
+
+    use crate::metadata::html_metadata::html_element::HtmlSymbol;
+
+    use super::{get_rename_edits, is_valid_identifier};
+
+    #[test]
+    fn get_rename_edits_test() {
+        let workspace_path = std::env::temp_dir().join("bhc_rename_test");
+        let html_path = workspace_path.join("index.html");
+        let css_path = workspace_path.join("base.css");
+
+        fs::create_dir_all(&workspace_path).unwrap();
+        fs::write(&html_path, "<a class=\"btn btn-primary\">Link</a>\n<a class=\"btn-primary-outline\">Link</a>\n<p class=\"lead\n\t\tbtn-primary\">Text</p>\n<style>p .btn-primary { margin: 0; }</style>").unwrap();
+        fs::write(&css_path, ".btn-primary { color: red; }\n.btn-primary-outline:hover, .btn.btn-primary { color: blue; }").unwrap();
+
+        let changes = get_rename_edits(std::slice::from_ref(&css_path), std::slice::from_ref(&html_path), &HtmlSymbol::Class(String::from("btn-primary")), "btn-main", &HashMap::new());
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[&Url::from_file_path(&css_path).unwrap()],
+            vec![
+                TextEdit::new(Range::new(Position::new(0, 1), Position::new(0, 12)), String::from("btn-main")),
+                TextEdit::new(Range::new(Position::new(1, 33), Position::new(1, 44)), String::from("btn-main")),
+            ]
+        );
+        assert_eq!(
+            changes[&Url::from_file_path(&html_path).unwrap()],
+            vec![
+                TextEdit::new(Range::new(Position::new(0, 14), Position::new(0, 25)), String::from("btn-main")),
+                // a class on a later line of the attribute, and a selector inside of a `<style>`
+                TextEdit::new(Range::new(Position::new(3, 2), Position::new(3, 13)), String::from("btn-main")),
+                TextEdit::new(Range::new(Position::new(4, 10), Position::new(4, 21)), String::from("btn-main")),
+            ]
+        );
+    }
+
+    #[test]
+    fn is_valid_identifier_test() {
+        assert!(is_valid_identifier("btn-primary"));
+        assert!(is_valid_identifier("_nav"));
+        assert!(is_valid_identifier("-card"));
+        assert!(is_valid_identifier("--"));
+        assert!(is_valid_identifier("--1st"));
+        assert!(!is_valid_identifier(""));
+        assert!(!is_valid_identifier("1st"));
+        assert!(!is_valid_identifier("-1st"));
+        assert!(!is_valid_identifier(".card"));
+        assert!(!is_valid_identifier("two words"));
+    }
+}
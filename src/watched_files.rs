@@ -0,0 +1,101 @@
+use std::ffi::OsStr;
+
+use tower_lsp::lsp_types::{DidChangeWatchedFilesRegistrationOptions, FileChangeType, FileEvent, FileSystemWatcher, GlobPattern, Registration};
+
+use crate::{config::FileFilter, error::{uri_to_path, BhcError}, file::is_virtual_file, glob::is_gitignore, metadata::metadata_lock::MetadataLock, workspace::FileScope, Backend, CONFIG_PATH, EXT_CSS, EXT_HTML, METADATA_PATH};
+
+const FILE_WATCHER_ID: &str = "bhc-file-watcher";
+
+impl Backend {
+    /// Ask the client to send `workspace/didChangeWatchedFiles` for every HTML and CSS file, so files changed outside of the editor, e.g. by git or a build tool, keep their metadata up to date.
+    /// The `.bhc/config.json` and `.gitignore` files are watched too, as they decide which files have metadata.
+    /// Errors are logged, e.g. when the client doesn't support registering watchers dynamically.
+    pub async fn register_file_watchers(&self) {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(format!("**/*.{{{},{}}}", EXT_HTML, EXT_CSS)),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(format!("**/{}", CONFIG_PATH)),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(String::from("**/.gitignore")),
+                    kind: None,
+                },
+            ],
+        };
+
+        let registration = Registration {
+            id: String::from(FILE_WATCHER_ID),
+            method: String::from("workspace/didChangeWatchedFiles"),
+            register_options: serde_json::to_value(options).ok(),
+        };
+
+        if let Err(error) = self.client.register_capability(vec![registration]).await {
+            self.report_error(BhcError::Client(format!("Could not register file watchers: {}", error))).await;
+        }
+    }
+
+    /// Create, update or delete the metadata of each HTML and CSS file in `changes`, in the order they happened.
+    /// Files the configuration of their workspace leaves out have their metadata deleted, and a change to the configuration or a `.gitignore` searches the workspace again.
+    /// Loose files, virtual files and any other kind of file are ignored. Errors are logged and don't stop the other changes.
+    pub async fn apply_file_events(&self, changes: &[FileEvent]) {
+        for change in changes {
+            if let Err(error) = self.apply_file_event(change).await {
+                self.report_error(error).await;
+            }
+        }
+    }
+
+    async fn apply_file_event(&self, event: &FileEvent) -> Result<(), BhcError> {
+        let file_path = match uri_to_path(&event.uri) {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        if is_gitignore(&file_path) || file_path.ends_with(CONFIG_PATH) {
+            // which files are searched could have changed, so the whole workspace is searched again
+            if let FileScope::Workspace(workspace_path) = self.get_file_scope(&file_path).await {
+                self.initialize_workspace(&workspace_path).await;
+            }
+
+            return Ok(())
+        }
+
+        let extension = file_path.extension().and_then(OsStr::to_str);
+
+        if (extension != Some(EXT_CSS) && extension != Some(EXT_HTML)) || is_virtual_file(&file_path) {
+            return Ok(())
+        }
+
+        let workspace_path = match self.get_file_scope(&file_path).await {
+            FileScope::Workspace(value) => value,
+            FileScope::Loose => return Ok(())
+        };
+
+        // workspaces without any web documents aren't initialized when opened, so the first one to be created does it
+        if !workspace_path.join(METADATA_PATH).is_file() {
+            self.initialize_workspace(&workspace_path).await;
+            return Ok(())
+        }
+
+        let filter = FileFilter::new(&workspace_path, &self.get_workspace_config(&workspace_path).await);
+        let is_included = filter.is_included(&file_path);
+
+        // another editor open on the same folder could be changing the same metadata
+        let _lock = match MetadataLock::acquire(&workspace_path).await {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+        };
+
+        match event.typ {
+            FileChangeType::CREATED | FileChangeType::CHANGED if is_included => self.add_file_metadata(&workspace_path, &file_path).await,
+            // a file that is left out doesn't keep its metadata, e.g. once it grows past the maximum size
+            FileChangeType::CREATED | FileChangeType::CHANGED | FileChangeType::DELETED => self.remove_file_metadata(&workspace_path, &file_path).await,
+            _ => Ok(())
+        }
+    }
+}
@@ -1,44 +1,60 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}};
 
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use tower_lsp::lsp_types::{TextDocumentItem, WorkspaceFolder};
 
-use crate::{file::{contains_web_documents, recursive_file_search}, logging::Logging, metadata::{css_metadata::CssMetaData, html_metadata::HtmlMetaData, workspace_metadata::{create_workspace_metadata, id_to_json_file_name, open_workspace_metadata, workspace_css_file::WorkspaceCssFile, workspace_html_file::WorkspaceHtmlFile, WorkspaceMetaData}, GroupedFiles}, Backend, CSS_METADATA_PATH, HTML_METADATA_PATH, METADATA_PATH, SHARED_PATH};
+use crate::{config::FileFilter, error::{uri_to_path, BhcError}, file::{contains_web_documents, recursive_file_search}, logging::Logging, metadata::{css_metadata::CssMetaData, html_metadata::HtmlMetaData, metadata_cache::{read_source_files, write_metadata_files, WorkspaceIndex}, metadata_lock::MetadataLock, migration::{migrate_workspace, rebuild_metadata}, reconcile::reconcile_workspace, workspace_metadata::{create_workspace_metadata, id_to_json_file_name, open_workspace_metadata, workspace_css_file::WorkspaceCssFile, workspace_html_file::WorkspaceHtmlFile, WorkspaceMetaData}, read_source, write_metadata, GroupedFiles, Staleness}, Backend, CSS_METADATA_PATH, HTML_METADATA_PATH, METADATA_PATH, SHARED_PATH};
 
 impl Backend {
-	/// Get the workspaces that are currently open. Calls into the LSP [`workspace/workspaceFolders`](https://microsoft.github.io/language-server-protocol/specification#workspace_workspaceFolders)
-	/// Returns `Ok(Vec<PathBuf>)` if there are workspaces and was able to transform from `WorkspaceFolder` to `PathBuf`.
-	/// Returns `Err(String)` if there was no workspaces found or able to be converted.
-	
-	pub async fn get_workspaces(&self) -> Result<Vec<PathBuf>, String> {
-        let mut workspace_pathbufs: Vec<PathBuf> = Vec::new(); 
-        
-        match self.client.workspace_folders().await {
-            Ok(value) => {
-                if let Some(workspaces) = value {
-                    workspace_pathbufs = self.parse_workspaces(&workspaces).await;
-                }
-            },
-            Err(error) => {
-                return Err(format!("Error occurred trying to get workspace folders: {:?}", error))
-            },
-        };
-
-        if workspace_pathbufs.is_empty() {
-            return Err(String::from("There are no workspaces open"))
-        }
-
-		Ok(workspace_pathbufs)
-    }
+	/// Get the workspaces that are currently open, from the folders cached by [`Backend::load_workspace_folders`] and kept up to date as folders are added and removed.
+	/// Returns `Ok(Vec<PathBuf>)` if there are workspaces.
+	/// Returns `Err(BhcError::NoWorkspaces)` if there are none open.
+	pub async fn get_workspaces(&self) -> Result<Vec<PathBuf>, BhcError> {
+		let workspaces = self.workspace_folders.read().await.clone();
+
+		if workspaces.is_empty() {
+			return Err(BhcError::NoWorkspaces)
+		}
+
+		Ok(workspaces)
+	}
+
+	/// Ask the client for the workspaces that are open and cache them, this is done once the server is initialized. Calls into the LSP [`workspace/workspaceFolders`](https://microsoft.github.io/language-server-protocol/specification#workspace_workspaceFolders)
+	/// Returns `Err(BhcError)` if the client could not be asked, in which case the cache is left as it was.
+	pub async fn load_workspace_folders(&self) -> Result<(), BhcError> {
+		let workspace_folders = match self.client.workspace_folders().await {
+			Ok(value) => value.unwrap_or_default(),
+			Err(error) => return Err(BhcError::Client(error.to_string()))
+		};
+
+		let workspaces = self.parse_workspaces(&workspace_folders).await;
+
+		*self.workspace_folders.write().await = workspaces;
+
+		Ok(())
+	}
+
+	/// Add the `added` workspaces to the cached workspace folders, and remove the `removed` ones.
+	pub async fn change_workspace_folders(&self, added: &[PathBuf], removed: &[PathBuf]) {
+		let mut workspace_folders = self.workspace_folders.write().await;
+
+		workspace_folders.retain(|workspace| !removed.contains(workspace));
+
+		for workspace in added {
+			if !workspace_folders.contains(workspace) {
+				workspace_folders.push(workspace.clone());
+			}
+		}
+	}
 
 	pub async fn parse_workspaces(&self, workspace_folders: &Vec<WorkspaceFolder>) -> Vec<PathBuf> {
 		let mut workspaces: Vec<PathBuf> = Vec::new();
 
 		for workspace in workspace_folders {
-			match workspace.uri.to_file_path() {
+			match uri_to_path(&workspace.uri) {
 				Ok(value) => workspaces.push(value),
 				Err(error) => {
-					self.log_error(format!("Error occurred trying to transform URI to PathBuf ({:?}) {:?}", workspace.uri, error)).await;
+					self.report_error(error).await;
 					continue
 				}
 			}
@@ -48,125 +64,338 @@ impl Backend {
 	}
 
 
+	/// For the supplied `file_path`, find the workspace it belongs to. See [`find_workspace_folder`].
+	/// Returns `FileScope::Loose` if it isn't inside of any open workspace, or there are no workspaces open.
+	pub async fn get_file_scope(&self, file_path: &Path) -> FileScope {
+		let workspaces = self.get_workspaces().await.unwrap_or_default();
+
+		match find_workspace_folder(&workspaces, file_path) {
+			Some(value) => FileScope::Workspace(value),
+			None => FileScope::Loose
+		}
+	}
+
 	/// For the supplied `file_path`, get the workspace `PathBuf` it belongs to.
 	/// Returns `Ok(PathBuf)` if it was able to find it.
-	/// Returns `Err(String)` if it couldn't find the workspace. This means that the file was opened externally, and not belonging to any currently open workspace.
-	pub async fn get_workspace_folder(&self, file_path: &PathBuf) -> Result<PathBuf, String> {
-       let workspaces = match self.get_workspaces().await {
-			Ok(value) => value,
-			Err(error) => return Err(error)
-	   };
-
-        match workspaces.first() {
-            Some(value) => Ok(value.clone()),
-            None => Err(format!("Could not find workspace for file: {:?}", file_path)),
-        }
-    }
+	/// Returns `Err(BhcError)` if it couldn't find the workspace. This means that the file was opened externally, and not belonging to any currently open workspace.
+	pub async fn get_workspace_folder(&self, file_path: &Path) -> Result<PathBuf, BhcError> {
+		match self.get_file_scope(file_path).await {
+			FileScope::Workspace(value) => Ok(value),
+			FileScope::Loose => Err(BhcError::Workspace(file_path.to_path_buf())),
+		}
+	}
 
-	/// For the supplied `workspace_path`, get the workspace metadata, 
+	/// For the supplied `workspace_path`, get the workspace metadata from the `MetadataCache`, 
 	/// Returns `Ok(WorkspaceMetaData)` on success.
-	/// Returns `Err(String)` if it was unable to find the file, or had trouble deseralizing it.
-	pub async fn get_workspace_metadata(&self, workspace_path: &PathBuf) -> Result<WorkspaceMetaData, String> {
-		match get_workspace_metadata(&workspace_path) {
-			Ok(value) => Ok(value),
-			Err(error) => Err(error)
-		}
+	/// Returns `Err(BhcError)` if the workspace hasn't been cached yet and it was unable to find the file, or had trouble deseralizing it.
+	pub async fn get_workspace_metadata(&self, workspace_path: &Path) -> Result<WorkspaceMetaData, BhcError> {
+		self.metadata_cache.read(workspace_path, |index| index.get_workspace_metadata()).await
 	}
 
 	/// For the supplied `text_document`, get the CSS metadata, 
 	/// Returns `Ok(CssMetaData)` on success.
-	/// Returns `Err(String)` if it was unable to find the file, or had trouble deseralizing it.
-	pub async fn get_css_metadata(&self, text_document: &TextDocumentItem) -> Result<CssMetaData, String> {
-		let workspace_path = match self.get_workspace(text_document).await {
+	/// Returns `Err(BhcError)` if the document isn't a stylesheet in any workspace, or the workspace metadata could not be read.
+	pub async fn get_css_metadata(&self, text_document: &TextDocumentItem) -> Result<CssMetaData, BhcError> {
+		let file_path = uri_to_path(&text_document.uri)?;
+
+		let workspace_path = match self.get_workspace_folder(&file_path).await {
 			Ok(value) => value, 
-			Err(error) => {
-				return Err(error)
-			}
+			Err(error) => return Err(error)
 		};
 
-		let file_path = text_document.uri.to_file_path().unwrap();
+		match self.metadata_cache.read(&workspace_path, |index| index.get_css_metadata(&file_path).cloned()).await {
+			Ok(Some(value)) => Ok(value),
+			Ok(None) => Err(BhcError::metadata(&file_path)),
+			Err(error) => Err(error)
+		}
+	}
 
-		match get_css_metadata(&workspace_path, &file_path) {
-			Ok(value) => return Ok(value),
-			Err(error) => {
-				return Err(error);
+	/// For the supplied `file_path` of a HTML document in the workspace at `workspace_path`, get the HTML metadata.
+	/// Returns `Ok(HtmlMetaData)` on success.
+	/// Returns `Err(BhcError)` if the document has no metadata, or the workspace metadata could not be read.
+	pub async fn get_html_metadata(&self, workspace_path: &Path, file_path: &Path) -> Result<HtmlMetaData, BhcError> {
+		match self.metadata_cache.read(workspace_path, |index| index.get_html_metadata(file_path).cloned()).await {
+			Ok(Some(value)) => Ok(value),
+			Ok(None) => Err(BhcError::metadata(file_path)),
+			Err(error) => Err(error)
+		}
+	}
+
+	/// Get the metadata of every stylesheet at `file_paths` in the workspace at `workspace_path`. The metadatas are in the same order as `file_paths`, so linked stylesheets keep the order they cascade in.
+	/// Returns `Ok(Some(Vec<CssMetaData>))` if there are any 
+	/// Returns `Ok(None)` if none of the stylesheets have metadata, e.g. they are outside of the workspace
+	/// Returns `Err(BhcError)` if the workspace metadata could not be read
+	pub async fn get_metadata_files(&self, file_paths: &[PathBuf], workspace_path: &Path) -> Result<Option<Vec<CssMetaData>>, BhcError> {
+		self.metadata_cache.read(workspace_path, |index| index.get_metadata_files(file_paths)).await
+	}
+
+	/// Get the metadata of every stylesheet at `css_paths`, for a file in the `scope`, in the same order as `css_paths`.
+	/// Loose files have no metadata, so their stylesheets are parsed from the editor or disk and given ids in the order they are linked. Stylesheets that can't be read are left out.
+	/// Returns `Ok(None)` if none of the stylesheets have metadata.
+	/// Returns `Err(BhcError)` if the workspace metadata could not be read.
+	pub async fn get_stylesheets(&self, scope: &FileScope, css_paths: &[PathBuf]) -> Result<Option<Vec<CssMetaData>>, BhcError> {
+		let workspace_path = match scope {
+			FileScope::Workspace(value) => value,
+			FileScope::Loose => {
+				let mut stylesheets: Vec<CssMetaData> = Vec::new();
+
+				for css_path in css_paths {
+					let css_string = match self.read_document(css_path).await {
+						Ok(value) => value,
+						Err(_) => continue
+					};
+
+					let mut stylesheet = CssMetaData::new();
+					stylesheet.id = stylesheets.len() as u32 + 1;
+					stylesheet.file_name = css_path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+					stylesheet.absolute_path = css_path.to_str().unwrap_or_default().to_string();
+					stylesheet.parse_contents(&css_string);
+
+					stylesheets.push(stylesheet);
+				}
+
+				return Ok(if stylesheets.is_empty() { None } else { Some(stylesheets) })
 			}
+		};
+
+		self.get_metadata_files(css_paths, workspace_path).await
+	}
+
+	/// Update the metadata of the CSS or HTML file at `file_path` after it has been saved, both on disk and in the `MetadataCache`.
+	/// The file is read and its metadata is written while the cache is free, so other requests aren't held up by the disk.
+	/// Loose files, and files that weren't given metadata when the workspace was initialized, are left alone.
+	/// Returns `Err(BhcError)` if the file or its metadata could not be read or written.
+	pub async fn update_file_metadata(&self, file_path: &Path) -> Result<(), BhcError> {
+		let workspace_path = match self.get_file_scope(file_path).await {
+			FileScope::Workspace(value) => value,
+			FileScope::Loose => return Ok(())
+		};
+
+		// workspaces without any web documents were never initialized, so they have no metadata to update
+		if !workspace_path.join(METADATA_PATH).is_file() {
+			return Ok(())
 		}
+
+		let _lock = match MetadataLock::acquire(&workspace_path).await {
+			Ok(value) => value,
+			Err(error) => return Err(error)
+		};
+
+		match self.metadata_cache.read(&workspace_path, |index| index.has_file_metadata(file_path)).await {
+			Ok(true) => (),
+			Ok(false) => return Ok(()),
+			Err(error) => return Err(error)
+		};
+
+		let (contents, stamp) = match read_source(file_path) {
+			Ok(value) => value,
+			Err(error) => return Err(error)
+		};
+
+		let writes = match self.metadata_cache.write(&workspace_path, |index| index.update_file_metadata(&workspace_path, file_path, &contents, stamp)).await {
+			Ok(Ok(Some(value))) => value,
+			Ok(Ok(None)) => return Ok(()),
+			Ok(Err(error)) | Err(error) => return Err(error)
+		};
+
+		write_metadata_files(&writes)
 	}
 
-	// pub async fn get_html_metadata(&self, text_document: &TextDocumentItem) -> HtmlMetaData {
-	// 	panic!("todo");
-	// }
+	/// Create the metadata of the CSS or HTML file at `file_path`, which has just been created or changed in the workspace at `workspace_path`, both on disk and in the `MetadataCache`.
+	/// The same as [`Backend::update_file_metadata`], the files are read and the metadata is written while the cache is free.
+	/// Returns `Err(BhcError)` if the file could not be read, or its metadata could not be written.
+	pub async fn add_file_metadata(&self, workspace_path: &Path, file_path: &Path) -> Result<(), BhcError> {
+		let document_paths = match self.metadata_cache.read(workspace_path, |index| {
+			// a file that already has metadata is only updated, which doesn't link any document again
+			if index.has_file_metadata(file_path) {
+				Vec::new()
+			} else {
+				index.get_linking_documents(file_path)
+			}
+		}).await {
+			Ok(value) => value,
+			Err(error) => return Err(error)
+		};
+
+		let (contents, stamp) = match read_source(file_path) {
+			Ok(value) => value,
+			Err(error) => return Err(error)
+		};
 
+		let documents = read_source_files(&document_paths);
+
+		let writes = match self.metadata_cache.write(workspace_path, |index| index.add_file_metadata(workspace_path, file_path, &contents, stamp, &documents)).await {
+			Ok(Ok(Some(value))) => value,
+			Ok(Ok(None)) => return Ok(()),
+			Ok(Err(error)) | Err(error) => return Err(error)
+		};
+
+		write_metadata_files(&writes)
+	}
+
+	/// Delete the metadata of the CSS or HTML file at `file_path`, which has been deleted from the workspace at `workspace_path` or is now left out of it, both on disk and in the `MetadataCache`.
+	/// The same as [`Backend::update_file_metadata`], the files are read and the metadata is written while the cache is free.
+	/// Returns `Err(BhcError)` if its metadata could not be deleted, or the metadata of the documents that linked to it could not be written.
+	pub async fn remove_file_metadata(&self, workspace_path: &Path, file_path: &Path) -> Result<(), BhcError> {
+		let document_paths = match self.metadata_cache.read(workspace_path, |index| {
+			if index.has_file_metadata(file_path) {
+				index.get_linking_documents(file_path)
+			} else {
+				Vec::new()
+			}
+		}).await {
+			Ok(value) => value,
+			Err(error) => return Err(error)
+		};
+
+		let documents = read_source_files(&document_paths);
+
+		let writes = match self.metadata_cache.write(workspace_path, |index| index.remove_file_metadata(workspace_path, file_path, &documents)).await {
+			Ok(Ok(Some(value))) => value,
+			Ok(Ok(None)) => return Ok(()),
+			Ok(Err(error)) | Err(error) => return Err(error)
+		};
+
+		write_metadata_files(&writes)
+	}
 
 	/// Initializes the metadata on startup, this includes creating metadata for the first time if it didn't exist, and updating any existing metadata since the last time the workspace was opened. 
 	/// This will return nothing and logs any errors that occurs throughout the process.
 	/// It will try to create as many metadata files and skip any that throw an error
 	pub async fn initialize_workspace(&self, workspace_path: &PathBuf) {
-		let files = recursive_file_search(&workspace_path);
+		let workspaces = self.get_workspaces().await.unwrap_or_default();
 
-		if !contains_web_documents(&files) {
+		// read again every time, as this is also how a workspace is searched again once its configuration changes
+		let config = self.reload_workspace_config(workspace_path).await;
+		let filter = FileFilter::new(workspace_path, &config);
+
+		// a workspace that was initialized before still needs the metadata of files the configuration now leaves out to be removed
+		if !contains_web_documents(&get_workspace_files(&workspaces, workspace_path, &filter)) && !workspace_path.join(METADATA_PATH).is_file() {
 			return
 		}
 
+		// held until the workspace is indexed, so another editor open on the same folder doesn't change the metadata part way through
+		let _lock = match MetadataLock::acquire(workspace_path).await {
+			Ok(value) => value,
+			Err(error) => {
+				self.report_error(error).await;
+				return
+			}
+		};
+
+		// metadata saved by an older version of the server is upgraded before anything reads it, or created again if it can't be, or if two files share an id
+		let workspace_metadata_path = &workspace_path.join(METADATA_PATH);
+		let checked = match migrate_workspace(workspace_path) {
+			Ok(unreadable) => {
+				for error in unreadable {
+					self.log_error(format!("Creating the metadata again for a file whose metadata could not be read. {}", error)).await;
+				}
+
+				if workspace_metadata_path.is_file() {
+					open_workspace_metadata(workspace_metadata_path).map(|_| ())
+				} else {
+					Ok(())
+				}
+			},
+			Err(error) => Err(error)
+		};
+
+		if let Err(error) = checked {
+			self.log_error(format!("Rebuilding the metadata of {:?} from scratch. {}", workspace_path, error)).await;
+
+			if let Err(error) = rebuild_metadata(workspace_path) {
+				self.report_error(error).await;
+				return
+			}
+		}
+
+		// searched again now that nothing else can change the metadata, as another editor could have before the lock was taken
+		let files = get_workspace_files(&workspaces, workspace_path, &filter);
+
 		let css_metadata_path = &workspace_path.join(CSS_METADATA_PATH);
 		let html_metadata_path = &workspace_path.join(HTML_METADATA_PATH);
 		let _shared_path = &workspace_path.join(SHARED_PATH);
-		let workspace_metadata_path = &workspace_path.join(METADATA_PATH);
 
 		// this is just for initialising, it doesn't need to be a provider of truth
-		let grouped_files: GroupedFiles = files.into();
+		let mut grouped_files: GroupedFiles = files.into();
 
 		// this is a source of truth and will be used to save back at the end.
 		let mut workspace_metadata = if grouped_files.contains_workspace_metadata() {
 			match open_workspace_metadata(workspace_metadata_path) {
 				Ok(value) => value,
 				Err(error) => {
-					self.log_error(error).await;
+					self.report_error(error).await;
 					return
 				}
 			}
 		} else {
-			match create_workspace_metadata(workspace_metadata_path, &workspace_path) {
+			match create_workspace_metadata(workspace_metadata_path, workspace_path) {
 				Ok(value) => value,
 				Err(error) => {
-					self.log_error(error).await;
+					self.report_error(error).await;
 					return
 				}
 			}
 		};
 
-		workspace_metadata.workspace_path = workspace_path.clone().into_os_string().into_string().unwrap();
+		workspace_metadata.workspace_path = match workspace_path.to_str() {
+			Some(value) => value.to_string(),
+			None => {
+				self.report_error(BhcError::path(workspace_path)).await;
+				return
+			}
+		};
+
+		// where each document was, as a moved document links to stylesheets relative to where it is now
+		let indexed_html_paths: HashMap<u32, String> = workspace_metadata.html_files.iter().map(|html_file| (html_file.id, html_file.absolute_path.clone())).collect();
+
+		// moved files keep their ids, and the metadata of deleted files is removed, before any new metadata is created
+		match reconcile_workspace(&mut grouped_files, &mut workspace_metadata) {
+			Ok(unreadable) => {
+				for error in unreadable {
+					self.log_error(format!("Creating the metadata again for a file whose metadata could not be read. {}", error)).await;
+				}
+			},
+			Err(error) => self.report_error(error).await
+		};
+
+		// metadata files left on disk without an entry keep their ids, so a new file can't overwrite them
+		workspace_metadata.reserve_ids(&grouped_files.json_files);
 
 		// create a hashmap of css files to their json metadata files. If the file key doesn't appear in the list, it means we have to create its metadata file from scratch
 		let css_metadata_map: HashMap<PathBuf, PathBuf> = grouped_files.map_css_files();
 
+		// the stylesheets that were parsed again, or created, as the documents that link to them have to be linked again too
+		let mut changed_sheets: HashSet<u32> = HashSet::new();
+		// any document could link to a stylesheet that has just been created
+		let mut is_sheet_created = false;
+
 		for css_file in &grouped_files.css_files {
 			match css_metadata_map.get_key_value(css_file) {
 				Some((_,css_metadata_file_path)) => {
-					match fs::read_to_string(css_metadata_file_path) {
-						Ok(contents) => {
-							// it exists so we can deserialize it
-							let mut css_metadata: CssMetaData = serde_json::from_str(&contents).unwrap();
-
-							// get the metadata of the original file
-							let css_file_metadata = fs::metadata(&css_metadata.absolute_path).unwrap();
-
-							let file_last_modified: DateTime<Utc> = css_file_metadata.modified().unwrap().into();
-
-							// if the original file has been updated more recently than the proclaimed last_updated time then we need to update the contents of 
-							if file_last_modified > css_metadata.last_updated {
-								match css_metadata.update_metadata(css_metadata_file_path) {
-									Ok(_) => (),
-									Err(error) => {
-										self.log_error(error).await;
-										continue
-									}
-								};
+					match CssMetaData::from_json(css_metadata_file_path) {
+						Ok(mut css_metadata) => {
+							let result = match css_metadata.check_staleness() {
+								Ok(Staleness::Fresh) => Ok(()),
+								Ok(Staleness::Touched(stamp)) => {
+									css_metadata.source_stamp = Some(stamp);
+									css_metadata.last_updated = Utc::now();
+									css_metadata.save_metadata(css_metadata_file_path)
+								},
+								Ok(Staleness::Stale) => {
+									changed_sheets.insert(css_metadata.id);
+									css_metadata.update_metadata(css_metadata_file_path).map(|_| ())
+								},
+								Err(error) => Err(error)
+							};
+
+							if let Err(error) = result {
+								self.report_error(error).await;
+								continue
 							}
 						},
 						Err(error) => {
-							self.log_error(format!("Error trying to read file ({:?}): {:?}", &css_metadata_file_path, error)).await;
+							self.report_error(error).await;
 							continue
 						}
 					}
@@ -176,7 +405,9 @@ impl Backend {
 					// we cannot know what sheets have been imported ahead of time
 					// we can know what styles there are though
 					// get the next available id
-					let id = workspace_metadata.get_next_available_css_id();
+					let id = workspace_metadata.allocate_css_id();
+					changed_sheets.insert(id);
+					is_sheet_created = true;
 
 					let file_name = id_to_json_file_name(&id);
 
@@ -187,7 +418,7 @@ impl Backend {
 					let css_metadata = match CssMetaData::create_metadata(&save_path, css_file, &id) {
 						Ok(value) => value,
 						Err(error) => {
-							self.log_error(error).await;
+							self.report_error(error).await;
 							continue
 						}
 					};
@@ -202,43 +433,76 @@ impl Backend {
 			}
 		}
 
+		// now that every CSS file has an id, the sheets they import can be given theirs
+		for css_file in &workspace_metadata.css_files {
+			let css_metadata_file_path = css_metadata_path.join(id_to_json_file_name(&css_file.id));
+
+			let mut css_metadata = match CssMetaData::from_json(&css_metadata_file_path) {
+				Ok(value) => value,
+				Err(error) => {
+					self.report_error(error).await;
+					continue
+				}
+			};
+
+			if css_metadata.update_import_ids(&workspace_metadata) {
+				match css_metadata.save_metadata(&css_metadata_file_path) {
+					Ok(_) => (),
+					Err(error) => {
+						self.report_error(error).await;
+						continue
+					}
+				};
+			}
+		}
+
 		let html_metadata_map: HashMap<PathBuf, PathBuf> = grouped_files.map_html_files();
 
-		//TODO: Will have to find a way to re-pair up orphaned files that have been moved externally.
+		// the documents that have to be read and linked again, the rest keep the stylesheets they already link to
+		let mut unlinked_ids: HashSet<u32> = HashSet::new();
 
 		for html_file in &grouped_files.html_files {
 			match html_metadata_map.get_key_value(html_file) {
 				Some((_,html_metadata_file_path)) => {
-					match fs::read_to_string(html_metadata_file_path) {
-						Ok(contents) => {
-							// it exists so we can deserialize it
-							let mut html_metadata: HtmlMetaData = serde_json::from_str(&contents).unwrap();
-
-							// get the metadata of the original file
-							let html_file_metadata = fs::metadata(&html_metadata.absolute_path).unwrap();
-
-							let file_last_modified: DateTime<Utc> = html_file_metadata.modified().unwrap().into();
-
-							// if the original file has been updated more recently than the proclaimed last_updated time then we need to update the contents of 
-							if file_last_modified > html_metadata.last_updated {
-								match html_metadata.update_metadata(html_metadata_file_path, &workspace_metadata) {
-									Ok(_) => (),
-									Err(error) => {
-										self.log_error(error).await;
-										continue
-									}
-								};
+					match HtmlMetaData::from_json(html_metadata_file_path) {
+						Ok(mut html_metadata) => {
+							let is_moved = indexed_html_paths.get(&html_metadata.id) != Some(&html_metadata.absolute_path);
+							let is_unlinked = is_moved || is_sheet_created || html_metadata.has_changed_links(&workspace_metadata, &changed_sheets);
+
+							let result = match html_metadata.check_staleness() {
+								Ok(Staleness::Fresh | Staleness::Touched(_)) if is_unlinked => {
+									unlinked_ids.insert(html_metadata.id);
+									Ok(())
+								},
+								Ok(Staleness::Fresh) => Ok(()),
+								// only the stamp is refreshed, so the document doesn't have to be read again next time
+								Ok(Staleness::Touched(stamp)) => {
+									html_metadata.source_stamp = Some(stamp);
+									html_metadata.last_updated = Utc::now();
+									write_metadata(html_metadata_file_path, &html_metadata)
+								},
+								// read and linked once every stylesheet has an id, below
+								Ok(Staleness::Stale) => {
+									unlinked_ids.insert(html_metadata.id);
+									Ok(())
+								},
+								Err(error) => Err(error)
+							};
+
+							if let Err(error) = result {
+								self.report_error(error).await;
+								continue
 							}
 						},
 						Err(error) => {
-							self.log_error(format!("Error trying to read file ({:?}): {:?}", &html_metadata_file_path, error)).await;
+							self.report_error(error).await;
 							continue
 						}
 					}
 
 				}, 
 				None => {
-					let id = workspace_metadata.get_next_available_html_id();
+					let id = workspace_metadata.allocate_html_id();
 
 					let file_name = id_to_json_file_name(&id);
 
@@ -249,14 +513,15 @@ impl Backend {
 					let html_metadata = match HtmlMetaData::create_metadata(&save_path, html_file, &id) {
 						Ok(value) => value,
 						Err(error) => {
-							self.log_error(error).await;
+							self.report_error(error).await;
 							continue
 						}
 					};
 
 					let html_file_metadata = WorkspaceHtmlFile::parse(&html_metadata);
 
-					workspace_metadata.add_html_file(html_file_metadata)
+					workspace_metadata.add_html_file(html_file_metadata);
+					unlinked_ids.insert(id);
 				} 
 
 			}
@@ -267,11 +532,15 @@ impl Backend {
 		let mut html_workspace_metadata_map: HashMap<usize, WorkspaceHtmlFile> = HashMap::new();
 		
 		for (index, html_file) in workspace_metadata.html_files.iter().enumerate() {
+			if !unlinked_ids.contains(&html_file.id) {
+				continue
+			}
+
 			// This transforms a basic HTML file into one that contains any included stylesheets inside it
 			let new_metadata = match html_file.update(&workspace_metadata){
 				Ok(value) => value,
 				Err(error) => {
-					self.log_error(error).await;
+					self.report_error(error).await;
 					continue
 				}
 			};
@@ -283,100 +552,69 @@ impl Backend {
 			match workspace_metadata.modify_html_file(&metdata_file, &index) {
 				Ok(_) => (),
 				Err(error) => {
-					self.log_error(error).await;
+					self.report_error(error).await;
 					continue
 				}
 			}
 		}
 
-		// TODO: do the references to eachother part
+		// Then every CSS file gets the HTML files that reference it
+		workspace_metadata.update_css_references();
 
-		// TODO: Remove any files that no longer exist 
-		// they will have the same filename, but different id's
 		match workspace_metadata.update_metadata(workspace_metadata_path) {
 			Ok(_) => (),
 			Err(error) => {
-				self.log_error(error).await;
+				self.report_error(error).await;
 				return 
 			}
 		};
 
-	}
-
-	/// For a given `text_document``, we return the correct workspace pathbuf.
-	/// Returns `Ok(PathBuf)` on success.
-	/// Returns `Err(String)` if it was unable to find the workspace path the `text_document` belongs to.
-	/// 
-	async fn get_workspace(&self, text_document: &TextDocumentItem) -> Result<PathBuf, String> {
-		let workspace_paths = match self.get_workspaces().await {
-			Ok(value) => value,
-			Err(error) => return Err(error)
+		// the metadata is read once here, every request after this uses the cache
+		match WorkspaceIndex::load(workspace_path) {
+			Ok(value) => self.metadata_cache.insert(workspace_path, value).await,
+			Err(error) => self.report_error(error).await
 		};
-
-		let file_path = text_document.uri.to_file_path().unwrap();
-
-		for workspace in workspace_paths {
-			if file_path.starts_with(&workspace) {
-				return Ok(workspace);
-			}
-		}
-
-		return Err(format!("Could not find any workspace for the given path: {:?}", file_path))
 	}
 }
 
-/// For the given `workspace_path`, return the WorkspaceMetaData. This can be found at `{workspace_path}/.bhc/.meta/meta.json`.
-/// Returns `Ok(WorkspaceMetaData)` if it was able to find the file and deserialize it
-/// Returns `Err(String)` if the file doesn't exist or was unable to deserialize it.
-fn get_workspace_metadata(workspace_path: &PathBuf) -> Result<WorkspaceMetaData, String> {
-	let mut final_path: PathBuf = workspace_path.clone();
-
-	final_path.push(METADATA_PATH);
+/// Where a file is served from, which decides whether it has metadata
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileScope {
+	Workspace(PathBuf), // the deepest open workspace folder that contains the file
+	Loose, // a file opened from outside of every workspace, only its own contents and links are used
+}
 
-	let metadata_string: String = match fs::read_to_string(&final_path) {
-		Ok(value) => value,
-		Err(error) => {
-			return Err(format!("Error trying to read workspace metadata file ({:?}): {:?}", &final_path, error))
-		}
-	};
+/// Find the workspace in `workspaces` that `file_path` belongs to. Workspace folders can be nested inside of each other, in which case the deepest one that contains the file owns it.
+/// Returns `None` if the file isn't inside of any of them.
+pub fn find_workspace_folder(workspaces: &[PathBuf], file_path: &Path) -> Option<PathBuf> {
+	workspaces
+	.iter()
+	.filter(|workspace| file_path.starts_with(workspace))
+	.max_by_key(|workspace| workspace.components().count())
+	.cloned()
+}
 
-	match serde_json::from_str(&metadata_string) {
-		Ok(value) => return Ok(value),
-		Err(error) => {
-			return Err(format!("Error deseralizing workspace metadata file ({:?}): {:?}", &final_path, error))
-		}
-	};
+/// Get every file in the workspace at `workspace_path` that the `filter` includes. Files inside of a nested workspace folder belong to that workspace instead.
+fn get_workspace_files(workspaces: &[PathBuf], workspace_path: &PathBuf, filter: &FileFilter) -> Vec<PathBuf> {
+	recursive_file_search(workspace_path, filter)
+	.into_iter()
+	.filter(|file_path| find_workspace_folder(workspaces, file_path).is_none_or(|workspace| &workspace == workspace_path))
+	.collect()
 }
 
-/// For the given `workspace_path`, and the provided `file_path`, get the CssMetaData for it. The `file_path` should be the actual file absolute path.
-/// Returns `Ok(CssMetaData)` if it was able to find the file and deserialize it
-/// Returns `Err(String)` if the file doesn't exist or was unable to deserialize it.
-fn get_css_metadata(workspace_path: &PathBuf, file_path: &PathBuf) -> Result<CssMetaData, String> {
-	let workspace_metadata = match get_workspace_metadata(&workspace_path) {
-		Ok(value) => value,
-		Err(error) => return Err(error)
-	};
-
-	for css_file in workspace_metadata.css_files {
-		if &PathBuf::from(&css_file.absolute_path) == file_path {
-			let metadata_string: String = match fs::read_to_string(file_path) {
-				Ok(value) => value,
-				Err(error) => {
-					return Err(format!("Error trying to read CSS metadata file ({:?}): {:?}", file_path, error))
-				}
-			};
-		
-			match serde_json::from_str(&metadata_string) {
-				Ok(value) => return Ok(value),
-				Err(error) => {
-					return Err(format!("Error deseralizing CSS metadata file ({:?}): {:?}", file_path, error))
-				}
-			};
-		}
-	}
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
 
-	Err(format!("Could not find a CSS metadata file at {:?}", file_path))
-}
+	use super::find_workspace_folder;
 
+	#[test]
+	fn find_workspace_folder_test() {
+		let workspaces = vec![PathBuf::from("/projects/site"), PathBuf::from("/projects/site/packages/docs"), PathBuf::from("/projects/site-admin")];
 
-// fn get_html_metadata(workspace_path: &PathBuf, file_path: &PathBuf) -> HtmlMetadata {}
+		assert_eq!(find_workspace_folder(&workspaces, &PathBuf::from("/projects/site/index.html")), Some(PathBuf::from("/projects/site")));
+		assert_eq!(find_workspace_folder(&workspaces, &PathBuf::from("/projects/site/packages/docs/css/base.css")), Some(PathBuf::from("/projects/site/packages/docs")));
+		assert_eq!(find_workspace_folder(&workspaces, &PathBuf::from("/projects/site-admin/index.html")), Some(PathBuf::from("/projects/site-admin")));
+		assert_eq!(find_workspace_folder(&workspaces, &PathBuf::from("/downloads/page.html")), None);
+	}
+}